@@ -0,0 +1,73 @@
+//! Benchmarks for field arithmetic and Shamir reconstruction.
+//!
+//! Run with `cargo bench`.
+
+#[macro_use]
+extern crate criterion;
+extern crate privcount;
+extern crate rand;
+
+use criterion::{black_box, BenchmarkId, Criterion};
+use privcount::{shamir, FE};
+use rand::{ChaChaRng, Rng, SeedableRng};
+use std::iter::FromIterator;
+
+/// Return a deterministic RNG, so that every run uses the same inputs.
+fn bench_rng() -> ChaChaRng {
+    ChaChaRng::from_seed(&[0x6265_6e63, 0x6866_6965, 0x6c64][..])
+}
+
+/// Return `n` random nonzero field elements.
+fn random_fes(rng: &mut ChaChaRng, n: usize) -> Vec<FE> {
+    Vec::from_iter((0..n).map(|_| loop {
+        let v: FE = rng.gen();
+        if v != FE::new(0) {
+            break v;
+        }
+    }))
+}
+
+fn bench_mul(c: &mut Criterion) {
+    let mut rng = bench_rng();
+    let xs = random_fes(&mut rng, 1024);
+    let ys = random_fes(&mut rng, 1024);
+    c.bench_function("FE::mul", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % xs.len();
+            black_box(xs[i]) * black_box(ys[i])
+        })
+    });
+}
+
+fn bench_recip(c: &mut Criterion) {
+    let mut rng = bench_rng();
+    let xs = random_fes(&mut rng, 1024);
+    c.bench_function("FE::recip", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % xs.len();
+            black_box(xs[i]).recip()
+        })
+    });
+}
+
+fn bench_recover(c: &mut Criterion) {
+    let mut rng = bench_rng();
+    let mut group = c.benchmark_group("shamir::recover_secret");
+    for &k in [3u32, 5, 10].iter() {
+        let mut builder = shamir::ParamBuilder::new(k, k).unwrap();
+        builder.fill_x_coordinates(&mut rng);
+        let params = builder.finalize().unwrap();
+        let secret: FE = rng.gen();
+        let shares = params.share_secret(secret, &mut rng);
+        assert_eq!(shamir::recover_secret(&shares), secret);
+        group.bench_with_input(BenchmarkId::from_parameter(k), &shares, |b, shares| {
+            b.iter(|| shamir::recover_secret(black_box(&shares[..])))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_mul, bench_recip, bench_recover);
+criterion_main!(benches);
@@ -8,19 +8,55 @@
 //! protocol, see the privcount specification.
 
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 use std::iter::FromIterator;
 use std::u32;
 
 use byteorder::{ByteOrder, NetworkEndian};
-use math::FE;
+use crypto::digest::Digest;
+use crypto::sha3::Sha3;
+use math::{max_secret, FE};
 use num::Zero;
+use rand::os::OsRng;
 use rand::Rng;
 
 use data::*;
 use encrypt::hybrid::PrivcountEncryptor;
 use encrypt::Encryptor;
+use noise::NoiseSource;
 use shamir;
 
+/// A marker trait for random number generators that are secure enough to
+/// use for blinding and sharing counters.
+///
+/// Everything the client does is only as secure as its RNG, so the
+/// constructors for CounterSet require this trait, to make it harder to
+/// use a weak or deterministic RNG by accident.  Only implement it for
+/// RNGs that draw from a cryptographically secure source.
+///
+/// # Examples
+///
+/// A seeded RNG isn't a SecureRng, so this won't compile:
+///
+/// ```compile_fail
+/// # extern crate privcount;
+/// # extern crate rand;
+/// # use privcount::client::CounterSet;
+/// # use privcount::data::*;
+/// # use privcount::noise::NoNoise;
+/// use rand::{ChaChaRng, SeedableRng};
+/// # fn main() {
+/// # let tr_keys = vec![TrKeys { enc_key: [9; 32], signing_key: [1; 32] }];
+/// let mut rng = ChaChaRng::from_seed(&[1, 2, 3][..]);
+/// let ctrs = CounterSet::new(&mut rng, &[CtrId(1)], &tr_keys, 1, NoNoise);
+/// # }
+/// ```
+pub trait SecureRng: Rng {}
+
+impl SecureRng for OsRng {}
+
+impl<R: SecureRng + ?Sized> SecureRng for &mut R {}
+
 /// Create a new random seed for a TR, and encrypt it to the TR.
 ///
 /// On success, returns the Seed object, and the encrypted message.
@@ -49,44 +85,120 @@ pub struct TrState {
     /// Note that these counters don't leak any information on their own: They are useless
     /// without being able to decrypt the encrypted seed.
     counters: Vec<FE>,
+    /// The masks for any counters that we add later on.
+    ///
+    /// The i'th counter always gets the i'th mask from the seed, so that
+    /// the TR can regenerate it.
+    masks: MaskIter,
 }
 
 impl TrState {
-    /// Create a new TrState for a TR with a given set of keys and a
-    /// given number of counters.
-    fn new<R: Rng>(rng: &mut R, keys: &TrKeys, n_counters: u32) -> Result<Self, &'static str> {
+    /// Create a new TrState for a TR with a given set of keys, a given X
+    /// coordinate, and a given number of counters.
+    fn new<R: Rng>(
+        rng: &mut R,
+        keys: &TrKeys,
+        x: FE,
+        n_counters: u32,
+    ) -> Result<Self, &'static str> {
+        if n_counters > MAX_COUNTERS {
+            return Err("Too many counters to generate masks for.");
+        }
         let (seed, encrypted_seed) = new_seed(rng, keys)?;
-        let counters = seed.counter_masks(n_counters)?;
+        let mut masks = seed.mask_iter();
+        let counters = Vec::from_iter(masks.by_ref().take(n_counters as usize));
         Ok(TrState {
             keys: keys.clone(),
             encrypted_seed: encrypted_seed,
-            x: keys.get_x_coord(),
+            x,
             counters,
+            masks,
         })
     }
 
-    /// Convert a TRState to a TRData, ready to be sent to a TR.
-    fn finalize<R: Rng>(self, rng: &mut R) -> Result<TrData, &'static str> {
+    /// Add a new counter to this TrState, initialized to the next mask from
+    /// the seed.
+    fn push_mask(&mut self) {
+        let mask = self.masks.next().expect("MaskIter should never run out");
+        self.counters.push(mask);
+    }
+
+    /// Convert a TRState to a TRData, ready to be sent to a TR, and sign it
+    /// with `client_keys`.
+    fn finalize<R: Rng>(
+        self,
+        rng: &mut R,
+        counter_ids: &[CtrId],
+        client_keys: &ClientKeyPair,
+    ) -> Result<TrData, &'static str> {
         let enc = PrivcountEncryptor::new(&self.keys.enc_key, &self.keys.signing_key);
-        let u64s = Vec::from_iter(self.counters.into_iter().map(|fe| fe.value()));
-        let mut encoded = Vec::with_capacity(u64s.len() * 8);
-        encoded.resize(u64s.len() * 8, 0);
-        NetworkEndian::write_u64_into(&u64s, &mut encoded[..]);
+        // CounterSet::new() made sure that this fits in a u32.
+        let n_counters = self.counters.len() as u32;
+        let mut encoded = Vec::with_capacity(self.counters.len() * 8);
+        for fe in self.counters.iter() {
+            encoded.extend_from_slice(&fe.to_bytes());
+        }
         let encrypted = enc.encrypt(&encoded, Y_ENCRYPTION_TWEAK, rng)?;
 
-        Ok(TrData::new(
+        let mut result = TrData::new(
             &self.keys,
             self.encrypted_seed,
             self.x,
+            n_counters,
             encrypted,
-        ))
+        );
+        result.sign(counter_ids, client_keys);
+        Ok(result)
+    }
+}
+
+/// Helper: Return the noise to add to the counter with ID `cid`.
+///
+/// The noise comes from `noise`, if there is one.  The client count never
+/// gets any noise.
+fn sample_noise<R: Rng>(rng: &mut R, noise: &Option<Box<dyn NoiseSource>>, cid: CtrId) -> FE {
+    match *noise {
+        Some(ref source) if cid != CLIENT_COUNT_ID => source.sample(rng),
+        _ => FE::zero(),
+    }
+}
+
+/// Helper: Create a new Counter with ID `cid`, at index `idx`, and blind
+/// its value into the TRs' counters at that index.
+///
+/// `shares` must hold one share of the counter's noise for each TR.  On
+/// input, each TR's counter at `idx` must hold that TR's mask.  On
+/// output, it holds the TR's share of the noise, less the mask and the
+/// counter's blinding value.
+fn blind_counter<R: Rng>(
+    rng: &mut R,
+    params: &shamir::Params<FE>,
+    shares: Vec<shamir::Share<FE>>,
+    tr_states: &mut [TrState],
+    idx: usize,
+    cid: CtrId,
+) -> Result<Counter, &'static str> {
+    if shares.len() != tr_states.len() {
+        return Err("Internal error: incorrect number of shares generated.");
+    }
+    let mut counter = Counter::new(cid);
+    counter.val = rng.gen();
+    let masks = Vec::from_iter(tr_states.iter().map(|s| s.counters[idx]));
+    let shares = params.blind_shares(shares, &masks, &counter.val);
+
+    for (share, tr_state) in shares.iter().zip(tr_states.iter_mut()) {
+        if share.x != tr_state.x {
+            return Err("Internal error: mismatched share generated.");
+        }
+        tr_state.counters[idx] = share.y;
     }
+    Ok(counter)
 }
 
 /// A CounterSet is a client's view of all of its counters
 pub struct CounterSet {
     /// A list of all of the counter IDs that the client is tracking
-    counter_ids: Vec<CtrId>, // XXXX use strings??
+    counter_ids: Vec<CtrId>,
     /// A map from couter ID to actual counter values.
     counters: HashMap<CtrId, Counter>,
     /// A set of TR states for all of the TRs that the client knows about.
@@ -94,6 +206,10 @@ pub struct CounterSet {
     /// Invariant: These objects must have the same number of counters
     /// as are in this CounterSet.
     tr_states: Vec<TrState>,
+    /// The parameters we use to share each counter's noise among the TRs.
+    shamir_params: shamir::Params<FE>,
+    /// The source of each new counter's noise, if we're adding any.
+    noise: Option<Box<dyn NoiseSource>>,
 }
 
 /// Information to track a client's view of a single counter.
@@ -103,12 +219,50 @@ pub struct CounterSet {
 /// encrypted seed data.
 ///
 /// Note that these values wrap at PRIME_ORDER, so you should make
-/// sure that no counter's total is too close to that value.
+/// sure that no counter's total, summed over all clients, can exceed
+/// `privcount::max_secret()`.  To help with that, each counter also
+/// keeps an unblinded count of how much has been added to it, and
+/// `checked_inc()` refuses to let that count go over a ceiling.
+///
+/// (That unblinded count is a real number of events, so anybody who can
+/// read the client's memory can learn it.  If that matters to you, stick
+/// to `inc()`, and don't rely on it.)
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Counter {
     id: CtrId,
     val: FE,
+    /// An upper bound on the total of the increments to this counter.
+    total: u64,
+    /// The largest value that `checked_inc()` will let `total` reach.
+    ceiling: u64,
+}
+
+impl fmt::Debug for Counter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Don't leak the unblinded total (or the value that would
+        // unblind it) into logs.
+        f.debug_struct("Counter")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An error from `Counter::checked_inc()`: the increment would have
+/// taken the counter over its ceiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowError;
+
+impl Display for OverflowError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str("Counter would exceed its ceiling.")
+    }
+}
+
+impl From<OverflowError> for &'static str {
+    fn from(_: OverflowError) -> &'static str {
+        "Counter would exceed its ceiling."
+    }
 }
 
 impl Counter {
@@ -117,70 +271,217 @@ impl Counter {
         Counter {
             id,
             val: FE::zero(),
+            total: 0,
+            ceiling: max_secret(),
         }
     }
+    /// Return the ID of this counter.
+    pub fn id(&self) -> CtrId {
+        self.id
+    }
     /// Add a value to this counter.
     pub fn inc(&mut self, v: u32) {
-        self.val += FE::from(v);
+        self.inc_u64(u64::from(v));
     }
     /// Subtract a value from this counter.
     pub fn dec(&mut self, v: u32) {
-        self.val -= FE::from(v);
+        self.dec_u64(u64::from(v));
+    }
+    /// Add a 64-bit value to this counter.
+    ///
+    /// Values of `PRIME_ORDER` or more wrap around, just as the total
+    /// does.
+    pub fn inc_u64(&mut self, v: u64) {
+        self.val += FE::new(v);
+        self.total = self.total.saturating_add(v);
+    }
+    /// Subtract a 64-bit value from this counter.
+    pub fn dec_u64(&mut self, v: u64) {
+        self.val -= FE::new(v);
+        self.total = self.total.saturating_sub(v);
+    }
+    /// Add `count * weight` to this counter.
+    ///
+    /// This is the same as calling `inc(weight)` `count` times, but it
+    /// takes only a single field multiplication.  As with `inc()`, make
+    /// sure that the total stays below `privcount::max_secret()`.
+    pub fn inc_scaled(&mut self, count: u32, weight: u32) {
+        self.val += FE::from(count) * FE::from(weight);
+        let product = u64::from(count) * u64::from(weight);
+        self.total = self.total.saturating_add(product);
+    }
+    /// Add a value to this counter, unless that would take its total over
+    /// its ceiling.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error, and leaves the counter unchanged, if the total of
+    /// the increments to this counter would exceed the ceiling.
+    pub fn checked_inc(&mut self, v: u32) -> Result<(), OverflowError> {
+        match self.total.checked_add(u64::from(v)) {
+            Some(t) if t <= self.ceiling => {
+                self.inc(v);
+                Ok(())
+            }
+            _ => Err(OverflowError),
+        }
+    }
+    /// Set the largest total that `checked_inc()` will allow.
+    ///
+    /// By default, this is `privcount::max_secret()`.  But that's a
+    /// bound on the total over *all* the clients, so if you know how
+    /// many clients there are, you should divide it among them.
+    pub fn set_ceiling(&mut self, ceiling: u64) {
+        self.ceiling = ceiling;
     }
 }
 
+/// Helper: derive each TR's X coordinate from its keys, and make sure
+/// that no two of them are the same.
+fn x_coords_from_keys(tr_ids: &[TrKeys]) -> Result<Vec<FE>, &'static str> {
+    let x_coords = Vec::from_iter(tr_ids.iter().map(|keys| keys.get_x_coord()));
+    for (i, x) in x_coords.iter().enumerate() {
+        if let Some(j) = x_coords[..i].iter().position(|x2| x2 == x) {
+            if tr_ids[i].signing_key == tr_ids[j].signing_key {
+                return Err("Two TRs have the same signing key.");
+            }
+            return Err("Two TRs' signing keys give the same X coordinate.");
+        }
+    }
+    Ok(x_coords)
+}
+
 impl CounterSet {
     /// Create a new CounterSet to track values for a given number of
     /// counters, enrypted to a given set of TR keys.  Any set of `k`
     /// TRs will be able to find the actual counter values.
-    pub fn new<R: Rng>(
+    ///
+    /// Besides the counters in `counter_ids`, the CounterSet tracks one
+    /// more, with ID `CLIENT_COUNT_ID`, whose value is always 1.
+    ///
+    /// Each TR's X coordinate is derived from its keys with
+    /// `TrKeys::get_x_coord()`.  If two TRs have the same signing key,
+    /// or (with negligible probability) two different signing keys give
+    /// the same X coordinate, this gives an error.
+    ///
+    /// The security of every counter depends on `rng`, so it must be a
+    /// `SecureRng`.  For tests that need a reproducible RNG, see
+    /// `new_insecure_for_testing()`.
+    ///
+    /// Every counter except the client count gets noise from `noise`,
+    /// including counters added later with `add_counter()`.  The noise
+    /// should be calibrated so that its sum over all the clients has the
+    /// distribution you want: see the `noise` module.  The totals will
+    /// then be off by that sum, which may be negative: use
+    /// `FE::signed_value()` to read them.  (With `NoNoise`, the totals
+    /// are exact, but the users get no differential privacy.)
+    pub fn new<R: SecureRng, S: NoiseSource + 'static>(
+        rng: &mut R,
+        counter_ids: &[CtrId],
+        tr_ids: &[TrKeys],
+        k: u32,
+        noise: S,
+    ) -> Result<Self, &'static str> {
+        let x_coords = x_coords_from_keys(tr_ids)?;
+        CounterSet::new_impl(
+            rng,
+            counter_ids,
+            tr_ids,
+            &x_coords,
+            k,
+            Some(Box::new(noise)),
+        )
+    }
+
+    /// As `new()`, but accept any RNG at all, and add no noise.
+    ///
+    /// **Don't use this outside of tests.**  With a predictable RNG, the
+    /// counters' blinding values and shares are predictable too, and
+    /// they protect nothing.
+    pub fn new_insecure_for_testing<R: Rng>(
+        rng: &mut R,
+        counter_ids: &[CtrId],
+        tr_ids: &[TrKeys],
+        k: u32,
+    ) -> Result<Self, &'static str> {
+        let x_coords = x_coords_from_keys(tr_ids)?;
+        CounterSet::new_impl(rng, counter_ids, tr_ids, &x_coords, k, None)
+    }
+
+    /// As `new()`, but use the X coordinates in `x_coords` for the TRs in
+    /// `tr_ids`, instead of deriving them from the TRs' keys.
+    ///
+    /// There must be exactly one X coordinate per TR; the X coordinates
+    /// must be nonzero and distinct.  The TRs will need to know their
+    /// X coordinates too: see `ServerKeys::decode_from_with_x()`.
+    pub fn new_with_x_coords<R: SecureRng, S: NoiseSource + 'static>(
         rng: &mut R,
         counter_ids: &[CtrId],
         tr_ids: &[TrKeys],
+        x_coords: &[FE],
         k: u32,
+        noise: S,
     ) -> Result<Self, &'static str> {
-        if counter_ids.len() > u32::MAX as usize {
+        CounterSet::new_impl(rng, counter_ids, tr_ids, x_coords, k, Some(Box::new(noise)))
+    }
+
+    /// Helper: implement `new()` and its variants.
+    fn new_impl<R: Rng>(
+        rng: &mut R,
+        counter_ids: &[CtrId],
+        tr_ids: &[TrKeys],
+        x_coords: &[FE],
+        k: u32,
+        noise: Option<Box<dyn NoiseSource>>,
+    ) -> Result<Self, &'static str> {
+        if counter_ids.len() >= u32::MAX as usize {
             return Err("Too many counters.");
         }
+        if counter_ids.contains(&CLIENT_COUNT_ID) {
+            return Err("CLIENT_COUNT_ID is reserved.");
+        }
         if tr_ids.len() > u32::MAX as usize {
             return Err("Too many tr_ids.");
         }
+        if x_coords.len() != tr_ids.len() {
+            return Err("Wrong number of X coordinates.");
+        }
 
-        let counter_ids = counter_ids.to_vec();
+        // The params check that the X coordinates are nonzero and distinct.
+        let shamir_params = {
+            let mut b = shamir::ParamBuilder::new(k, tr_ids.len() as u32)?;
+            b.add_x_coordinates(x_coords);
+            b.finalize()?
+        };
+
+        let mut counter_ids = counter_ids.to_vec();
+        counter_ids.push(CLIENT_COUNT_ID);
         let n_counters = counter_ids.len() as u32;
-        let n_trs = tr_ids.len() as u32;
         let mut tr_states = {
-            let mut tr_states_result: Result<Vec<_>, _> = tr_ids
+            let tr_states_result: Result<Vec<_>, _> = tr_ids
                 .iter()
-                .map(|k| TrState::new(rng, k, n_counters))
+                .zip(shamir_params.x_coordinates().iter())
+                .map(|(k, x)| TrState::new(rng, k, *x, n_counters))
                 .collect();
             tr_states_result?
         };
 
-        let shamir_params = {
-            let mut b = shamir::ParamBuilder::new(k, n_trs)?;
-            for state in tr_states.iter() {
-                b.add_x_coordinate(&state.x);
-            }
-            b.finalize()?
-        };
+        // Share all the noise first, and only then pick each counter's
+        // blinding value: that's the order in which clients have always
+        // drawn from the RNG, so seeded runs stay reproducible.
+        let noise_vals = Vec::from_iter(
+            counter_ids
+                .iter()
+                .map(|cid| sample_noise(rng, &noise, *cid)),
+        );
+        let all_shares = shamir_params.share_many(&noise_vals, rng);
 
         let mut counters = HashMap::new();
-        for (idx, cid) in counter_ids.iter().enumerate() {
-            let mut counter = Counter::new(*cid);
-            let noise = FE::new(0); // XXXXX no noise!
-            let shares = shamir_params.share_secret(noise, rng);
-            if shares.len() != tr_ids.len() {
-                return Err("Internal error: incorrect number of shares generated.");
-            }
-            counter.val = rng.gen();
-
-            for (share, tr_state) in shares.iter().zip(tr_states.iter_mut()) {
-                if share.x != tr_state.x {
-                    return Err("Internal error: mismatched share generated.");
-                }
-                let mask = tr_state.counters[idx];
-                tr_state.counters[idx] = share.y - mask - counter.val;
+        for ((idx, cid), shares) in counter_ids.iter().enumerate().zip(all_shares) {
+            let mut counter =
+                blind_counter(rng, &shamir_params, shares, &mut tr_states, idx, *cid)?;
+            if *cid == CLIENT_COUNT_ID {
+                counter.inc(1);
             }
             counters.insert(*cid, counter);
         }
@@ -189,17 +490,133 @@ impl CounterSet {
             counter_ids,
             counters,
             tr_states,
+            shamir_params,
+            noise,
         })
     }
 
+    /// Start tracking a new counter, with ID `id` and value zero.
+    ///
+    /// The new counter goes after all the existing ones: if there were
+    /// previously `n` counters, it gets index `n`, and its mask for each
+    /// TR is the `n`th mask from that TR's seed.  Because the TRs learn
+    /// the order of the counters from `CounterData::counter_ids`, they
+    /// will regenerate the same masks without any other changes.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if we're already tracking a counter with this ID, or
+    /// if we already have MAX_COUNTERS counters.
+    pub fn add_counter<R: SecureRng>(
+        &mut self,
+        rng: &mut R,
+        id: CtrId,
+    ) -> Result<(), &'static str> {
+        if self.counters.contains_key(&id) {
+            return Err("Duplicate counter ID.");
+        }
+        if self.counter_ids.len() >= MAX_COUNTERS as usize {
+            return Err("Too many counters.");
+        }
+
+        let idx = self.counter_ids.len();
+        for state in self.tr_states.iter_mut() {
+            state.push_mask();
+        }
+        let noise = sample_noise(rng, &self.noise, id);
+        let shares = self.shamir_params.share_secret(noise, rng);
+        let counter = blind_counter(
+            rng,
+            &self.shamir_params,
+            shares,
+            &mut self.tr_states,
+            idx,
+            id,
+        )?;
+        self.counter_ids.push(id);
+        self.counters.insert(id, counter);
+        Ok(())
+    }
+
+    /// Return the IDs of all the counters in this CounterSet, in order.
+    ///
+    /// This includes `CLIENT_COUNT_ID`.
+    pub fn counter_ids(&self) -> &[CtrId] {
+        &self.counter_ids
+    }
+
+    /// Return an iterator over all the counters in this CounterSet, in
+    /// the same order as `counter_ids()`.
+    ///
+    /// The counters' values are blinded, so this is mostly useful for
+    /// checking which counters exist.
+    pub fn iter(&self) -> impl Iterator<Item = (CtrId, &Counter)> {
+        self.counter_ids
+            .iter()
+            .map(move |id| (*id, &self.counters[id]))
+    }
+
     /// Return a reference to the counter with a given ID, if one exists.
+    ///
+    /// The client-count counter is maintained automatically, so this
+    /// returns None for `CLIENT_COUNT_ID`.
     pub fn ctr(&mut self, ctr_id: CtrId) -> Option<&mut Counter> {
+        if ctr_id == CLIENT_COUNT_ID {
+            return None;
+        }
         self.counters.get_mut(&ctr_id)
     }
 
+    /// Add `v` to the count for `key` in the sketch described by `sketch`.
+    ///
+    /// This increments one cell in every row of the sketch.  All of the
+    /// sketch's counter IDs must have been passed to `new()`.
+    pub fn inc_sketch(
+        &mut self,
+        sketch: &CountMinSketch,
+        key: &[u8],
+        v: u32,
+    ) -> Result<(), &'static str> {
+        for id in sketch.cells_for_key(key) {
+            self.ctr(id)
+                .ok_or("Sketch counter is not in this CounterSet.")?
+                .inc(v);
+        }
+        Ok(())
+    }
+
     /// Finalize this CounterSet, and return a CounterData to be distributed in pieces
     /// to the TRs.
-    pub fn finalize<R: Rng>(mut self, rng: &mut R) -> Result<CounterData, &'static str> {
+    ///
+    /// This adds each counter's blinded value to every TR's share, which
+    /// must happen exactly once: doing it twice would corrupt every
+    /// counter.  That's why this method consumes the CounterSet, so that
+    /// calling it twice is a compile-time error:
+    ///
+    /// ```compile_fail
+    /// # extern crate privcount;
+    /// # extern crate rand;
+    /// # use privcount::client::CounterSet;
+    /// # use privcount::data::*;
+    /// # use privcount::noise::NoNoise;
+    /// # fn main() {
+    /// # let mut rng = rand::os::OsRng::new().unwrap();
+    /// # let tr_keys = vec![TrKeys { enc_key: [9; 32], signing_key: [1; 32] }];
+    /// # let client_keys = ClientKeyPair::generate(&mut rng);
+    /// let ctrs = CounterSet::new(&mut rng, &[CtrId(1)], &tr_keys, 1, NoNoise).unwrap();
+    /// let data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+    /// // Error: `ctrs` was moved by the first call.
+    /// let again = ctrs.finalize(&mut rng, &client_keys).unwrap();
+    /// # }
+    /// ```
+    ///
+    /// Every TrData is signed with `client_keys`, so that the TRs can
+    /// check where it came from.
+    pub fn finalize<R: Rng>(
+        mut self,
+        rng: &mut R,
+        client_keys: &ClientKeyPair,
+    ) -> Result<CounterData, &'static str> {
         let counter_ids = self.counter_ids;
 
         for (idx, cid) in counter_ids.iter().enumerate() {
@@ -215,9 +632,289 @@ impl CounterSet {
         let tr_data: Result<Vec<_>, _> = self
             .tr_states
             .into_iter()
-            .map(|state| state.finalize(rng))
+            .map(|state| state.finalize(rng, &counter_ids, client_keys))
             .collect();
 
         Ok(CounterData::new(counter_ids, tr_data?))
     }
 }
+
+/// The layout of a count-min sketch, stored in a block of ordinary
+/// counters.
+///
+/// Some statistics are over too many distinct keys to give each key its
+/// own CtrId.  Instead, a count-min sketch keeps `depth` rows of `width`
+/// cells each.  To count a key, we hash it to one cell in each row, and
+/// increment all of those cells.  To estimate a key's total, we take the
+/// smallest of its cells.
+///
+/// Each cell is just a regular counter, with its own CtrId, so the
+/// clients and TRs blind, share, and sum them exactly as they would any
+/// other counter.  Only after the tally is reconstructed does anybody
+/// treat them as a sketch, with `query()`.
+///
+/// # Accuracy and privacy
+///
+/// Collisions can only make a cell larger, so an estimate is never less
+/// than the true total.  With `N` as the sum of all the increments,
+/// `width` of at least `e/epsilon`, and `depth` of at least
+/// `ln(1/delta)`, an estimate exceeds the true total by more than
+/// `epsilon * N` with probability at most `delta`.
+///
+/// Wider sketches are more accurate, but every cell is revealed in the
+/// final tally, so they also reveal more.  In particular, a key with a
+/// rare, large count will stand out in a wide sketch.  Anybody can
+/// compute which cells a key maps to, so the tally reveals an estimate
+/// for every key, whether anybody asks about it or not.
+#[derive(Debug, Clone)]
+pub struct CountMinSketch {
+    /// The CtrId of the first cell; the others follow consecutively.
+    first_id: u32,
+    /// The number of rows.
+    depth: u32,
+    /// The number of cells in each row.
+    width: u32,
+}
+
+impl CountMinSketch {
+    /// Describe a new count-min sketch with `depth` rows of `width` cells,
+    /// using the `depth * width` counter IDs starting at `first_id`.
+    pub fn new(first_id: CtrId, depth: u32, width: u32) -> Result<Self, &'static str> {
+        if depth == 0 || width == 0 {
+            return Err("Sketch dimensions must be nonzero.");
+        }
+        let n_cells = depth.checked_mul(width).ok_or("Sketch is too large.")?;
+        if n_cells > MAX_COUNTERS || first_id.0.checked_add(n_cells).is_none() {
+            return Err("Sketch is too large.");
+        }
+        Ok(CountMinSketch {
+            first_id: first_id.0,
+            depth,
+            width,
+        })
+    }
+
+    /// Return the counter IDs for every cell in this sketch, in order.
+    ///
+    /// Pass these to `CounterSet::new()`, along with any other counters.
+    pub fn counter_ids(&self) -> Vec<CtrId> {
+        Vec::from_iter((0..self.depth * self.width).map(|i| CtrId(self.first_id + i)))
+    }
+
+    /// Return the counter IDs of the cells that `key` maps to: one in each
+    /// row.
+    ///
+    /// The cell in row `r` is chosen by taking the first 8 bytes of
+    /// `SHA3-256(r | key)` as a big-endian integer, modulo `width`, where
+    /// `r` is a 4-byte big-endian integer.
+    pub fn cells_for_key(&self, key: &[u8]) -> Vec<CtrId> {
+        Vec::from_iter((0..self.depth).map(|row| {
+            let mut row_bytes = [0; 4];
+            NetworkEndian::write_u32(&mut row_bytes, row);
+            let mut d = Sha3::sha3_256();
+            d.input(&row_bytes);
+            d.input(key);
+            let mut digest = [0; 32];
+            d.result(&mut digest);
+            let col = NetworkEndian::read_u64(&digest[..8]) % u64::from(self.width);
+            CtrId(self.first_id + row * self.width + col as u32)
+        }))
+    }
+
+    /// Estimate the total for `key`, given the reconstructed tally.
+    ///
+    /// Returns None if any of the key's cells are missing from `totals`.
+    /// Otherwise, the result is never less than the key's true total.
+    pub fn query(&self, key: &[u8], totals: &HashMap<CtrId, u64>) -> Option<u64> {
+        let mut estimate = None;
+        for id in self.cells_for_key(key) {
+            let v = *totals.get(&id)?;
+            estimate = Some(estimate.map_or(v, |e: u64| e.min(v)));
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use client::*;
+    use math::PRIME_ORDER;
+    use noise::NoNoise;
+
+    #[test]
+    fn client_count() {
+        let mut rng = ::rand::os::OsRng::new().unwrap();
+        let tr_keys = vec![TrKeys {
+            enc_key: [9; 32],
+            signing_key: [1; 32],
+        }];
+        assert!(
+            CounterSet::new(&mut rng, &[CtrId(1), CLIENT_COUNT_ID], &tr_keys, 1, NoNoise).is_err()
+        );
+
+        let mut ctrs = CounterSet::new(&mut rng, &[CtrId(1)], &tr_keys, 1, NoNoise).unwrap();
+        assert!(ctrs.ctr(CLIENT_COUNT_ID).is_none());
+        assert!(ctrs.add_counter(&mut rng, CLIENT_COUNT_ID).is_err());
+        let data = ctrs
+            .finalize(&mut rng, &ClientKeyPair::from_seed(&[4; 32]))
+            .unwrap();
+        assert_eq!(data.counter_ids, vec![CtrId(1), CLIENT_COUNT_ID]);
+    }
+
+    #[test]
+    fn colliding_x_coords() {
+        let mut rng = ::rand::os::OsRng::new().unwrap();
+        let tr_keys = vec![
+            TrKeys {
+                enc_key: [9; 32],
+                signing_key: [1; 32],
+            },
+            TrKeys {
+                enc_key: [8; 32],
+                signing_key: [2; 32],
+            },
+            TrKeys {
+                enc_key: [7; 32],
+                signing_key: [1; 32],
+            },
+        ];
+        assert_eq!(
+            CounterSet::new(&mut rng, &[CtrId(1)], &tr_keys, 2, NoNoise).err(),
+            Some("Two TRs have the same signing key.")
+        );
+        assert!(CounterSet::new(&mut rng, &[CtrId(1)], &tr_keys[..2], 2, NoNoise).is_ok());
+
+        // With explicit X coordinates, the params catch it.
+        let x_coords = [FE::new(1), FE::new(2), FE::new(1)];
+        assert_eq!(
+            CounterSet::new_with_x_coords(&mut rng, &[CtrId(1)], &tr_keys, &x_coords, 2, NoNoise)
+                .err(),
+            Some("Duplicate X coordinate.")
+        );
+    }
+
+    #[test]
+    fn iterate() {
+        let mut rng = ::rand::os::OsRng::new().unwrap();
+        let tr_keys = vec![TrKeys {
+            enc_key: [9; 32],
+            signing_key: [1; 32],
+        }];
+        let ids = [CtrId(3), CtrId(1), CtrId(2)];
+        let mut ctrs = CounterSet::new(&mut rng, &ids, &tr_keys, 1, NoNoise).unwrap();
+        ctrs.add_counter(&mut rng, CtrId(7)).unwrap();
+
+        let expected = [CtrId(3), CtrId(1), CtrId(2), CLIENT_COUNT_ID, CtrId(7)];
+        assert_eq!(ctrs.counter_ids(), &expected[..]);
+        let seen = Vec::from_iter(ctrs.iter().map(|(id, ctr)| {
+            assert_eq!(id, ctr.id());
+            id
+        }));
+        assert_eq!(seen, expected.to_vec());
+    }
+
+    #[test]
+    fn sketch_layout() {
+        assert!(CountMinSketch::new(CtrId(0), 0, 10).is_err());
+        assert!(CountMinSketch::new(CtrId(0), 10, 0).is_err());
+        assert!(CountMinSketch::new(CtrId(0), 1 << 16, 1 << 16).is_err());
+        assert!(CountMinSketch::new(CtrId(u32::MAX - 5), 2, 3).is_err());
+
+        let sketch = CountMinSketch::new(CtrId(100), 3, 8).unwrap();
+        let ids = sketch.counter_ids();
+        assert_eq!(ids.len(), 24);
+        assert_eq!(ids[0], CtrId(100));
+        assert_eq!(ids[23], CtrId(123));
+
+        let cells = sketch.cells_for_key(b"example.com");
+        assert_eq!(cells, sketch.cells_for_key(b"example.com"));
+        assert_eq!(cells.len(), 3);
+        for (row, id) in cells.iter().enumerate() {
+            let row = row as u32;
+            assert!(id.0 >= 100 + row * 8 && id.0 < 100 + (row + 1) * 8);
+        }
+    }
+
+    #[test]
+    fn sketch_query() {
+        let sketch = CountMinSketch::new(CtrId(0), 4, 16).unwrap();
+        let mut totals = HashMap::new();
+        for id in sketch.counter_ids() {
+            totals.insert(id, 0);
+        }
+        for (key, n) in [(&b"a"[..], 5), (b"b", 17), (b"c", 1)].iter() {
+            for id in sketch.cells_for_key(key) {
+                *totals.get_mut(&id).unwrap() += *n;
+            }
+        }
+        assert!(sketch.query(b"a", &totals).unwrap() >= 5);
+        assert!(sketch.query(b"b", &totals).unwrap() >= 17);
+        assert!(sketch.query(b"c", &totals).unwrap() >= 1);
+        assert!(sketch.query(b"a", &HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn checked_inc() {
+        let mut a = Counter::new(CtrId(1));
+        a.set_ceiling(100);
+        a.checked_inc(60).unwrap();
+        a.checked_inc(40).unwrap();
+        assert_eq!(a.checked_inc(1), Err(OverflowError));
+        assert_eq!(a.val, FE::new(100));
+
+        a.dec(30);
+        a.checked_inc(30).unwrap();
+        assert!(a.checked_inc(u32::MAX).is_err());
+        a.inc_scaled(2, 5);
+        assert!(a.checked_inc(0).is_err());
+        assert_eq!(a.val, FE::new(110));
+
+        // The default ceiling is max_secret().
+        let mut b = Counter::new(CtrId(2));
+        b.checked_inc(u32::MAX).unwrap();
+        b.inc_scaled(u32::MAX, 1 << 30);
+        assert!(b.checked_inc(u32::MAX).is_err());
+    }
+
+    #[test]
+    fn counter_debug() {
+        let mut a = Counter::new(CtrId(3));
+        a.inc(12345);
+        assert_eq!(format!("{:?}", a), "Counter { id: CtrId(3), .. }");
+    }
+
+    #[test]
+    fn inc_u64() {
+        let big = u64::from(u32::MAX) * 3 + 5;
+        let mut a = Counter::new(CtrId(1));
+        a.inc_u64(big);
+        a.inc_u64(big);
+        a.inc(7);
+        assert_eq!(a.val, FE::new(big * 2 + 7));
+        a.inc_u64(PRIME_ORDER - 1);
+        assert_eq!(a.val, FE::new(big * 2 + 6));
+
+        let mut b = Counter::new(CtrId(2));
+        b.inc(10);
+        b.dec_u64(big);
+        assert_eq!(b.val, FE::new(PRIME_ORDER + 10 - big));
+        b.dec(1);
+        assert_eq!(b.val.signed_value(), 9 - big as i64);
+    }
+
+    #[test]
+    fn inc_scaled() {
+        let mut a = Counter::new(CtrId(1));
+        let mut b = Counter::new(CtrId(1));
+        a.inc_scaled(1000, 7);
+        b.inc(7000);
+        assert_eq!(a.val, b.val);
+
+        a.inc_scaled(u32::MAX, u32::MAX);
+        assert_eq!(a.val, FE::new(7000 + (u32::MAX as u64) * (u32::MAX as u64)));
+
+        a.inc_scaled(0, 99);
+        a.inc_scaled(99, 0);
+        assert_eq!(a.val, FE::new(7000 + (u32::MAX as u64) * (u32::MAX as u64)));
+    }
+}
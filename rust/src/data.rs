@@ -2,9 +2,14 @@
 
 use byteorder::{ByteOrder, NetworkEndian};
 use crypto::digest::Digest;
+use crypto::ed25519;
 use crypto::sha3;
+use crypto::util::fixed_time_eq;
+use rand::Rng;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
 
-use math::FE;
+use math::{hash_to_nonzero_fe, FE};
 
 /// A mostly-opaque identifier for a single Privcount counter.
 ///
@@ -19,6 +24,19 @@ pub struct ClientKey {
     pub signing_key: [u8; 32],
 }
 
+/// A Privcount client's signing keypair.
+///
+/// The client signs every TrData that it sends with the secret key, so
+/// that the TRs can check that the data came from the client whose
+/// `ClientKey` they expected.
+pub struct ClientKeyPair {
+    /// The Ed25519 secret key, in the 64-byte form that `crypto::ed25519`
+    /// expects.
+    secret: [u8; 64],
+    /// The corresponding public key.
+    public: ClientKey,
+}
+
 /// The key material, as seen by a Privcount client, for a Privcount TR.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TrKeys {
@@ -36,10 +54,44 @@ pub struct TrData {
     pub encrypted_seed: Vec<u8>,
     /// The X coordinate for all of the shares that are sent to this TR
     pub x: FE,
+    /// The number of counters that this TrData claims to carry.
+    ///
+    /// The TR can check this before decrypting anything, and must check it
+    /// against the counters that it expects.
+    pub n_counters: u32,
     /// The encrypted Y coordinates for all of the shares that will be sent to
     /// this TR.  The counters correspond to the `counter_ids` fields within the
     /// `CounterData` structure.
     pub encrypted_counters: Vec<u8>,
+    /// The client's Ed25519 signature over everything else in this TrData,
+    /// and over the counter IDs.  See `TrData::sign()`.
+    pub signature: [u8; 64],
+}
+
+/// One piece of a TrData, small enough to send in a single network
+/// message.  See `TrData::chunk()`.
+///
+/// Every chunk carries a copy of the TrData's small fields, so that the
+/// receiver can tell which chunks belong together.  Only the first chunk
+/// (with `seq == 0`) carries the encrypted seed.
+#[derive(Debug, Clone)]
+pub struct TrDataChunk {
+    /// The keys for the TR receiving the data.
+    pub keys: TrKeys,
+    /// The X coordinate from the TrData.
+    pub x: FE,
+    /// The number of counters from the TrData.
+    pub n_counters: u32,
+    /// The position of this chunk, starting at 0.
+    pub seq: u32,
+    /// The total number of chunks for this TrData.
+    pub n_chunks: u32,
+    /// The client's signature from the TrData.
+    pub signature: [u8; 64],
+    /// The encrypted seed, if this is the first chunk; otherwise empty.
+    pub encrypted_seed: Vec<u8>,
+    /// This chunk's portion of the encrypted counters.
+    pub encrypted_counters: Vec<u8>,
 }
 
 /// All of the data that a client exports
@@ -53,10 +105,23 @@ pub struct CounterData {
 /// How many counters will we support?
 pub const MAX_COUNTERS: u32 = 1 << 28;
 
+/// A reserved counter ID for the number of contributing clients.
+///
+/// Every CounterSet includes this counter automatically, and increments
+/// it by exactly one, so its reconstructed total is the number of clients
+/// that reported.  Callers may not use this ID for their own counters.
+pub const CLIENT_COUNT_ID: CtrId = CtrId(u32::MAX);
+
 /// Tweak value used when encrypting the privcount seed.
 pub const SEED_ENCRYPTION_TWEAK: &'static [u8] = b"privctr-seed-v1";
 /// Tweak value used when encrypting the privcount Y coordinates
 pub const Y_ENCRYPTION_TWEAK: &'static [u8] = b"privctr-shares-v1";
+/// Tweak value used when deriving a TR's X coordinate from its keys.
+pub const X_COORD_TWEAK: &[u8] = b"privctr-x-coord-v1";
+/// Tweak value used when signing a TrData.
+pub const SIGNATURE_TWEAK: &[u8] = b"privctr-client-sig-v1";
+/// Tweak value used when deriving a counter ID from its name.
+pub const CTR_NAME_TWEAK: &[u8] = b"privctr-counter-name-v1";
 
 /// Length of a raw seed
 pub const SEED_LEN: usize = 32;
@@ -64,13 +129,109 @@ pub const SEED_LEN: usize = 32;
 /// A random seed value, extended with SHAKE256, to produce a "mask" value for each counter.
 pub struct Seed(Vec<u8>);
 
+/// An iterator over the counter masks generated from a Seed.
+///
+/// It keeps a SHAKE256 state, and squeezes out more bytes as needed.
+pub struct MaskIter {
+    xof: sha3::Sha3,
+}
+
+impl CtrId {
+    /// Return the CtrId for the counter with a given name.
+    ///
+    /// The ID is the first 32 bits of a SHA3-256 hash of the name, so
+    /// every client that uses the same name will get the same ID.  It is
+    /// never `CLIENT_COUNT_ID`.
+    ///
+    /// Note that 32 bits isn't very many: with a few thousand names, the
+    /// chance that two of them collide is around one in a thousand.  If
+    /// you have a fixed set of names, check them for collisions when you
+    /// configure them.
+    pub fn from_name(name: &str) -> CtrId {
+        let mut d = sha3::Sha3::sha3_256();
+        d.input(CTR_NAME_TWEAK);
+        d.input(name.as_bytes());
+        let mut digest = [0; 32];
+        d.result(&mut digest);
+        let id = NetworkEndian::read_u32(&digest[..4]);
+        if CtrId(id) == CLIENT_COUNT_ID {
+            CtrId(id - 1)
+        } else {
+            CtrId(id)
+        }
+    }
+}
+
+impl ClientKeyPair {
+    /// Create a ClientKeyPair from a 32-byte seed.
+    ///
+    /// The same seed always gives the same keys.
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        let (secret, public) = ed25519::keypair(seed);
+        ClientKeyPair {
+            secret,
+            public: ClientKey {
+                signing_key: public,
+            },
+        }
+    }
+
+    /// Generate a new random ClientKeyPair.
+    ///
+    /// Use a secure RNG here, or the keys will be predictable.
+    pub fn generate<R: Rng>(rng: &mut R) -> Self {
+        let mut seed = [0; 32];
+        rng.fill_bytes(&mut seed);
+        let result = ClientKeyPair::from_seed(&seed);
+        #[cfg(feature = "zeroize")]
+        seed.zeroize();
+        result
+    }
+
+    /// Return the public half of this keypair.
+    pub fn public(&self) -> &ClientKey {
+        &self.public
+    }
+}
+
+/// With the `zeroize` feature, a ClientKeyPair erases its secret key when
+/// it's dropped.
+#[cfg(feature = "zeroize")]
+impl Drop for ClientKeyPair {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
 impl TrKeys {
     /// Return the X coordinate that we should use for this TR's shares.
     ///
     /// This coordinate is generated from the TR's public signing key, so that it will be
-    /// the same for all shares that any client generates for this TR.
+    /// the same for all shares that any client generates for this TR.  It
+    /// is never zero.
+    ///
+    /// We hash the whole signing key, with X_COORD_TWEAK for domain
+    /// separation, so keys that share a prefix still get unrelated
+    /// coordinates.  Two different keys can still collide, but with `n`
+    /// TRs the birthday bound makes that happen with probability about
+    /// `n^2 / 2^63`: under 2^-43 even for MAX_SHARES TRs.  The client
+    /// checks for collisions anyway, since they'd make sharing fail.
     pub fn get_x_coord(&self) -> FE {
-        FE::new(NetworkEndian::read_u64(&self.signing_key[..8]))
+        let mut input = X_COORD_TWEAK.to_vec();
+        input.extend_from_slice(&self.signing_key);
+        hash_to_nonzero_fe(&input)
+    }
+
+    /// Return true if these keys are the same as `other`.
+    ///
+    /// Unlike `==`, this takes the same amount of time no matter where
+    /// the keys differ, so use it when comparing keys to decide whether
+    /// to decrypt something.
+    pub fn ct_eq(&self, other: &TrKeys) -> bool {
+        // Don't short-circuit: always compare both keys.
+        let enc_eq = fixed_time_eq(&self.enc_key, &other.enc_key);
+        let signing_eq = fixed_time_eq(&self.signing_key, &other.signing_key);
+        enc_eq & signing_eq
     }
 }
 
@@ -82,17 +243,326 @@ impl CounterData {
             tr_data,
         }
     }
+
+    /// Encode this CounterData for sending over the network.
+    ///
+    /// All integers are big-endian.  The encoding is:
+    ///
+    /// ```text
+    /// u32 number of counter IDs
+    /// u32 each counter ID
+    /// u32 number of TrData objects
+    /// each TrData, encoded as by TrData::to_bytes()
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if any length doesn't fit in a u32.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_u32(&mut out, self.counter_ids.len());
+        for id in self.counter_ids.iter() {
+            put_u32(&mut out, id.0 as usize);
+        }
+        put_u32(&mut out, self.tr_data.len());
+        for d in self.tr_data.iter() {
+            d.encode_into(&mut out);
+        }
+        out
+    }
+
+    /// Decode a CounterData from the encoding that `to_bytes()` produces.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if the encoding is truncated, has extra bytes at the
+    /// end, has more than MAX_COUNTERS counters, or has an X coordinate
+    /// that isn't a canonical field element.
+    pub fn from_bytes(b: &[u8]) -> Result<CounterData, &'static str> {
+        let mut r = Reader(b);
+        let n_ids = r.u32()?;
+        if n_ids > MAX_COUNTERS {
+            return Err("Too many counters.");
+        }
+        let mut counter_ids = Vec::new();
+        for _ in 0..n_ids {
+            counter_ids.push(CtrId(r.u32()?));
+        }
+        let n_trs = r.u32()?;
+        let mut tr_data = Vec::new();
+        for _ in 0..n_trs {
+            tr_data.push(TrData::decode_from(&mut r)?);
+        }
+        if !r.0.is_empty() {
+            return Err("Extra bytes after CounterData.");
+        }
+        Ok(CounterData::new(counter_ids, tr_data))
+    }
+}
+
+/// Helper: append `v` to `out` as a big-endian u32.
+///
+/// # Panics
+///
+/// Panics if `v` doesn't fit in a u32.
+fn put_u32(out: &mut Vec<u8>, v: usize) {
+    assert!(v <= u32::MAX as usize);
+    let mut b = [0; 4];
+    NetworkEndian::write_u32(&mut b, v as u32);
+    out.extend_from_slice(&b);
+}
+
+/// Helper: a cursor for decoding the bytes in a slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Remove and return the next `n` bytes.
+    fn take(&mut self, n: usize) -> Result<&'a [u8], &'static str> {
+        if self.0.len() < n {
+            return Err("Truncated input.");
+        }
+        let (these, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(these)
+    }
+
+    /// Remove and return the next big-endian u32.
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(NetworkEndian::read_u32(self.take(4)?))
+    }
 }
 
 impl TrData {
     /// Construct a new TRData object.
-    pub fn new(keys: &TrKeys, encrypted_seed: Vec<u8>, x: FE, encrypted_counters: Vec<u8>) -> Self {
+    pub fn new(
+        keys: &TrKeys,
+        encrypted_seed: Vec<u8>,
+        x: FE,
+        n_counters: u32,
+        encrypted_counters: Vec<u8>,
+    ) -> Self {
         TrData {
             keys: keys.clone(),
             encrypted_seed,
             x,
+            n_counters,
             encrypted_counters,
+            signature: [0; 64],
+        }
+    }
+
+    /// Sign this TrData with the client's keys.
+    ///
+    /// `counter_ids` must be the counter IDs from the CounterData that
+    /// this TrData goes in.  We sign them along with everything in the
+    /// TrData, so that nobody can reorder the counters, or move this
+    /// TrData to another TR, without breaking the signature.
+    pub fn sign(&mut self, counter_ids: &[CtrId], keys: &ClientKeyPair) {
+        let msg = self.signed_message(counter_ids);
+        self.signature = ed25519::signature(&msg, &keys.secret);
+    }
+
+    /// Return true if this TrData has a valid signature by `client`, for
+    /// the counter IDs `counter_ids`.
+    pub fn verify_signature(&self, counter_ids: &[CtrId], client: &ClientKey) -> bool {
+        let msg = self.signed_message(counter_ids);
+        ed25519::verify(&msg, &client.signing_key, &self.signature)
+    }
+
+    /// Helper: return the message that `sign()` signs.
+    fn signed_message(&self, counter_ids: &[CtrId]) -> Vec<u8> {
+        let mut msg = SIGNATURE_TWEAK.to_vec();
+        put_u32(&mut msg, counter_ids.len());
+        for id in counter_ids.iter() {
+            put_u32(&mut msg, id.0 as usize);
+        }
+        self.encode_unsigned_into(&mut msg);
+        msg
+    }
+
+    /// Encode this TrData for sending over the network.
+    ///
+    /// All integers are big-endian.  The encoding is:
+    ///
+    /// ```text
+    /// [u8; 32] enc_key
+    /// [u8; 32] signing_key
+    /// u32 length of encrypted_seed
+    /// [u8] encrypted_seed
+    /// [u8; 8] X coordinate
+    /// u32 n_counters
+    /// u32 length of encrypted_counters
+    /// [u8] encrypted_counters
+    /// [u8; 64] signature
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if either length doesn't fit in a u32.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    /// Decode a TrData from the encoding that `to_bytes()` produces.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if the encoding is truncated, has extra bytes at the
+    /// end, or has an X coordinate that isn't a canonical field element.
+    pub fn from_bytes(b: &[u8]) -> Result<TrData, &'static str> {
+        let mut r = Reader(b);
+        let result = TrData::decode_from(&mut r)?;
+        if !r.0.is_empty() {
+            return Err("Extra bytes after TrData.");
+        }
+        Ok(result)
+    }
+
+    /// Helper: append the encoding of this TrData to `out`.
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.encode_unsigned_into(out);
+        out.extend_from_slice(&self.signature);
+    }
+
+    /// Helper: append the encoding of this TrData, without its signature,
+    /// to `out`.
+    fn encode_unsigned_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.keys.enc_key);
+        out.extend_from_slice(&self.keys.signing_key);
+        put_u32(out, self.encrypted_seed.len());
+        out.extend_from_slice(&self.encrypted_seed);
+        out.extend_from_slice(&self.x.to_bytes());
+        put_u32(out, self.n_counters as usize);
+        put_u32(out, self.encrypted_counters.len());
+        out.extend_from_slice(&self.encrypted_counters);
+    }
+
+    /// Helper: decode a TrData from the front of `r`.
+    fn decode_from(r: &mut Reader) -> Result<TrData, &'static str> {
+        let mut keys = TrKeys {
+            enc_key: [0; 32],
+            signing_key: [0; 32],
+        };
+        keys.enc_key.copy_from_slice(r.take(32)?);
+        keys.signing_key.copy_from_slice(r.take(32)?);
+        let seed_len = r.u32()? as usize;
+        let encrypted_seed = r.take(seed_len)?.to_vec();
+        let mut x = [0; 8];
+        x.copy_from_slice(r.take(8)?);
+        let x = FE::from_bytes(&x).ok_or("X coordinate out of range.")?;
+        let n_counters = r.u32()?;
+        let counters_len = r.u32()? as usize;
+        let encrypted_counters = r.take(counters_len)?.to_vec();
+        let mut result = TrData::new(&keys, encrypted_seed, x, n_counters, encrypted_counters);
+        result.signature.copy_from_slice(r.take(64)?);
+        Ok(result)
+    }
+
+    /// Return true if this TrData has the same structure as `other`: that
+    /// is, if they have the same keys, X coordinate, and number of
+    /// counters.
+    ///
+    /// This deliberately ignores the encrypted seed and the encrypted
+    /// counters, which are different every time a client encrypts them.
+    /// It's meant for tests that want to check which TRs a client is
+    /// reporting to, and with which X coordinates; it says nothing about
+    /// whether the payloads match.
+    pub fn eq_ignoring_ciphertext(&self, other: &TrData) -> bool {
+        self.keys == other.keys && self.x == other.x && self.n_counters == other.n_counters
+    }
+
+    /// Split this TrData into chunks carrying at most `max_bytes` of
+    /// encrypted counters each.
+    ///
+    /// The chunks are returned in order, but they can be sent and received
+    /// in any order: `reassemble()` puts them back together by their
+    /// sequence numbers.  There is always at least one chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_bytes` is zero, or if more than `u32::MAX` chunks
+    /// would be needed.
+    pub fn chunk(&self, max_bytes: usize) -> Vec<TrDataChunk> {
+        assert!(max_bytes > 0);
+        let pieces: Vec<&[u8]> = if self.encrypted_counters.is_empty() {
+            vec![&[]]
+        } else {
+            self.encrypted_counters.chunks(max_bytes).collect()
+        };
+        assert!(pieces.len() <= u32::MAX as usize);
+        let n_chunks = pieces.len() as u32;
+
+        pieces
+            .into_iter()
+            .enumerate()
+            .map(|(seq, piece)| TrDataChunk {
+                keys: self.keys.clone(),
+                x: self.x,
+                n_counters: self.n_counters,
+                seq: seq as u32,
+                n_chunks,
+                signature: self.signature,
+                encrypted_seed: if seq == 0 {
+                    self.encrypted_seed.clone()
+                } else {
+                    Vec::new()
+                },
+                encrypted_counters: piece.to_vec(),
+            })
+            .collect()
+    }
+
+    /// Reassemble a TrData from the chunks that `chunk()` produced.
+    ///
+    /// The chunks may be given in any order.  The encrypted counters are
+    /// MAC'd as a whole, so we must recover them exactly: we give an error
+    /// if any chunk is missing or duplicated, or if the chunks disagree
+    /// about which TrData they came from.
+    pub fn reassemble(chunks: &[TrDataChunk]) -> Result<TrData, &'static str> {
+        let first = chunks.first().ok_or("No chunks to reassemble.")?;
+        if chunks.len() != first.n_chunks as usize {
+            return Err("Wrong number of chunks.");
+        }
+
+        let mut ordered: Vec<Option<&TrDataChunk>> = vec![None; chunks.len()];
+        for c in chunks.iter() {
+            if c.keys != first.keys
+                || c.x != first.x
+                || c.n_counters != first.n_counters
+                || c.n_chunks != first.n_chunks
+                || c.signature[..] != first.signature[..]
+            {
+                return Err("Chunks are from different TrData objects.");
+            }
+            let slot = ordered
+                .get_mut(c.seq as usize)
+                .ok_or("Chunk sequence number out of range.")?;
+            if slot.is_some() {
+                return Err("Duplicate chunk.");
+            }
+            *slot = Some(c);
+        }
+
+        // Since there are exactly n_chunks chunks, and none is a
+        // duplicate, every slot should now be full.
+        let mut encrypted_counters = Vec::new();
+        for c in ordered.iter() {
+            let c = c.ok_or("Missing chunk.")?;
+            encrypted_counters.extend_from_slice(&c.encrypted_counters);
         }
+        let encrypted_seed = ordered[0].ok_or("Missing chunk.")?.encrypted_seed.clone();
+
+        let mut result = TrData::new(
+            &first.keys,
+            encrypted_seed,
+            first.x,
+            first.n_counters,
+            encrypted_counters,
+        );
+        result.signature = first.signature;
+        Ok(result)
     }
 }
 
@@ -109,40 +579,367 @@ impl Seed {
             Err("Incorrect seed length.")
         }
     }
+    /// Return an iterator over the counter masks for this seed.
+    ///
+    /// The iterator never runs out, and yields exactly the same sequence of
+    /// masks as `counter_masks()`.
+    pub fn mask_iter(self) -> MaskIter {
+        let mut xof = sha3::Sha3::shake_256();
+        xof.input(&self.0);
+        MaskIter { xof }
+    }
+
     /// Create a vector of `n_masks` counter masks from this seed.
     ///
     /// These masks are used to initialize the counters to a value based on the seed,
     /// which can then be encrypted and forgotten.
+    ///
+    /// We squeeze SHAKE256 for as long as we need to, so out-of-range
+    /// values never make this fail: we just skip them.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if `n_masks` is more than MAX_COUNTERS.
     pub fn counter_masks(self, n_masks: u32) -> Result<Vec<FE>, &'static str> {
-        const EXTRA_MASKS: u32 = 4;
-        const EXTRA_BYTES_PER_MASK: usize = 1;
-
         if n_masks > MAX_COUNTERS {
             return Err("Too many counters to generate masks for.");
         }
+        Ok(self.mask_iter().take(n_masks as usize).collect())
+    }
+}
 
-        // With very high probability, this is more data than we need.
-        let bytes_needed: usize = (n_masks + EXTRA_MASKS) as usize * (8 + EXTRA_BYTES_PER_MASK);
+/// Helper: turn the 64-bit values from `next_word` into a counter mask,
+/// skipping any that are out of range.
+fn next_mask<F: FnMut() -> u64>(mut next_word: F) -> FE {
+    // Out-of-range values are rare, but we have to skip them to stay
+    // unbiased.
+    loop {
+        if let Some(elt) = FE::from_u64_unbiased(next_word()) {
+            return elt;
+        }
+    }
+}
 
+impl Iterator for MaskIter {
+    type Item = FE;
+    fn next(&mut self) -> Option<FE> {
+        let xof = &mut self.xof;
+        Some(next_mask(|| {
+            let mut bytes = [0; 8];
+            xof.result(&mut bytes);
+            NetworkEndian::read_u64(&bytes)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data::*;
+    use std::iter::FromIterator;
+
+    #[test]
+    fn mask_iter_matches_counter_masks() {
+        for n in [0, 1, 7, 100, 5000].iter() {
+            let seed = [*n as u8; SEED_LEN];
+            let eager = Seed::from_bytes(&seed).unwrap().counter_masks(*n).unwrap();
+            let lazy: Vec<_> = Seed::from_bytes(&seed)
+                .unwrap()
+                .mask_iter()
+                .take(*n as usize)
+                .collect();
+            assert_eq!(eager, lazy);
+        }
+    }
+
+    #[test]
+    fn counter_masks_are_unchanged() {
+        // Generating the masks incrementally gives the same masks as
+        // reading a single large block from SHAKE256, as we used to.
+        let seed = [0x33; SEED_LEN];
+        let n = 1000;
         let mut xof = sha3::Sha3::shake_256();
-        let mut bytes = Vec::new();
-        bytes.resize(bytes_needed, 0);
-        xof.input(&self.0);
+        xof.input(&seed);
+        let mut bytes = vec![0; (n + 4) * 9];
         xof.result(&mut bytes);
+        let one_shot = Vec::from_iter(
+            bytes
+                .chunks(8)
+                .filter_map(|b| FE::from_u64_unbiased(NetworkEndian::read_u64(b)))
+                .take(n),
+        );
+        let masks = Seed::from_bytes(&seed)
+            .unwrap()
+            .counter_masks(n as u32)
+            .unwrap();
+        assert_eq!(masks, one_shot);
 
-        let mut result = Vec::new();
-        let mut slice = &bytes[..];
-        while result.len() < n_masks as usize {
-            if slice.len() < 8 {
-                return Err("Internal error: too many masks were out-of-range.");
-            }
-            let (these, remainder) = slice.split_at(8);
-            let v64 = NetworkEndian::read_u64(these);
-            if let Some(elt) = FE::from_u64_unbiased(v64) {
-                result.push(elt)
-            }
-            slice = remainder;
+        assert!(Seed::from_bytes(&seed)
+            .unwrap()
+            .counter_masks(MAX_COUNTERS + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn mask_rejections() {
+        use math::PRIME_ORDER;
+        // Values whose low 62 bits are PRIME_ORDER or more get skipped,
+        // however many of them there are.
+        let words = [
+            u64::MAX,
+            PRIME_ORDER,
+            PRIME_ORDER | (1 << 63),
+            (1 << 62) - 1,
+            PRIME_ORDER + 1,
+            u64::MAX - 1,
+            PRIME_ORDER - 1,
+            12345,
+        ];
+        let mut words = words.iter();
+        assert_eq!(
+            next_mask(|| *words.next().unwrap()),
+            FE::new(PRIME_ORDER - 1)
+        );
+        assert_eq!(next_mask(|| *words.next().unwrap()), FE::new(12345));
+        assert!(words.next().is_none());
+
+        // The high bits are ignored.
+        let mut words = [(1 << 63) | 77].iter();
+        assert_eq!(next_mask(|| *words.next().unwrap()), FE::new(77));
+    }
+
+    #[test]
+    fn ctr_id_from_name() {
+        let names = [
+            "exit.bytes",
+            "exit.streams",
+            "exit.bytes ",
+            "Exit.bytes",
+            "entry.connections",
+            "",
+        ];
+        let ids = Vec::from_iter(names.iter().map(|n| CtrId::from_name(n)));
+        for (i, (name, id)) in names.iter().zip(ids.iter()).enumerate() {
+            assert_eq!(CtrId::from_name(name), *id);
+            assert!(*id != CLIENT_COUNT_ID);
+            assert!(!ids[..i].contains(id));
         }
-        Ok(result)
+    }
+
+    #[test]
+    fn x_coord() {
+        let mut signing_key = [0; 32];
+        let zero = TrKeys {
+            enc_key: [1; 32],
+            signing_key,
+        };
+        assert!(zero.get_x_coord() != FE::new(0));
+
+        // Keys whose first 8 bytes are zero still get distinct, nonzero
+        // coordinates.
+        signing_key[31] = 1;
+        let low = TrKeys {
+            enc_key: [1; 32],
+            signing_key,
+        };
+        assert!(low.get_x_coord() != FE::new(0));
+        assert!(low.get_x_coord() != zero.get_x_coord());
+
+        // The coordinate only depends on the signing key.
+        let other_enc = TrKeys {
+            enc_key: [2; 32],
+            signing_key,
+        };
+        assert_eq!(other_enc.get_x_coord(), low.get_x_coord());
+    }
+
+    #[test]
+    fn signatures() {
+        let keys = ClientKeyPair::from_seed(&[5; 32]);
+        let other = ClientKeyPair::from_seed(&[6; 32]);
+        assert_eq!(keys.public(), ClientKeyPair::from_seed(&[5; 32]).public());
+        assert!(keys.public() != other.public());
+
+        let ids = [CtrId(1), CtrId(2), CtrId(3)];
+        let data = sample_tr_data(16);
+        assert!(data.verify_signature(&ids, keys.public()));
+        assert!(!data.verify_signature(&ids, other.public()));
+        assert!(!data.verify_signature(&[CtrId(2), CtrId(1), CtrId(3)], keys.public()));
+
+        let mut bad = sample_tr_data(16);
+        bad.encrypted_seed[0] ^= 1;
+        assert!(!bad.verify_signature(&ids, keys.public()));
+        let mut bad = sample_tr_data(16);
+        bad.x = FE::new(78);
+        assert!(!bad.verify_signature(&ids, keys.public()));
+        let mut bad = sample_tr_data(16);
+        bad.signature[63] ^= 1;
+        assert!(!bad.verify_signature(&ids, keys.public()));
+    }
+
+    #[test]
+    fn keys_ct_eq() {
+        let a = TrKeys {
+            enc_key: [1; 32],
+            signing_key: [2; 32],
+        };
+        let mut b = a.clone();
+        assert!(a.ct_eq(&b));
+        b.enc_key[31] = 0;
+        assert!(!a.ct_eq(&b));
+        let mut c = a.clone();
+        c.signing_key[0] = 0;
+        assert!(!a.ct_eq(&c));
+    }
+
+    fn sample_tr_data(n_bytes: usize) -> TrData {
+        let keys = TrKeys {
+            enc_key: [1; 32],
+            signing_key: [2; 32],
+        };
+        let counters = (0..n_bytes).map(|i| i as u8).collect();
+        let mut data = TrData::new(&keys, vec![9; 40], FE::new(77), 3, counters);
+        data.sign(
+            &[CtrId(1), CtrId(2), CtrId(3)],
+            &ClientKeyPair::from_seed(&[5; 32]),
+        );
+        data
+    }
+
+    #[test]
+    fn structural_eq() {
+        let a = sample_tr_data(16);
+        let mut b = sample_tr_data(24);
+        b.encrypted_seed = vec![1; 40];
+        assert!(a.eq_ignoring_ciphertext(&b));
+        b.x = FE::new(1);
+        assert!(!a.eq_ignoring_ciphertext(&b));
+        let mut c = sample_tr_data(16);
+        c.keys.enc_key = [0; 32];
+        assert!(!a.eq_ignoring_ciphertext(&c));
+        let mut d = sample_tr_data(16);
+        d.n_counters = 4;
+        assert!(!a.eq_ignoring_ciphertext(&d));
+    }
+
+    #[test]
+    fn counter_data_encoding() {
+        use client::CounterSet;
+        use noise::NoNoise;
+        let mut rng = ::rand::os::OsRng::new().unwrap();
+        let tr_keys = vec![
+            TrKeys {
+                enc_key: [9; 32],
+                signing_key: [1; 32],
+            },
+            TrKeys {
+                enc_key: [8; 32],
+                signing_key: [2; 32],
+            },
+        ];
+        let mut ctrs =
+            CounterSet::new(&mut rng, &[CtrId(1), CtrId(5)], &tr_keys, 2, NoNoise).unwrap();
+        ctrs.ctr(CtrId(5)).unwrap().inc(3);
+        let data = ctrs
+            .finalize(&mut rng, &ClientKeyPair::from_seed(&[4; 32]))
+            .unwrap();
+
+        let encoded = data.to_bytes();
+        let back = CounterData::from_bytes(&encoded).unwrap();
+        assert_eq!(back.counter_ids, data.counter_ids);
+        assert_eq!(back.tr_data.len(), 2);
+        for (a, b) in back.tr_data.iter().zip(data.tr_data.iter()) {
+            assert!(a.eq_ignoring_ciphertext(b));
+            assert_eq!(a.encrypted_seed, b.encrypted_seed);
+            assert_eq!(a.encrypted_counters, b.encrypted_counters);
+            assert_eq!(a.signature[..], b.signature[..]);
+        }
+        assert_eq!(back.to_bytes(), encoded);
+
+        assert!(CounterData::from_bytes(&encoded[..encoded.len() - 1]).is_err());
+        let mut extra = encoded.clone();
+        extra.push(0);
+        assert!(CounterData::from_bytes(&extra).is_err());
+        assert!(CounterData::from_bytes(&[]).is_err());
+        let empty = CounterData::new(Vec::new(), Vec::new());
+        assert_eq!(empty.to_bytes(), vec![0; 8]);
+    }
+
+    #[test]
+    fn tr_data_encoding() {
+        let data = sample_tr_data(24);
+        let encoded = data.to_bytes();
+        assert_eq!(encoded.len(), 32 + 32 + 4 + 40 + 8 + 4 + 4 + 24 + 64);
+        let back = TrData::from_bytes(&encoded).unwrap();
+        assert!(back.eq_ignoring_ciphertext(&data));
+        assert_eq!(back.signature[..], data.signature[..]);
+        assert_eq!(back.encrypted_seed, data.encrypted_seed);
+        assert_eq!(back.encrypted_counters, data.encrypted_counters);
+
+        // Truncated anywhere: in the keys, in a length, in the seed, in the
+        // X coordinate, and in the counters.
+        for &len in [0, 10, 66, 70, 110, 120, 140, encoded.len() - 1].iter() {
+            assert_eq!(
+                TrData::from_bytes(&encoded[..len]).err(),
+                Some("Truncated input.")
+            );
+        }
+        let mut extra = encoded.clone();
+        extra.push(0);
+        assert!(TrData::from_bytes(&extra).is_err());
+
+        // A length that runs off the end.
+        let mut long = encoded.clone();
+        long[64..68].copy_from_slice(&[0xff; 4]);
+        assert_eq!(TrData::from_bytes(&long).err(), Some("Truncated input."));
+    }
+
+    #[test]
+    fn chunk_and_reassemble() {
+        for &(len, max, n) in [
+            (0, 10, 1),
+            (1, 10, 1),
+            (10, 10, 1),
+            (11, 10, 2),
+            (95, 8, 12),
+        ]
+        .iter()
+        {
+            let data = sample_tr_data(len);
+            let mut chunks = data.chunk(max);
+            assert_eq!(chunks.len(), n);
+            assert!(chunks.iter().all(|c| c.encrypted_counters.len() <= max));
+
+            chunks.reverse();
+            let back = TrData::reassemble(&chunks).unwrap();
+            assert_eq!(back.keys, data.keys);
+            assert_eq!(back.x, data.x);
+            assert_eq!(back.n_counters, data.n_counters);
+            assert_eq!(back.encrypted_seed, data.encrypted_seed);
+            assert_eq!(back.encrypted_counters, data.encrypted_counters);
+            assert_eq!(back.signature[..], data.signature[..]);
+        }
+    }
+
+    #[test]
+    fn reassemble_errors() {
+        let chunks = sample_tr_data(30).chunk(10);
+        assert!(TrData::reassemble(&[]).is_err());
+        // Missing chunk
+        assert!(TrData::reassemble(&chunks[1..]).is_err());
+        // Duplicate chunk
+        let dup = vec![chunks[0].clone(), chunks[1].clone(), chunks[1].clone()];
+        assert!(TrData::reassemble(&dup).is_err());
+        // Mismatched chunk
+        let mut other = chunks.clone();
+        other[2].x = FE::new(78);
+        assert!(TrData::reassemble(&other).is_err());
+        // Chunk with a different signature
+        let mut other = chunks.clone();
+        other[1].signature[0] ^= 1;
+        assert!(TrData::reassemble(&other).is_err());
+        // Out-of-range chunk
+        let mut other = chunks.clone();
+        other[2].seq = 3;
+        assert!(TrData::reassemble(&other).is_err());
     }
 }
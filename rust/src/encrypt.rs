@@ -1,13 +1,87 @@
 //! A hybrid encyption scheme used by PrivCount, and traits to support it.
 
 use rand::Rng;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+/// An error from encrypting a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptError {
+    /// We tried to compute a MAC longer than the MAC function can give.
+    MacTooLong,
+    /// The random number generator failed.
+    ///
+    /// (The `Rng` trait we use can't report failure, so nothing returns
+    /// this yet; it's here for encryptors whose RNGs can.)
+    RngFailure,
+    /// We couldn't write the ciphertext to its destination.
+    WriteFailed,
+}
+
+impl EncryptError {
+    /// Return a description of this error.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            EncryptError::MacTooLong => "MAC output too long.",
+            EncryptError::RngFailure => "Random number generator failed.",
+            EncryptError::WriteFailed => "Couldn't write ciphertext.",
+        }
+    }
+}
+
+impl Display for EncryptError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Error for EncryptError {}
+
+impl From<EncryptError> for &'static str {
+    fn from(e: EncryptError) -> &'static str {
+        e.as_str()
+    }
+}
 
 /// An encryptor is an object that knows how to compute tweaked encryptions of a
 /// given input.  It encapsulates whatever public keys or shared secrets are needed.
+///
+/// # Randomness
+///
+/// The `rng` passed to an encryptor must be a cryptographically secure
+/// RNG: anybody who can predict its output can decrypt the message.
+///
+/// It's a trait object, so that callers can pass any generator at all,
+/// including a `&mut R` for a generic `R: Rng`.  There's no generic
+/// version: an encryption only draws a few dozen bytes from the RNG,
+/// so the dynamic dispatch doesn't cost anything measurable.
 pub trait Encryptor {
     /// Encrypt the value `inp` using the tweak value `tweak`, and possibly the
     /// secure random number generator `rng`.  The output will be longer than the input.
-    fn encrypt(&self, inp: &[u8], tweak: &[u8], rng: &mut Rng) -> Result<Vec<u8>, &'static str>;
+    fn encrypt(
+        &self,
+        inp: &[u8],
+        tweak: &[u8],
+        rng: &mut dyn Rng,
+    ) -> Result<Vec<u8>, EncryptError> {
+        self.encrypt_with_aad(inp, tweak, &[], rng)
+    }
+
+    /// As `encrypt()`, but also authenticate the associated data `aad`.
+    ///
+    /// The associated data isn't included in the output, and isn't
+    /// hidden: it's for context (like a counter ID or an epoch) that the
+    /// recipient already knows, and that the ciphertext should only be
+    /// valid for.  The recipient must give the same `aad` to decrypt.
+    fn encrypt_with_aad(
+        &self,
+        inp: &[u8],
+        tweak: &[u8],
+        aad: &[u8],
+        rng: &mut dyn Rng,
+    ) -> Result<Vec<u8>, EncryptError>;
 }
 
 /// An encryptor is an object that knows how to compute tweaked
@@ -21,22 +95,129 @@ pub trait Decryptor {
     /// Note that this function returns an Option rather than a
     /// Result: It is generally dangerous to leak any information
     /// about why, exactly, a plaintext couldn't be decrypted.
-    fn decrypt(&self, inp: &[u8], tweak: &[u8]) -> Option<Vec<u8>>;
+    fn decrypt(&self, inp: &[u8], tweak: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_with_aad(inp, tweak, &[])
+    }
+
+    /// As `decrypt()`, but also check that the input was encrypted with
+    /// the associated data `aad`.
+    fn decrypt_with_aad(&self, inp: &[u8], tweak: &[u8], aad: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Overwrite `buf`, which held secret material, with zeros.
+///
+/// This only does anything with the `zeroize` feature.
+#[cfg(feature = "zeroize")]
+fn wipe<Z: Zeroize + ?Sized>(buf: &mut Z) {
+    buf.zeroize();
 }
+/// Overwrite `buf`, which held secret material, with zeros.
+///
+/// This only does anything with the `zeroize` feature.
+#[cfg(not(feature = "zeroize"))]
+fn wipe<Z: ?Sized>(_buf: &mut Z) {}
 
 /// Functions to generate keys needed by privcount.
 pub mod keygen {
+    use crypto::curve25519::curve25519_base;
     use rand::Rng;
+    use std::fmt::{self, Debug, Formatter};
+    #[cfg(feature = "zeroize")]
+    use zeroize::Zeroize;
+
+    /// A Curve25519 secret key.
+    ///
+    /// This is its own type, rather than a `[u8; 32]`, so that it can't
+    /// be passed where a public key or an Ed25519 key belongs.
+    #[derive(Clone, PartialEq, Eq)]
+    pub struct Curve25519Secret([u8; 32]);
+
+    impl Curve25519Secret {
+        /// Use `bytes` as a Curve25519 secret key.
+        pub fn from_bytes(bytes: [u8; 32]) -> Self {
+            Curve25519Secret(bytes)
+        }
+
+        /// Return the bytes of this secret key.
+        pub fn as_bytes(&self) -> &[u8; 32] {
+            &self.0
+        }
+
+        /// Return the Curve25519 public key for this secret key.
+        pub fn public(&self) -> [u8; 32] {
+            curve25519_base(&self.0)
+        }
+    }
+
+    impl Debug for Curve25519Secret {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            // Don't leak the key into logs.
+            f.write_str("Curve25519Secret(..)")
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    impl Zeroize for Curve25519Secret {
+        fn zeroize(&mut self) {
+            self.0.zeroize();
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    impl Drop for Curve25519Secret {
+        fn drop(&mut self) {
+            self.zeroize();
+        }
+    }
+
     /// Generate and return a random Curve25519 secret key.
     ///
-    /// Obviously, you must use a secure RNG.
-    pub fn curve25519_seckey_gen(rng: &mut Rng) -> [u8; 32] {
+    /// Obviously, you must use a cryptographically secure RNG: anybody
+    /// who can predict its output can recover the key.
+    ///
+    /// The result is secret: the caller is responsible for erasing it
+    /// when it is no longer needed.  (With the `zeroize` feature, it's
+    /// erased when it's dropped, and so are the copies that `ServerKeys`
+    /// and `PrivcountDecryptor` keep.)
+    pub fn curve25519_seckey_gen(rng: &mut dyn Rng) -> Curve25519Secret {
         let mut result = [0; 32];
         rng.fill_bytes(&mut result);
         result[0] &= 248;
         result[31] &= 127;
         result[31] |= 64;
-        result
+        Curve25519Secret(result)
+    }
+
+    /// Personalization string for `ratchet_curve25519_seckey()`.
+    const RATCHET_TWEAK: &[u8] = b"privctr-period-ratchet-v1";
+
+    /// Derive the Curve25519 secret key for reporting period `period + 1`
+    /// from the secret key for `period`.
+    ///
+    /// The new key is the first 32 bytes of
+    /// `SHAKE256(RATCHET_TWEAK | period | seckey)`, clamped as in
+    /// `curve25519_seckey_gen()`, where `period` is an 8-byte big-endian
+    /// integer.
+    ///
+    /// This function is one-way: a later key reveals nothing about an
+    /// earlier one.  That only helps if the caller actually erases the
+    /// earlier keys.
+    pub fn ratchet_curve25519_seckey(seckey: &Curve25519Secret, period: u64) -> Curve25519Secret {
+        use byteorder::{ByteOrder, NetworkEndian};
+        use crypto::digest::Digest;
+        use crypto::sha3::Sha3;
+        let mut period_bytes = [0; 8];
+        NetworkEndian::write_u64(&mut period_bytes, period);
+        let mut xof = Sha3::shake_256();
+        xof.input(RATCHET_TWEAK);
+        xof.input(&period_bytes);
+        xof.input(&seckey.0);
+        let mut result = [0; 32];
+        xof.result(&mut result);
+        result[0] &= 248;
+        result[31] &= 127;
+        result[31] |= 64;
+        Curve25519Secret(result)
     }
 }
 
@@ -46,6 +227,11 @@ pub mod keygen {
 /// rend-spec-v3.txt section 2.5.3 and amended in the privcount-shamir
 /// spec.
 ///
+/// For platforms without AES hardware, `ChaChaEncryptor` and
+/// `ChaChaDecryptor` implement the same scheme with ChaCha20 in place of
+/// AES-CTR.  The two kinds of ciphertext have different version bytes, so
+/// neither can be mistaken for the other.
+///
 /// # Examples
 ///
 /// ```
@@ -53,16 +239,15 @@ pub mod keygen {
 /// extern crate rand;
 /// extern crate crypto;
 ///
-/// use privcount::encrypt::{Encryptor,Decryptor,hybrid};
-/// use crypto::curve25519;
+/// use privcount::encrypt::{Encryptor,Decryptor,EncryptError,hybrid};
 ///
-/// # pub fn main() -> Result<(), &'static str> {
+/// # pub fn main() -> Result<(), EncryptError> {
 /// // Use a secure RNG, folks.
 /// let mut rng = rand::os::OsRng::new().unwrap();
 ///
 /// // Let's suppose that we have a curve25519 keypair, an ed25519 key, and a message to send.
 /// let private_key = privcount::encrypt::keygen::curve25519_seckey_gen(&mut rng);
-/// let public_key = curve25519::curve25519_base(&private_key);
+/// let public_key = private_key.public();
 /// let identity_key = [123 ; 32];// pretend this is an ed25519 key.
 ///
 /// let secret_message = b"The magic words are Theophile Escargot.";
@@ -84,33 +269,109 @@ pub mod hybrid {
 
     use super::*;
     use crypto::aes;
+    use crypto::chacha20::ChaCha20;
     use crypto::curve25519::{curve25519, curve25519_base};
     use crypto::digest::Digest;
     use crypto::sha3;
+    use crypto::symmetriccipher::SynchronousStreamCipher;
     use crypto::util::fixed_time_eq;
+    use std::io::Write;
 
     /*
      * These values are specified as usize because they're used as the size of
      * buffer slices
      */
-    const SALT_LEN: usize = 16;
-    const S_KEY_LEN: usize = 32;
-    const S_IV_LEN: usize = 16;
-    const MAC_KEY_LEN: usize = 32; // ????????? specified anywhere?
-    const MAC_OUT_LEN: usize = 32;
+    /// Length of the random salt used by this encryption.
+    pub const SALT_LEN: usize = 16;
+    /// Length of the AES-256 key that `derive_keys()` puts first.
+    pub const S_KEY_LEN: usize = 32;
+    /// Length of the AES-CTR IV that `derive_keys()` puts second.
+    pub const S_IV_LEN: usize = 16;
+    /// Length of the MAC key that `derive_keys()` puts third.
+    pub const MAC_KEY_LEN: usize = 32;
+    /// Length of the MAC at the end of every ciphertext.
+    pub const MAC_OUT_LEN: usize = 32;
     /// Length of the Curve25519 public key used by this encryption.
     pub const PK_PUBLIC_LEN: usize = 32;
     /// Length of the Curve25519 secret key used by this encryption.
     pub const PK_SECRET_LEN: usize = 32;
     /// Length of the Ed25519 public key used by this encryption
     pub const SIGNING_PUBLIC_LEN: usize = 32;
-    /// The number of bytes added to a message by encrypting it.
-    pub const ENCRYPTED_OVERHEAD: usize = PK_PUBLIC_LEN + SALT_LEN + MAC_OUT_LEN;
+    /// Length of the version byte at the start of every ciphertext.
+    pub const VERSION_LEN: usize = 1;
+    /// The version byte for the current ciphertext format:
+    /// `version | pubkey | salt | ciphertext | mac`, encrypted with AES-CTR.
+    ///
+    /// (Version 0x01 was the same format, but its MAC didn't cover any
+    /// associated data.  We don't accept it any more: if the MAC only
+    /// covered associated data when there was some, then a ciphertext
+    /// with associated data could be passed off as a longer one without.)
+    pub const FORMAT_VERSION: u8 = 0x05;
+    /// The version byte for ciphertexts in the same format as
+    /// `FORMAT_VERSION`, but encrypted with ChaCha20.
+    pub const CHACHA_FORMAT_VERSION: u8 = 0x02;
+    /// Length of the ChaCha20 nonce: we use the first 12 bytes of the IV
+    /// from `derive_keys()`.
+    const CHACHA_NONCE_LEN: usize = 12;
+    /// The version byte for ciphertexts in the same format as
+    /// `FORMAT_VERSION`, but whose plaintext is padded: see
+    /// `PrivcountEncryptor::set_padding()`.
+    pub const PADDED_FORMAT_VERSION: u8 = 0x04;
+    /// Length of the message length that ends a padded plaintext.
+    const PADDING_LEN_LEN: usize = 8;
+    /// The number of bytes added to a message by encrypting it (without
+    /// padding).
+    pub const ENCRYPTED_OVERHEAD: usize = VERSION_LEN + PK_PUBLIC_LEN + SALT_LEN + MAC_OUT_LEN;
+    /// Length of the part of a ciphertext that comes before the
+    /// encrypted message.
+    const HEADER_LEN: usize = VERSION_LEN + PK_PUBLIC_LEN + SALT_LEN;
+    /// Length of all the symmetric key material we derive for a message.
+    const KEYS_LEN: usize = S_KEY_LEN + S_IV_LEN + MAC_KEY_LEN;
+    /// Domain-separation string used to derive keys in
+    /// `PrivcountEncryptor::encrypt_deterministic_derived()`.
+    const DERIVED_ENCRYPTION_CONST: &[u8] = b"privctr-derived-ephemeral-v1";
+    /// The version byte for a single recipient's part of a message from
+    /// `encrypt_to_many()`:
+    /// `version | wrapped data key | salt | ciphertext | mac`.
+    pub const MULTI_FORMAT_VERSION: u8 = 0x03;
+    /// Length of the random data key used by `encrypt_to_many()`.
+    const DATA_KEY_LEN: usize = 32;
+    /// Length of the data key, once it's encrypted to a recipient.
+    const WRAPPED_KEY_LEN: usize = ENCRYPTED_OVERHEAD + DATA_KEY_LEN;
+    /// The number of bytes added to a message by encrypting it with
+    /// `encrypt_to_many()`, for each recipient.
+    pub const MULTI_ENCRYPTED_OVERHEAD: usize =
+        VERSION_LEN + WRAPPED_KEY_LEN + SALT_LEN + MAC_OUT_LEN;
+    /// Associated data used when encrypting a data key to a recipient in
+    /// `encrypt_to_many()`, so that it can't be mistaken for a message.
+    const MULTI_RECIPIENT_CONST: &[u8] = b"privctr-multi-recipient-v1";
+
+    /// The stream cipher used to encrypt a message.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Cipher {
+        /// AES-256 in counter mode.
+        Aes,
+        /// ChaCha20, with a 96-bit nonce.
+        ChaCha,
+    }
+
+    impl Cipher {
+        /// Return the version byte for ciphertexts that use this cipher.
+        fn version(self) -> u8 {
+            match self {
+                Cipher::Aes => FORMAT_VERSION,
+                Cipher::ChaCha => CHACHA_FORMAT_VERSION,
+            }
+        }
+    }
 
     /// An Encryptor that implements the hybrid scheme used by privcount.
     pub struct PrivcountEncryptor {
         key: [u8; PK_PUBLIC_LEN],
         signing_key: [u8; SIGNING_PUBLIC_LEN],
+        cipher: Cipher,
+        /// If present, pad every plaintext to a multiple of this length.
+        padding: Option<usize>,
     }
 
     impl PrivcountEncryptor {
@@ -119,6 +380,8 @@ pub mod hybrid {
             PrivcountEncryptor {
                 key: *key,
                 signing_key: *signing_key,
+                cipher: Cipher::Aes,
+                padding: None,
             }
         }
 
@@ -126,78 +389,477 @@ pub mod hybrid {
         pub fn key(&self) -> &[u8; PK_PUBLIC_LEN] {
             &self.key
         }
+
+        /// Pad every message that this encryptor encrypts, so that the
+        /// length of the ciphertext only reveals the length of the message
+        /// rounded up to a multiple of `block_size`.
+        ///
+        /// The padded plaintext is `message | zeros | len(message)`, where
+        /// the length is a 64-bit big-endian integer, and there are as few
+        /// zeros as will make the total a multiple of `block_size`.  The
+        /// padding is encrypted and covered by the MAC like the rest of the
+        /// plaintext, and the ciphertext starts with `PADDED_FORMAT_VERSION`,
+        /// so a `PrivcountDecryptor` removes the padding automatically.
+        pub fn set_padding(&mut self, block_size: usize) -> Result<(), &'static str> {
+            if block_size == 0 {
+                return Err("Invalid padding block size.");
+            }
+            self.padding = Some(block_size);
+            Ok(())
+        }
+
+        /// Return the version byte for this encryptor's ciphertexts.
+        fn version(&self) -> u8 {
+            match self.padding {
+                Some(_) => PADDED_FORMAT_VERSION,
+                None => self.cipher.version(),
+            }
+        }
     }
 
-    impl Encryptor for PrivcountEncryptor {
-        fn encrypt(
+    impl PrivcountEncryptor {
+        /// Encrypt the value `inp` using the tweak value `tweak` and a
+        /// caller-provided `salt`.
+        ///
+        /// A fresh ephemeral key is still drawn from `rng`, so two
+        /// encryptions with the same salt will not be identical.
+        ///
+        /// This exists so that tests can exercise salt handling.
+        /// Production code must use `encrypt()`, which picks a random salt.
+        pub fn encrypt_with_salt(
             &self,
             inp: &[u8],
             tweak: &[u8],
-            rng: &mut Rng,
-        ) -> Result<Vec<u8>, &'static str> {
-            let mut keys = [0; S_KEY_LEN + S_IV_LEN + MAC_KEY_LEN];
+            salt: &[u8; SALT_LEN],
+            rng: &mut dyn Rng,
+        ) -> Result<Vec<u8>, EncryptError> {
+            let mut seckey_tmp = keygen::curve25519_seckey_gen(rng);
+            let result = self.encrypt_inner(inp, tweak, &[], seckey_tmp.as_bytes(), salt);
+            wipe(&mut seckey_tmp);
+            result
+        }
 
-            let seckey_tmp = super::keygen::curve25519_seckey_gen(rng);
-            let pubkey_tmp = curve25519_base(&seckey_tmp);
+        /// Encrypt the value `inp` using the tweak value `tweak`, with an
+        /// ephemeral key and salt derived deterministically from this
+        /// encryptor's keys, the message, and the tweak.
+        ///
+        /// **Don't use this for real data.**  Encrypting the same message
+        /// twice with this function gives the same ciphertext, so it gives
+        /// up the randomized-encryption property that the rest of this
+        /// module relies on.  It exists only so that independent
+        /// implementations can generate identical test vectors without
+        /// sharing an RNG.
+        ///
+        /// The ephemeral secret key and salt are the first PK_SECRET_LEN
+        /// and next SALT_LEN bytes of SHAKE256 over
+        /// `DERIVED_ENCRYPTION_CONST | key | signing_key | len(inp) | inp | tweak`,
+        /// where `len(inp)` is a 64-bit big-endian integer, and the key
+        /// is clamped as in `keygen::curve25519_seckey_gen()`.
+        pub fn encrypt_deterministic_derived(
+            &self,
+            inp: &[u8],
+            tweak: &[u8],
+        ) -> Result<Vec<u8>, EncryptError> {
+            use byteorder::{BigEndian as NetworkOrder, ByteOrder};
+            let mut inplen = [0; 8];
+            NetworkOrder::write_u64(&mut inplen, inp.len() as u64);
 
-            let shared_key = curve25519(&seckey_tmp, &self.key);
+            let mut xof = sha3::Sha3::shake_256();
+            xof.input(DERIVED_ENCRYPTION_CONST);
+            xof.input(&self.key);
+            xof.input(&self.signing_key);
+            xof.input(&inplen);
+            xof.input(inp);
+            xof.input(tweak);
+            let mut derived = [0; PK_SECRET_LEN + SALT_LEN];
+            xof.result(&mut derived);
+
+            let mut seckey_tmp = [0; PK_SECRET_LEN];
+            let mut salt = [0; SALT_LEN];
+            seckey_tmp.copy_from_slice(&derived[..PK_SECRET_LEN]);
+            salt.copy_from_slice(&derived[PK_SECRET_LEN..]);
+            seckey_tmp[0] &= 248;
+            seckey_tmp[31] &= 127;
+            seckey_tmp[31] |= 64;
+
+            let result = self.encrypt_inner(inp, tweak, &[], &seckey_tmp, &salt);
+            wipe(&mut derived);
+            wipe(&mut seckey_tmp);
+            result
+        }
+
+        /// Begin encrypting a message using the tweak value `tweak`,
+        /// writing the ciphertext to `out` as it's produced.
+        ///
+        /// Pass the message to the returned EncryptingWriter in as many
+        /// chunks as you like, then call its `finalize()` method.  The
+        /// output is the same as `encrypt()` would give for the whole
+        /// message with the same RNG, but neither the message nor its
+        /// ciphertext needs to be in memory all at once.
+        pub fn encrypt_writer<'a>(
+            &self,
+            tweak: &[u8],
+            rng: &mut dyn Rng,
+            out: &'a mut dyn Write,
+        ) -> Result<EncryptingWriter<'a>, EncryptError> {
+            let mut seckey_tmp = keygen::curve25519_seckey_gen(rng);
+            let salt = generate_salt(rng);
+            let result = self.start_encryption(tweak, seckey_tmp.as_bytes(), &salt, out);
+            wipe(&mut seckey_tmp);
+            result
+        }
+
+        /// Helper: encrypt `inp` with associated data `aad`, using a given
+        /// ephemeral secret key and salt.
+        fn encrypt_inner(
+            &self,
+            inp: &[u8],
+            tweak: &[u8],
+            aad: &[u8],
+            seckey_tmp: &[u8; PK_SECRET_LEN],
+            salt: &[u8; SALT_LEN],
+        ) -> Result<Vec<u8>, EncryptError> {
+            let mut result = Vec::with_capacity(inp.len() + ENCRYPTED_OVERHEAD);
+            {
+                let mut writer = self.start_encryption(tweak, seckey_tmp, salt, &mut result)?;
+                writer.update(inp)?;
+                writer.finalize_with_aad(aad)?;
+            }
+            Ok(result)
+        }
+
+        /// Helper: derive the keys for a message using a given ephemeral
+        /// secret key and salt, write the header of the ciphertext to
+        /// `out`, and return an EncryptingWriter for the rest.
+        fn start_encryption<'a>(
+            &self,
+            tweak: &[u8],
+            seckey_tmp: &[u8; PK_SECRET_LEN],
+            salt: &[u8; SALT_LEN],
+            out: &'a mut dyn Write,
+        ) -> Result<EncryptingWriter<'a>, EncryptError> {
+            let mut keys = [0; KEYS_LEN];
+
+            let pubkey_tmp = curve25519_base(seckey_tmp);
+
+            let mut shared_key = curve25519(seckey_tmp, &self.key);
             let mut secret_input = Vec::new();
             secret_input.extend_from_slice(&shared_key);
             secret_input.extend_from_slice(&self.signing_key);
 
-            let salt = generate_salt(rng);
+            derive_keys(&secret_input, tweak, salt, &mut keys);
+            wipe(&mut shared_key);
+            wipe(&mut secret_input);
+            let (cipher, mut mac) = symmetric_state(self.cipher, &keys);
+            wipe(&mut keys);
 
-            generate_keys(&secret_input, tweak, &salt, &mut keys);
-            let (enc_key, rest) = keys.split_at(S_KEY_LEN);
-            let (enc_iv, mac_key) = rest.split_at(S_IV_LEN);
-            debug_assert!(mac_key.len() == MAC_KEY_LEN);
+            let mut header = Vec::with_capacity(HEADER_LEN);
+            header.push(self.version());
+            header.extend_from_slice(&pubkey_tmp);
+            header.extend_from_slice(salt);
+            mac.input(&header);
+            out.write_all(&header)
+                .map_err(|_| EncryptError::WriteFailed)?;
 
-            let mut result = Vec::new();
-            result.extend_from_slice(&pubkey_tmp);
-            result.extend_from_slice(&salt);
+            Ok(EncryptingWriter {
+                cipher,
+                mac,
+                out,
+                buf: Vec::new(),
+                padding: self.padding,
+                len: 0,
+            })
+        }
+    }
 
-            let mut cipher = aes::ctr(aes::KeySize::KeySize256, enc_key, enc_iv);
-            let prefix_len = result.len();
-            result.resize(prefix_len + inp.len(), 0);
-            cipher.process(&inp, &mut result[prefix_len..]);
+    /// An encryption in progress, created by
+    /// `PrivcountEncryptor::encrypt_writer()`.
+    ///
+    /// Each chunk of the message is encrypted and written out as soon as
+    /// it arrives.
+    pub struct EncryptingWriter<'a> {
+        /// The cipher for the message.
+        cipher: Box<dyn SynchronousStreamCipher + 'static>,
+        /// The MAC of everything we've written so far.
+        mac: sha3::Sha3,
+        /// Where to write the ciphertext.
+        out: &'a mut dyn Write,
+        /// Scratch space for encrypting each chunk.
+        buf: Vec<u8>,
+        /// If present, the block size to pad the message to.
+        padding: Option<usize>,
+        /// The length of the message so far.
+        len: u64,
+    }
 
+    impl<'a> EncryptingWriter<'a> {
+        /// Encrypt the next `chunk` of the message, and write it out.
+        pub fn update(&mut self, chunk: &[u8]) -> Result<(), EncryptError> {
+            self.len += chunk.len() as u64;
+            self.write_encrypted(chunk)
+        }
+
+        /// Helper: encrypt `chunk` of the plaintext, and write it out.
+        fn write_encrypted(&mut self, chunk: &[u8]) -> Result<(), EncryptError> {
+            self.buf.resize(chunk.len(), 0);
+            self.cipher.process(chunk, &mut self.buf);
+            self.mac.input(&self.buf);
+            self.out
+                .write_all(&self.buf)
+                .map_err(|_| EncryptError::WriteFailed)
+        }
+
+        /// Finish the encryption, and write out the MAC.
+        pub fn finalize(self) -> Result<(), EncryptError> {
+            self.finalize_with_aad(&[])
+        }
+
+        /// As `finalize()`, but also authenticate the associated data
+        /// `aad`, as in `Encryptor::encrypt_with_aad()`.
+        pub fn finalize_with_aad(mut self, aad: &[u8]) -> Result<(), EncryptError> {
+            if let Some(block_size) = self.padding {
+                use byteorder::{BigEndian as NetworkOrder, ByteOrder};
+                let total = (self.len % block_size as u64) as usize + PADDING_LEN_LEN;
+                let n_zeros = (block_size - total % block_size) % block_size;
+                let mut trailer = vec![0; n_zeros + PADDING_LEN_LEN];
+                NetworkOrder::write_u64(&mut trailer[n_zeros..], self.len);
+                self.write_encrypted(&trailer)?;
+            }
             let mut mac_bytes = [0; MAC_OUT_LEN];
-            mac(&mac_key, &result, &mut mac_bytes)?;
-            result.extend_from_slice(&mac_bytes);
+            mac_finish(self.mac, aad, &mut mac_bytes)?;
+            wipe(&mut self.buf);
+            self.out
+                .write_all(&mac_bytes)
+                .map_err(|_| EncryptError::WriteFailed)
+        }
+    }
 
-            Ok(result)
+    impl Encryptor for PrivcountEncryptor {
+        fn encrypt_with_aad(
+            &self,
+            inp: &[u8],
+            tweak: &[u8],
+            aad: &[u8],
+            rng: &mut dyn Rng,
+        ) -> Result<Vec<u8>, EncryptError> {
+            let mut seckey_tmp = keygen::curve25519_seckey_gen(rng);
+            let salt = generate_salt(rng);
+            let result = self.encrypt_inner(inp, tweak, aad, seckey_tmp.as_bytes(), &salt);
+            wipe(&mut seckey_tmp);
+            result
+        }
+    }
+
+    /// Encrypt the value `inp` using the tweak value `tweak`, so that
+    /// each of several recipients can decrypt it.
+    ///
+    /// Each recipient is a Curve25519 public key and an Ed25519 signing
+    /// key, as for `PrivcountEncryptor::new()`.  We pick a random data
+    /// key, encrypt the message with it once, and encrypt the data key
+    /// to each recipient, so the per-recipient cost doesn't grow with the
+    /// size of the message.  Use `MultiCiphertext::for_recipient()` to
+    /// get the part that each recipient needs; recipients decrypt it with
+    /// an ordinary `PrivcountDecryptor`.
+    ///
+    /// Every recipient learns the data key, so any recipient could forge
+    /// a message that the others would accept.  Only use this when the
+    /// recipients trust each other that far.
+    pub fn encrypt_to_many(
+        recipients: &[([u8; PK_PUBLIC_LEN], [u8; SIGNING_PUBLIC_LEN])],
+        inp: &[u8],
+        tweak: &[u8],
+        rng: &mut dyn Rng,
+    ) -> Result<MultiCiphertext, EncryptError> {
+        let mut data_key = [0; DATA_KEY_LEN];
+        rng.fill_bytes(&mut data_key);
+        let result = encrypt_to_many_inner(recipients, &data_key, inp, tweak, rng);
+        wipe(&mut data_key);
+        result
+    }
+
+    /// Helper: implement `encrypt_to_many()` with a given data key.
+    fn encrypt_to_many_inner(
+        recipients: &[([u8; PK_PUBLIC_LEN], [u8; SIGNING_PUBLIC_LEN])],
+        data_key: &[u8; DATA_KEY_LEN],
+        inp: &[u8],
+        tweak: &[u8],
+        rng: &mut dyn Rng,
+    ) -> Result<MultiCiphertext, EncryptError> {
+        let mut wrapped_keys = Vec::with_capacity(recipients.len());
+        for (key, signing_key) in recipients.iter() {
+            let enc = PrivcountEncryptor::new(key, signing_key);
+            wrapped_keys.push(enc.encrypt_with_aad(data_key, tweak, MULTI_RECIPIENT_CONST, rng)?);
+        }
+
+        let salt = generate_salt(rng);
+        let mut keys = [0; KEYS_LEN];
+        derive_keys(data_key, tweak, &salt, &mut keys);
+        let (mut cipher, mut mac) = symmetric_state(Cipher::Aes, &keys);
+        wipe(&mut keys);
+
+        let mut body = Vec::with_capacity(SALT_LEN + inp.len() + MAC_OUT_LEN);
+        body.extend_from_slice(&salt);
+        body.resize(SALT_LEN + inp.len(), 0);
+        cipher.process(inp, &mut body[SALT_LEN..]);
+        mac.input(&[MULTI_FORMAT_VERSION]);
+        mac.input(&body);
+        let mut mac_bytes = [0; MAC_OUT_LEN];
+        mac_finish(mac, &[], &mut mac_bytes)?;
+        body.extend_from_slice(&mac_bytes);
+
+        Ok(MultiCiphertext { wrapped_keys, body })
+    }
+
+    /// A message encrypted to several recipients by `encrypt_to_many()`.
+    #[derive(Clone, Debug)]
+    pub struct MultiCiphertext {
+        /// The data key, encrypted to each recipient in turn.
+        wrapped_keys: Vec<Vec<u8>>,
+        /// The message, encrypted with the data key: `salt | ciphertext | mac`.
+        body: Vec<u8>,
+    }
+
+    impl MultiCiphertext {
+        /// Return the number of recipients for this message.
+        pub fn n_recipients(&self) -> usize {
+            self.wrapped_keys.len()
+        }
+
+        /// Return the part of this message that every recipient shares.
+        pub fn body(&self) -> &[u8] {
+            &self.body
+        }
+
+        /// Return the ciphertext for the recipient at position `idx` in
+        /// the list passed to `encrypt_to_many()`, or None if there is no
+        /// such recipient.
+        ///
+        /// The recipient can decrypt this with `Decryptor::decrypt()`.
+        pub fn for_recipient(&self, idx: usize) -> Option<Vec<u8>> {
+            let wrapped = self.wrapped_keys.get(idx)?;
+            let mut result = Vec::with_capacity(VERSION_LEN + wrapped.len() + self.body.len());
+            result.push(MULTI_FORMAT_VERSION);
+            result.extend_from_slice(wrapped);
+            result.extend_from_slice(&self.body);
+            Some(result)
+        }
+    }
+
+    /// Helper: remove the padding from a decrypted plaintext that was
+    /// padded as described in `PrivcountEncryptor::set_padding()`.  If
+    /// the padding is malformed, erase the plaintext and return false.
+    fn strip_padding(padded: &mut Vec<u8>) -> bool {
+        use byteorder::{BigEndian as NetworkOrder, ByteOrder};
+        let msg_len = if padded.len() >= PADDING_LEN_LEN {
+            let (body, len) = padded.split_at(padded.len() - PADDING_LEN_LEN);
+            let len = NetworkOrder::read_u64(len);
+            if len <= body.len() as u64 && body[len as usize..].iter().all(|b| *b == 0) {
+                Some(len as usize)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        match msg_len {
+            Some(len) => {
+                padded.truncate(len);
+                true
+            }
+            None => {
+                wipe(padded);
+                padded.clear();
+                false
+            }
         }
     }
 
     /// Return a random salt to be used for the hybrid encryption
-    fn generate_salt(rng: &mut Rng) -> [u8; SALT_LEN] {
+    fn generate_salt(rng: &mut dyn Rng) -> [u8; SALT_LEN] {
         let mut salt = [0; SALT_LEN];
         rng.fill_bytes(&mut salt);
         salt
     }
 
     /// Use SHAKE256 to fill `output` with key material based on the other inputs.
-    fn generate_keys(secret_input: &[u8], string_const: &[u8], salt: &[u8], output: &mut [u8]) {
+    ///
+    /// This is the key derivation function for the hybrid scheme: `output`
+    /// is filled with `SHAKE256(secret_input | salt | tweak)`.  The scheme
+    /// uses the first `S_KEY_LEN + S_IV_LEN + MAC_KEY_LEN` bytes of it:
+    ///
+    ///   * bytes `0..32`: the AES-256 key,
+    ///   * bytes `32..48`: the AES-CTR IV,
+    ///   * bytes `48..80`: the MAC key.
+    ///
+    /// In `PrivcountEncryptor`, `secret_input` is the Curve25519 shared
+    /// secret followed by the recipient's Ed25519 signing key.  Other
+    /// Encryptors can use this function to derive their keys compatibly.
+    ///
+    /// The inputs are concatenated without any length prefixes, so only
+    /// the last one can safely vary in length: every caller must use the
+    /// same fixed length for `secret_input`, and another for `salt`.
+    /// Otherwise, two different sets of inputs could give the same keys.
+    pub fn derive_keys(secret_input: &[u8], tweak: &[u8], salt: &[u8], output: &mut [u8]) {
         let mut xof = sha3::Sha3::shake_256();
         xof.input(secret_input);
         xof.input(salt);
-        xof.input(string_const);
+        xof.input(tweak);
         xof.result(output);
     }
 
-    /// SHA3-based MAC used to authenticate encrypted info.
-    fn mac(key: &[u8], val: &[u8], result: &mut [u8]) -> Result<(), &'static str> {
+    /// Helper: split the derived key material `keys` into a stream
+    /// cipher of type `cipher` and a MAC state, ready to process the
+    /// encrypted part of a message.
+    fn symmetric_state(
+        cipher: Cipher,
+        keys: &[u8; KEYS_LEN],
+    ) -> (Box<dyn SynchronousStreamCipher + 'static>, sha3::Sha3) {
+        let (enc_key, rest) = keys.split_at(S_KEY_LEN);
+        let (enc_iv, mac_key) = rest.split_at(S_IV_LEN);
+        debug_assert_eq!(mac_key.len(), MAC_KEY_LEN);
+        let stream: Box<dyn SynchronousStreamCipher + 'static> = match cipher {
+            Cipher::Aes => aes::ctr(aes::KeySize::KeySize256, enc_key, enc_iv),
+            Cipher::ChaCha => Box::new(ChaCha20::new(enc_key, &enc_iv[..CHACHA_NONCE_LEN])),
+        };
+        (stream, mac_start(mac_key))
+    }
+
+    /// SHA3-based MAC used to authenticate encrypted info `val`, along
+    /// with the associated data `aad`.
+    ///
+    /// The MAC covers `len(key) | key | val | aad | len(aad)`, where the
+    /// lengths are 64-bit big-endian integers.
+    fn mac(key: &[u8], val: &[u8], aad: &[u8], result: &mut [u8]) -> Result<(), EncryptError> {
+        let mut d = mac_start(key);
+        d.input(val);
+        mac_finish(d, aad, result)
+    }
+
+    /// Begin computing a MAC with `key`, as in `mac()`.  The caller
+    /// should input `val`, then call `mac_finish()`.
+    fn mac_start(key: &[u8]) -> sha3::Sha3 {
         use byteorder::{BigEndian as NetworkOrder, ByteOrder};
-        if result.len() > MAC_OUT_LEN {
-            return Err("MAC output too long.");
-        }
         let mut keylen = [0; 8];
         NetworkOrder::write_u64(&mut keylen, key.len() as u64);
 
         let mut d = sha3::Sha3::sha3_256();
         d.input(&keylen);
         d.input(key);
-        d.input(val);
+        d
+    }
+
+    /// Finish computing a MAC that was begun with `mac_start()`, using
+    /// the associated data `aad`.
+    fn mac_finish(mut d: sha3::Sha3, aad: &[u8], result: &mut [u8]) -> Result<(), EncryptError> {
+        use byteorder::{BigEndian as NetworkOrder, ByteOrder};
+        if result.len() > MAC_OUT_LEN {
+            return Err(EncryptError::MacTooLong);
+        }
+        let mut aadlen = [0; 8];
+        NetworkOrder::write_u64(&mut aadlen, aad.len() as u64);
+
+        d.input(aad);
+        d.input(&aadlen);
         d.result(result);
         Ok(())
     }
@@ -208,60 +870,405 @@ pub mod hybrid {
         secret_key: [u8; PK_SECRET_LEN],
         /// public ed25519 key.
         signing_key: [u8; SIGNING_PUBLIC_LEN],
+        /// The stream cipher we expect messages to use.
+        cipher: Cipher,
     }
 
     impl PrivcountDecryptor {
         /// Construct a new privcount decryptor from a curve25519 private key and a public
         /// Ed25519 key.
         pub fn new(
-            secret_key: &[u8; PK_SECRET_LEN],
+            secret_key: &keygen::Curve25519Secret,
             signing_key: &[u8; SIGNING_PUBLIC_LEN],
         ) -> Self {
             PrivcountDecryptor {
-                secret_key: *secret_key,
+                secret_key: *secret_key.as_bytes(),
                 signing_key: *signing_key,
+                cipher: Cipher::Aes,
             }
         }
-    }
 
-    impl Decryptor for PrivcountDecryptor {
-        fn decrypt(&self, inp: &[u8], tweak: &[u8]) -> Option<Vec<u8>> {
+        /// Check whether the MAC on `inp` is correct for this key and the
+        /// tweak value `tweak`, without decrypting anything.
+        ///
+        /// This lets an auditor check the integrity of stored reports
+        /// without handling their plaintext.  If this function returns
+        /// true, then `decrypt()` will succeed on the same inputs.
+        ///
+        /// This only handles single-recipient messages: it always returns
+        /// false for the output of `encrypt_to_many()`.
+        pub fn verify_mac(&self, inp: &[u8], tweak: &[u8]) -> bool {
+            match self.derive_and_check(inp, tweak, &[]) {
+                Some((mut keys, _)) => {
+                    wipe(&mut keys);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        /// Helper: Parse `inp`, derive the keys for it, and check its MAC
+        /// against the associated data `aad`.
+        ///
+        /// On success, returns the derived key material, and the encrypted
+        /// portion of `inp`.
+        fn derive_and_check<'a>(
+            &self,
+            inp: &'a [u8],
+            tweak: &[u8],
+            aad: &[u8],
+        ) -> Option<([u8; S_KEY_LEN + S_IV_LEN + MAC_KEY_LEN], &'a [u8])> {
             // Try to unserialize the input.
-            if inp.len() < PK_PUBLIC_LEN + SALT_LEN + MAC_OUT_LEN {
+            if inp.len() < ENCRYPTED_OVERHEAD || self.is_padded(inp[0]).is_none() {
                 return None;
             }
-            let enc_len = inp.len() - PK_PUBLIC_LEN - SALT_LEN - MAC_OUT_LEN;
-            let (pubkey, rest) = inp.split_at(PK_PUBLIC_LEN);
+            let enc_len = inp.len() - ENCRYPTED_OVERHEAD;
+            let (pubkey, rest) = inp[VERSION_LEN..].split_at(PK_PUBLIC_LEN);
             let (salt, rest) = rest.split_at(SALT_LEN);
             let (enc, mac_received) = rest.split_at(enc_len);
             debug_assert_eq!(mac_received.len(), MAC_OUT_LEN);
 
-            let shared_key = curve25519(&self.secret_key, pubkey);
+            let mut keys = self.message_keys(pubkey, salt, tweak);
+            let mac_key = &keys[S_KEY_LEN + S_IV_LEN..];
+            debug_assert_eq!(mac_key.len(), MAC_KEY_LEN);
+
+            let mut mac_computed = [0; MAC_OUT_LEN];
+            let mac_covered_portion = &inp[0..inp.len() - MAC_OUT_LEN];
+            if mac(mac_key, mac_covered_portion, aad, &mut mac_computed).is_err()
+                || !fixed_time_eq(&mac_computed, mac_received)
+            {
+                wipe(&mut keys);
+                return None;
+            }
+
+            Some((keys, enc))
+        }
+
+        /// Helper: if we can decrypt messages with the version byte
+        /// `version`, return whether they're padded.  Otherwise return
+        /// None.
+        fn is_padded(&self, version: u8) -> Option<bool> {
+            if version == self.cipher.version() {
+                Some(false)
+            } else if version == PADDED_FORMAT_VERSION && self.cipher == Cipher::Aes {
+                Some(true)
+            } else {
+                None
+            }
+        }
+
+        /// Helper: derive the keys for a message whose ephemeral public
+        /// key is `pubkey` and whose salt is `salt`.
+        fn message_keys(&self, pubkey: &[u8], salt: &[u8], tweak: &[u8]) -> [u8; KEYS_LEN] {
+            let mut shared_key = curve25519(&self.secret_key, pubkey);
             let mut secret_input = Vec::new();
             secret_input.extend_from_slice(&shared_key);
             secret_input.extend_from_slice(&self.signing_key);
 
-            let mut keys = [0; S_KEY_LEN + S_IV_LEN + MAC_KEY_LEN];
-            generate_keys(&secret_input, tweak, &salt, &mut keys);
-            let (enc_key, rest) = keys.split_at(S_KEY_LEN);
-            let (enc_iv, mac_key) = rest.split_at(S_IV_LEN);
-            debug_assert_eq!(mac_key.len(), MAC_KEY_LEN);
+            let mut keys = [0; KEYS_LEN];
+            derive_keys(&secret_input, tweak, salt, &mut keys);
+            wipe(&mut shared_key);
+            wipe(&mut secret_input);
+            keys
+        }
+
+        /// Begin decrypting a message using the tweak value `tweak`, when
+        /// the ciphertext will arrive in chunks.
+        ///
+        /// Pass the ciphertext to the returned DecryptingBuffer in as
+        /// many chunks as you like, then call its `finalize()` method to
+        /// get the message.  The result is the same as `decrypt()` would
+        /// give for the whole ciphertext.  (Like `verify_mac()`, this
+        /// doesn't handle the output of `encrypt_to_many()`.)
+        pub fn decrypt_buffer(&self, tweak: &[u8]) -> DecryptingBuffer<'_> {
+            DecryptingBuffer {
+                decryptor: self,
+                tweak: tweak.to_vec(),
+                state: None,
+                pending: Vec::new(),
+                plaintext: Vec::new(),
+                bad_version: false,
+                padded: false,
+            }
+        }
+    }
+
+    /// A decryption in progress, created by
+    /// `PrivcountDecryptor::decrypt_buffer()`.
+    ///
+    /// The MAC comes at the end of the ciphertext, so we can't release
+    /// any of the message until we've seen all of it.  Instead, we decrypt
+    /// each chunk into a buffer as soon as it arrives, and only return
+    /// the buffer once the MAC checks out.  That way we never need to
+    /// hold the whole ciphertext in memory.
+    pub struct DecryptingBuffer<'a> {
+        /// The decryptor whose key we're using.
+        decryptor: &'a PrivcountDecryptor,
+        /// The tweak value for this message.
+        tweak: Vec<u8>,
+        /// Once we've seen the header, the cipher for the message, and
+        /// the MAC of everything we've processed so far.
+        state: Option<(Box<dyn SynchronousStreamCipher + 'static>, sha3::Sha3)>,
+        /// Input that we haven't processed yet: either an incomplete
+        /// header, or the last MAC_OUT_LEN bytes we've seen, which might
+        /// be the MAC.
+        pending: Vec<u8>,
+        /// The message we've decrypted so far.
+        plaintext: Vec<u8>,
+        /// True if the header had a version we don't recognize.
+        bad_version: bool,
+        /// True if the header says that the plaintext is padded.
+        padded: bool,
+    }
+
+    impl<'a> DecryptingBuffer<'a> {
+        /// Process the next `chunk` of the ciphertext.
+        pub fn update(&mut self, chunk: &[u8]) {
+            if self.bad_version {
+                return;
+            }
+            self.pending.extend_from_slice(chunk);
+
+            if self.state.is_none() {
+                if self.pending.len() < HEADER_LEN {
+                    return;
+                }
+                match self.decryptor.is_padded(self.pending[0]) {
+                    Some(padded) => self.padded = padded,
+                    None => {
+                        self.bad_version = true;
+                        return;
+                    }
+                }
+                let mut keys = {
+                    let (pubkey, salt) =
+                        self.pending[VERSION_LEN..HEADER_LEN].split_at(PK_PUBLIC_LEN);
+                    self.decryptor.message_keys(pubkey, salt, &self.tweak)
+                };
+                let (cipher, mut mac) = symmetric_state(self.decryptor.cipher, &keys);
+                wipe(&mut keys);
+                mac.input(&self.pending[..HEADER_LEN]);
+                self.state = Some((cipher, mac));
+                self.pending.drain(..HEADER_LEN);
+            }
+
+            if self.pending.len() > MAC_OUT_LEN {
+                let n = self.pending.len() - MAC_OUT_LEN;
+                let (cipher, mac) = self.state.as_mut().expect("missing cipher state");
+                mac.input(&self.pending[..n]);
+                let start = self.plaintext.len();
+                self.plaintext.resize(start + n, 0);
+                cipher.process(&self.pending[..n], &mut self.plaintext[start..]);
+                self.pending.drain(..n);
+            }
+        }
+
+        /// Finish the decryption, and return the message if the
+        /// ciphertext was well-formed and its MAC was correct.
+        pub fn finalize(self) -> Option<Vec<u8>> {
+            self.finalize_with_aad(&[])
+        }
+
+        /// As `finalize()`, but also check the associated data `aad`, as
+        /// in `Decryptor::decrypt_with_aad()`.
+        pub fn finalize_with_aad(mut self, aad: &[u8]) -> Option<Vec<u8>> {
+            let ok = match self.state.take() {
+                Some((_, mac)) if !self.bad_version && self.pending.len() == MAC_OUT_LEN => {
+                    let mut mac_computed = [0; MAC_OUT_LEN];
+                    mac_finish(mac, aad, &mut mac_computed).is_ok()
+                        && fixed_time_eq(&mac_computed, &self.pending)
+                }
+                _ => false,
+            };
+            if ok && self.padded {
+                if strip_padding(&mut self.plaintext) {
+                    Some(self.plaintext)
+                } else {
+                    None
+                }
+            } else if ok {
+                Some(self.plaintext)
+            } else {
+                wipe(&mut self.plaintext);
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    impl Zeroize for PrivcountDecryptor {
+        fn zeroize(&mut self) {
+            self.secret_key.zeroize();
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    impl Drop for PrivcountDecryptor {
+        fn drop(&mut self) {
+            self.zeroize();
+        }
+    }
+
+    impl PrivcountDecryptor {
+        /// Decrypt the value `inp` using the tweak value `tweak`, putting
+        /// the message in `out`.
+        ///
+        /// This is the same as `decrypt()`, except that it reuses the
+        /// memory in `out` rather than allocating a new Vec for every
+        /// message.  On success, `out` holds exactly the message, and we
+        /// return true.  On failure, `out` is empty, and we return false:
+        /// as with `decrypt()`, we don't say why.
+        pub fn decrypt_into(&self, inp: &[u8], tweak: &[u8], out: &mut Vec<u8>) -> bool {
+            self.decrypt_into_with_aad(inp, tweak, &[], out)
+        }
+
+        /// As `decrypt_into()`, but also check that the input was
+        /// encrypted with the associated data `aad`.
+        pub fn decrypt_into_with_aad(
+            &self,
+            inp: &[u8],
+            tweak: &[u8],
+            aad: &[u8],
+            out: &mut Vec<u8>,
+        ) -> bool {
+            let ok = match inp.first() {
+                Some(&MULTI_FORMAT_VERSION) if self.cipher == Cipher::Aes => {
+                    self.decrypt_multi(inp, tweak, aad, out)
+                }
+                _ => self.decrypt_single(inp, tweak, aad, out),
+            };
+            if !ok {
+                wipe(out);
+                out.clear();
+            }
+            ok
+        }
+
+        /// Helper: decrypt a message for a single recipient into `out`.
+        fn decrypt_single(&self, inp: &[u8], tweak: &[u8], aad: &[u8], out: &mut Vec<u8>) -> bool {
+            let (mut keys, enc) = match self.derive_and_check(inp, tweak, aad) {
+                Some(checked) => checked,
+                None => return false,
+            };
+            let (mut cipher, _) = symmetric_state(self.cipher, &keys);
+            wipe(&mut keys);
+            out.clear();
+            out.resize(enc.len(), 0);
+            cipher.process(enc, out);
+
+            inp[0] != PADDED_FORMAT_VERSION || strip_padding(out)
+        }
+
+        /// Helper: decrypt our part of a message from `encrypt_to_many()`
+        /// into `out`.
+        fn decrypt_multi(&self, inp: &[u8], tweak: &[u8], aad: &[u8], out: &mut Vec<u8>) -> bool {
+            if inp.len() < MULTI_ENCRYPTED_OVERHEAD {
+                return false;
+            }
+            let (wrapped, body) = inp[VERSION_LEN..].split_at(WRAPPED_KEY_LEN);
+            let (mac_covered_portion, mac_received) = body.split_at(body.len() - MAC_OUT_LEN);
+            let (salt, enc) = mac_covered_portion.split_at(SALT_LEN);
+
+            // Borrow `out` to hold the data key.
+            if !self.decrypt_single(wrapped, tweak, MULTI_RECIPIENT_CONST, out) {
+                return false;
+            }
+            let mut keys = [0; KEYS_LEN];
+            derive_keys(out, tweak, salt, &mut keys);
+            wipe(out);
+            out.clear();
+            let (mut cipher, mut mac) = symmetric_state(Cipher::Aes, &keys);
+            wipe(&mut keys);
 
             let mut mac_computed = [0; MAC_OUT_LEN];
-            let mac_covered_portion = &inp[0..inp.len() - MAC_OUT_LEN];
-            if mac(&mac_key, &mac_covered_portion, &mut mac_computed).is_err() {
-                return None;
+            mac.input(&[MULTI_FORMAT_VERSION]);
+            mac.input(mac_covered_portion);
+            if mac_finish(mac, aad, &mut mac_computed).is_err()
+                || !fixed_time_eq(&mac_computed, mac_received)
+            {
+                return false;
             }
-            if !fixed_time_eq(&mac_computed, &mac_received) {
-                return None;
+
+            out.resize(enc.len(), 0);
+            cipher.process(enc, out);
+            true
+        }
+    }
+
+    impl Decryptor for PrivcountDecryptor {
+        fn decrypt_with_aad(&self, inp: &[u8], tweak: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+            let mut out = Vec::new();
+            if self.decrypt_into_with_aad(inp, tweak, aad, &mut out) {
+                Some(out)
+            } else {
+                None
             }
+        }
+    }
 
-            let mut cipher = aes::ctr(aes::KeySize::KeySize256, enc_key, enc_iv);
-            let mut result = Vec::new();
-            result.resize(enc.len(), 0);
-            cipher.process(&enc, &mut result);
+    /// An Encryptor that implements the hybrid scheme used by privcount,
+    /// with ChaCha20 in place of AES-CTR.
+    ///
+    /// Its ciphertexts can only be decrypted with a `ChaChaDecryptor`.
+    pub struct ChaChaEncryptor(PrivcountEncryptor);
 
-            Some(result)
+    impl ChaChaEncryptor {
+        /// Create a new encryptor from a public key and a signing key.
+        pub fn new(key: &[u8; PK_PUBLIC_LEN], signing_key: &[u8; SIGNING_PUBLIC_LEN]) -> Self {
+            let mut inner = PrivcountEncryptor::new(key, signing_key);
+            inner.cipher = Cipher::ChaCha;
+            ChaChaEncryptor(inner)
+        }
+
+        /// Return the public key used by this encryptor.
+        pub fn key(&self) -> &[u8; PK_PUBLIC_LEN] {
+            self.0.key()
+        }
+    }
+
+    impl Encryptor for ChaChaEncryptor {
+        fn encrypt_with_aad(
+            &self,
+            inp: &[u8],
+            tweak: &[u8],
+            aad: &[u8],
+            rng: &mut dyn Rng,
+        ) -> Result<Vec<u8>, EncryptError> {
+            self.0.encrypt_with_aad(inp, tweak, aad, rng)
+        }
+    }
+
+    /// A Decryptor for the ciphertexts from a `ChaChaEncryptor`.
+    pub struct ChaChaDecryptor(PrivcountDecryptor);
+
+    impl ChaChaDecryptor {
+        /// Construct a new decryptor from a curve25519 private key and a
+        /// public Ed25519 key.
+        pub fn new(
+            secret_key: &keygen::Curve25519Secret,
+            signing_key: &[u8; SIGNING_PUBLIC_LEN],
+        ) -> Self {
+            let mut inner = PrivcountDecryptor::new(secret_key, signing_key);
+            inner.cipher = Cipher::ChaCha;
+            ChaChaDecryptor(inner)
+        }
+
+        /// As `PrivcountDecryptor::decrypt_into()`.
+        pub fn decrypt_into(&self, inp: &[u8], tweak: &[u8], out: &mut Vec<u8>) -> bool {
+            self.0.decrypt_into(inp, tweak, out)
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    impl Zeroize for ChaChaDecryptor {
+        fn zeroize(&mut self) {
+            self.0.zeroize();
+        }
+    }
+
+    impl Decryptor for ChaChaDecryptor {
+        fn decrypt_with_aad(&self, inp: &[u8], tweak: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+            self.0.decrypt_with_aad(inp, tweak, aad)
         }
     }
 }
@@ -270,8 +1277,49 @@ pub mod hybrid {
 mod tests {
     use super::hybrid::*;
     use super::*;
-    use crypto::curve25519::curve25519_base;
     use rand::os::OsRng;
+    use std::iter::FromIterator;
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_decryptor() {
+        use zeroize::Zeroize;
+        let mut rng = OsRng::new().unwrap();
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let encrypted = PrivcountEncryptor::new(&pk, &[3; 32])
+            .encrypt(b"hello", b"tweak", &mut rng)
+            .unwrap();
+
+        let mut decryptor = PrivcountDecryptor::new(&sk, &[3; 32]);
+        assert!(decryptor.decrypt(&encrypted, b"tweak").is_some());
+        decryptor.zeroize();
+        assert!(decryptor.decrypt(&encrypted, b"tweak").is_none());
+        drop(decryptor);
+    }
+
+    #[test]
+    fn secret_type() {
+        use crypto::curve25519::curve25519_base;
+        let mut rng = OsRng::new().unwrap();
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let raw = *sk.as_bytes();
+        assert_eq!(sk.public(), curve25519_base(&raw));
+        assert_eq!(keygen::Curve25519Secret::from_bytes(raw), sk);
+        assert_eq!(format!("{:?}", sk), "Curve25519Secret(..)");
+    }
+
+    #[test]
+    fn ratchet() {
+        let mut rng = OsRng::new().unwrap();
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let next = keygen::ratchet_curve25519_seckey(&sk, 0);
+        assert_ne!(sk, next);
+        assert_eq!(next, keygen::ratchet_curve25519_seckey(&sk, 0));
+        assert_ne!(next, keygen::ratchet_curve25519_seckey(&sk, 1));
+        assert_eq!(next.as_bytes()[0] & 7, 0);
+        assert_eq!(next.as_bytes()[31] & 0xc0, 64);
+    }
 
     #[test]
     fn roundtrip() {
@@ -280,8 +1328,8 @@ mod tests {
         let tweak = b"Said you've been threatened by gangsters.";
         let mut rng = OsRng::new().unwrap();
         let signing_key = [17; SIGNING_PUBLIC_LEN]; // not actually used to sign
-        let sk = super::keygen::curve25519_seckey_gen(&mut rng);
-        let pk = curve25519_base(&sk);
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
         let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
         let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
 
@@ -311,8 +1359,8 @@ mod tests {
 
         let mut rng = OsRng::new().unwrap();
         let signing_key = [62; SIGNING_PUBLIC_LEN]; // not actually used to sign
-        let sk = super::keygen::curve25519_seckey_gen(&mut rng);
-        let pk = curve25519_base(&sk);
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
         let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
 
         let enc1 = encryptor.encrypt(&msg[..], &tweak[..], &mut rng);
@@ -320,4 +1368,564 @@ mod tests {
         assert_ne!(enc1, enc2);
     }
 
+    #[test]
+    fn fixed_salt() {
+        let msg = b"Don't let them fool you with dope and cocaine";
+        let tweak = b"Ain't no harm to feel your own pain";
+        let salt = [7; SALT_LEN];
+
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [99; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let enc1 = encryptor
+            .encrypt_with_salt(&msg[..], &tweak[..], &salt, &mut rng)
+            .unwrap();
+        let enc2 = encryptor
+            .encrypt_with_salt(&msg[..], &tweak[..], &salt, &mut rng)
+            .unwrap();
+        // Same salt, different ephemeral keys: the outputs still differ.
+        assert_ne!(enc1, enc2);
+        let salt_pos = VERSION_LEN + PK_PUBLIC_LEN;
+        assert_eq!(&enc1[salt_pos..salt_pos + SALT_LEN], &salt[..]);
+        assert_eq!(&enc2[salt_pos..salt_pos + SALT_LEN], &salt[..]);
+
+        let expected = Some(msg.to_vec());
+        assert_eq!(decryptor.decrypt(&enc1, &tweak[..]), expected);
+        assert_eq!(decryptor.decrypt(&enc2, &tweak[..]), expected);
+    }
+
+    #[test]
+    fn verify_mac_only() {
+        let msg = b"Nothing's gonna stop us now";
+        let tweak = b"tweak for the MAC check";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [3; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let mut encrypted = encryptor.encrypt(&msg[..], &tweak[..], &mut rng).unwrap();
+        assert!(decryptor.verify_mac(&encrypted, &tweak[..]));
+        assert!(!decryptor.verify_mac(&encrypted, b"some other tweak"));
+        assert!(!decryptor.verify_mac(&encrypted[..10], &tweak[..]));
+
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        assert!(!decryptor.verify_mac(&encrypted, &tweak[..]));
+        assert_eq!(decryptor.decrypt(&encrypted, &tweak[..]), None);
+    }
+
+    #[test]
+    fn derived_is_deterministic() {
+        let msg = b"The same thing twice";
+        let tweak = b"deterministic tweak";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [5; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let enc1 = encryptor
+            .encrypt_deterministic_derived(&msg[..], &tweak[..])
+            .unwrap();
+        let enc2 = encryptor
+            .encrypt_deterministic_derived(&msg[..], &tweak[..])
+            .unwrap();
+        assert_eq!(enc1, enc2);
+        assert_eq!(decryptor.decrypt(&enc1, &tweak[..]), Some(msg.to_vec()));
+
+        let other_tweak = encryptor
+            .encrypt_deterministic_derived(&msg[..], b"another tweak")
+            .unwrap();
+        let other_msg = encryptor
+            .encrypt_deterministic_derived(b"Something else", &tweak[..])
+            .unwrap();
+        let pk_range = VERSION_LEN..VERSION_LEN + PK_PUBLIC_LEN;
+        assert_ne!(&enc1[pk_range.clone()], &other_tweak[pk_range.clone()]);
+        assert_ne!(&enc1[pk_range.clone()], &other_msg[pk_range]);
+    }
+
+    #[test]
+    fn version_byte() {
+        let msg = b"Versioned";
+        let tweak = b"tweak for the version check";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [8; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let encrypted = encryptor.encrypt(&msg[..], &tweak[..], &mut rng).unwrap();
+        assert_eq!(encrypted[0], FORMAT_VERSION);
+        let derived = encryptor
+            .encrypt_deterministic_derived(&msg[..], &tweak[..])
+            .unwrap();
+        assert_eq!(derived[0], FORMAT_VERSION);
+
+        // Flipping the version byte breaks decryption and the MAC, and
+        // so does any version we don't recognize, including the old 0x01.
+        for v in [FORMAT_VERSION ^ 1, 0x00, 0x01, 0x02, 0xff].iter() {
+            let mut modified = encrypted.clone();
+            modified[0] = *v;
+            assert_eq!(decryptor.decrypt(&modified, &tweak[..]), None);
+            assert!(!decryptor.verify_mac(&modified, &tweak[..]));
+        }
+        assert_eq!(
+            decryptor.decrypt(&encrypted, &tweak[..]),
+            Some(msg.to_vec())
+        );
+    }
+
+    #[test]
+    fn associated_data() {
+        let msg = b"Counter values go here";
+        let tweak = b"tweak for the AAD check";
+        let aad = b"counter 7, epoch 12";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [11; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let encrypted = encryptor
+            .encrypt_with_aad(&msg[..], &tweak[..], &aad[..], &mut rng)
+            .unwrap();
+        assert_eq!(encrypted.len() - msg.len(), ENCRYPTED_OVERHEAD);
+        let expected = Some(msg.to_vec());
+        assert_eq!(
+            decryptor.decrypt_with_aad(&encrypted, &tweak[..], &aad[..]),
+            expected
+        );
+
+        // Any other AAD fails, including none at all.
+        assert_eq!(
+            decryptor.decrypt_with_aad(&encrypted, &tweak[..], b"counter 7, epoch 13"),
+            None
+        );
+        assert_eq!(decryptor.decrypt(&encrypted, &tweak[..]), None);
+        assert!(!decryptor.verify_mac(&encrypted, &tweak[..]));
+
+        // The plain methods are the same as using empty AAD.
+        let plain = encryptor.encrypt(&msg[..], &tweak[..], &mut rng).unwrap();
+        assert_eq!(
+            decryptor.decrypt_with_aad(&plain, &tweak[..], &[]),
+            expected
+        );
+        assert_eq!(
+            decryptor.decrypt_with_aad(&plain, &tweak[..], &aad[..]),
+            None
+        );
+
+        // Moving bytes between the ciphertext and the AAD doesn't work.
+        let last = encrypted.len() - MAC_OUT_LEN - 1;
+        let mut shifted_aad = vec![encrypted[last]];
+        shifted_aad.extend_from_slice(&aad[..]);
+        let mut shifted = encrypted[..last].to_vec();
+        shifted.extend_from_slice(&encrypted[last + 1..]);
+        assert_eq!(
+            decryptor.decrypt_with_aad(&shifted, &tweak[..], &shifted_aad),
+            None
+        );
+    }
+
+    #[test]
+    fn chunked() {
+        use rand::{ChaChaRng, SeedableRng};
+        let msg = Vec::from_iter((0..100_000u32).map(|i| (i * 7 + i / 251) as u8));
+        let tweak = b"tweak for chunked encryption";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [21; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let seed: &[u32] = &[1, 2, 3, 4];
+        let one_shot = encryptor
+            .encrypt(&msg, &tweak[..], &mut ChaChaRng::from_seed(seed))
+            .unwrap();
+        let mut chunked = Vec::new();
+        {
+            let mut w = encryptor
+                .encrypt_writer(&tweak[..], &mut ChaChaRng::from_seed(seed), &mut chunked)
+                .unwrap();
+            for chunk in msg.chunks(4093) {
+                w.update(chunk).unwrap();
+            }
+            w.finalize().unwrap();
+        }
+        assert_eq!(chunked, one_shot);
+
+        // Decrypt in chunks of several sizes, including ones smaller
+        // than the header and the MAC.
+        for size in [1, 7, 40, 1000, one_shot.len()].iter() {
+            let mut b = decryptor.decrypt_buffer(&tweak[..]);
+            for chunk in one_shot.chunks(*size) {
+                b.update(chunk);
+            }
+            assert_eq!(b.finalize(), Some(msg.clone()));
+        }
+
+        // Bad tweaks, truncation, corruption, versions, and AAD are all
+        // caught.
+        let decrypt_chunked = |ct: &[u8], tweak: &[u8], aad: &[u8]| {
+            let mut b = decryptor.decrypt_buffer(tweak);
+            for chunk in ct.chunks(333) {
+                b.update(chunk);
+            }
+            b.finalize_with_aad(aad)
+        };
+        assert_eq!(decrypt_chunked(&one_shot, b"wrong tweak", &[]), None);
+        assert_eq!(decrypt_chunked(&one_shot[..50], &tweak[..], &[]), None);
+        assert_eq!(decrypt_chunked(&one_shot[..10], &tweak[..], &[]), None);
+        assert_eq!(decrypt_chunked(&[], &tweak[..], &[]), None);
+        let mut bad = one_shot.clone();
+        bad[5000] ^= 0x80;
+        assert_eq!(decrypt_chunked(&bad, &tweak[..], &[]), None);
+        let mut bad = one_shot.clone();
+        bad[0] = 0x02;
+        assert_eq!(decrypt_chunked(&bad, &tweak[..], &[]), None);
+        assert_eq!(decrypt_chunked(&one_shot, &tweak[..], b"aad"), None);
+
+        let with_aad = encryptor
+            .encrypt_with_aad(&msg[..1000], &tweak[..], b"aad", &mut rng)
+            .unwrap();
+        assert_eq!(
+            decrypt_chunked(&with_aad, &tweak[..], b"aad"),
+            Some(msg[..1000].to_vec())
+        );
+    }
+
+    #[test]
+    fn any_rng() {
+        use rand::{ChaChaRng, Rng, SeedableRng};
+
+        /// Encrypt with a generic RNG, the way client code does.
+        fn encrypt_generic<R: Rng>(enc: &PrivcountEncryptor, rng: &mut R) -> Vec<u8> {
+            enc.encrypt(b"generic", b"tweak", rng).unwrap()
+        }
+
+        let mut os_rng = OsRng::new().unwrap();
+        let mut seeded = ChaChaRng::from_seed(&[9, 9, 9][..]);
+        let signing_key = [4; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut seeded);
+        let pk = sk.public();
+        let encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let rngs: Vec<Box<dyn Rng>> = vec![
+            Box::new(OsRng::new().unwrap()),
+            Box::new(ChaChaRng::from_seed(&[1, 2][..])),
+        ];
+        for mut rng in rngs {
+            let enc = encryptor.encrypt(b"msg", b"tweak", &mut *rng).unwrap();
+            assert_eq!(decryptor.decrypt(&enc, b"tweak"), Some(b"msg".to_vec()));
+        }
+        for enc in [
+            encrypt_generic(&encryptor, &mut os_rng),
+            encrypt_generic(&encryptor, &mut seeded),
+        ]
+        .iter()
+        {
+            assert_eq!(decryptor.decrypt(enc, b"tweak"), Some(b"generic".to_vec()));
+        }
+
+        // A seeded RNG gives the same keys and ciphertexts every time.
+        let mut a = ChaChaRng::from_seed(&[5][..]);
+        let mut b = ChaChaRng::from_seed(&[5][..]);
+        assert_eq!(
+            keygen::curve25519_seckey_gen(&mut a),
+            keygen::curve25519_seckey_gen(&mut b)
+        );
+        assert_eq!(
+            encrypt_generic(&encryptor, &mut a),
+            encrypt_generic(&encryptor, &mut b)
+        );
+    }
+
+    #[test]
+    fn kdf_known_answer() {
+        let secret_input = Vec::from_iter(0..64u8);
+        let salt = [0xa5; SALT_LEN];
+        let mut keys = [0; S_KEY_LEN + S_IV_LEN + MAC_KEY_LEN];
+        derive_keys(&secret_input, b"privctr-kdf-test", &salt, &mut keys);
+        let hex = String::from_iter(keys.iter().map(|b| format!("{:02x}", b)));
+        assert_eq!(
+            hex,
+            "c7f482e199f4cd0c394da668e54fb2cd584e7bd65ddab7cc4a204b75ff6184d8\
+             6266de269183430d80a54a1d6eeb2860\
+             084cb123b562a4988253d2c9f2adbbbdfb4ec8eb7696e3a4e8be6ccc42508774"
+        );
+
+        // The output is a prefix of SHAKE256, so asking for less gives a
+        // prefix of the same answer.
+        let mut short = [0; 16];
+        derive_keys(&secret_input, b"privctr-kdf-test", &salt, &mut short);
+        assert_eq!(&short[..], &keys[..16]);
+
+        let mut other = [0; 16];
+        derive_keys(&secret_input, b"privctr-kdf-test2", &salt, &mut other);
+        assert_ne!(other, short);
+    }
+
+    #[test]
+    fn chacha() {
+        let msg = b"No AES hardware here";
+        let tweak = b"tweak for chacha";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [13; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let aes_enc = PrivcountEncryptor::new(&pk, &signing_key);
+        let aes_dec = PrivcountDecryptor::new(&sk, &signing_key);
+        let chacha_enc = ChaChaEncryptor::new(&pk, &signing_key);
+        let chacha_dec = ChaChaDecryptor::new(&sk, &signing_key);
+        assert_eq!(chacha_enc.key(), &pk);
+
+        let encrypted = chacha_enc.encrypt(&msg[..], &tweak[..], &mut rng).unwrap();
+        assert_eq!(encrypted[0], CHACHA_FORMAT_VERSION);
+        assert_eq!(encrypted.len() - msg.len(), ENCRYPTED_OVERHEAD);
+        assert_eq!(
+            chacha_dec.decrypt(&encrypted, &tweak[..]),
+            Some(msg.to_vec())
+        );
+        assert_eq!(chacha_dec.decrypt(&encrypted, b"wrong tweak"), None);
+
+        let with_aad = chacha_enc
+            .encrypt_with_aad(&msg[..], &tweak[..], b"aad", &mut rng)
+            .unwrap();
+        assert_eq!(
+            chacha_dec.decrypt_with_aad(&with_aad, &tweak[..], b"aad"),
+            Some(msg.to_vec())
+        );
+        assert_eq!(chacha_dec.decrypt(&with_aad, &tweak[..]), None);
+
+        // Neither scheme will decrypt the other's ciphertexts, even if we
+        // change the version byte to match.
+        let aes_encrypted = aes_enc.encrypt(&msg[..], &tweak[..], &mut rng).unwrap();
+        assert_eq!(chacha_dec.decrypt(&aes_encrypted, &tweak[..]), None);
+        assert_eq!(aes_dec.decrypt(&encrypted, &tweak[..]), None);
+        let mut relabeled = aes_encrypted.clone();
+        relabeled[0] = CHACHA_FORMAT_VERSION;
+        assert_eq!(chacha_dec.decrypt(&relabeled, &tweak[..]), None);
+        let mut relabeled = encrypted.clone();
+        relabeled[0] = FORMAT_VERSION;
+        assert_eq!(aes_dec.decrypt(&relabeled, &tweak[..]), None);
+    }
+
+    #[test]
+    fn many_recipients() {
+        let msg = b"One seed for everybody";
+        let tweak = b"tweak for many recipients";
+        let mut rng = OsRng::new().unwrap();
+        let keys = Vec::from_iter((0..4).map(|i| {
+            let sk = keygen::curve25519_seckey_gen(&mut rng);
+            let pk = sk.public();
+            (sk, pk, [i as u8; SIGNING_PUBLIC_LEN])
+        }));
+        let recipients = Vec::from_iter(keys[..3].iter().map(|&(_, pk, sig)| (pk, sig)));
+
+        let multi = encrypt_to_many(&recipients, &msg[..], &tweak[..], &mut rng).unwrap();
+        assert_eq!(multi.n_recipients(), 3);
+        assert_eq!(multi.for_recipient(3), None);
+        for (i, &(ref sk, _, sig)) in keys[..3].iter().enumerate() {
+            let mine = multi.for_recipient(i).unwrap();
+            assert_eq!(mine[0], MULTI_FORMAT_VERSION);
+            assert_eq!(mine.len() - msg.len(), MULTI_ENCRYPTED_OVERHEAD);
+            assert!(mine.ends_with(multi.body()));
+            let decryptor = PrivcountDecryptor::new(sk, &sig);
+            assert_eq!(decryptor.decrypt(&mine, &tweak[..]), Some(msg.to_vec()));
+            assert_eq!(decryptor.decrypt(&mine, b"wrong tweak"), None);
+            assert_eq!(decryptor.decrypt_with_aad(&mine, &tweak[..], b"aad"), None);
+
+            // Nobody else can decrypt this recipient's part: not another
+            // recipient, not a non-recipient, and not the ChaCha scheme.
+            let j = (i + 1) % 3;
+            let other = PrivcountDecryptor::new(&keys[j].0, &keys[j].2);
+            assert_eq!(other.decrypt(&mine, &tweak[..]), None);
+            let outsider = PrivcountDecryptor::new(&keys[3].0, &keys[3].2);
+            assert_eq!(outsider.decrypt(&mine, &tweak[..]), None);
+            let chacha = ChaChaDecryptor::new(sk, &sig);
+            assert_eq!(chacha.decrypt(&mine, &tweak[..]), None);
+
+            // Tampering with the shared body is caught.
+            let mut bad = mine.clone();
+            let last = bad.len() - MAC_OUT_LEN - 1;
+            bad[last] ^= 1;
+            assert_eq!(decryptor.decrypt(&bad, &tweak[..]), None);
+            assert_eq!(decryptor.decrypt(&mine[..50], &tweak[..]), None);
+        }
+
+        // A wrapped key isn't an ordinary message.
+        let (ref sk, _, sig) = keys[0];
+        let mine = multi.for_recipient(0).unwrap();
+        let wrapped = &mine[VERSION_LEN..mine.len() - multi.body().len()];
+        let decryptor = PrivcountDecryptor::new(sk, &sig);
+        assert_eq!(decryptor.decrypt(wrapped, &tweak[..]), None);
+
+        let none = encrypt_to_many(&[], &msg[..], &tweak[..], &mut rng).unwrap();
+        assert_eq!(none.n_recipients(), 0);
+    }
+
+    #[test]
+    fn decrypt_into() {
+        let tweak = b"tweak for buffers";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [16; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let mut encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+
+        let msg = Vec::from_iter(0..=255u8);
+        let mut ciphertexts = Vec::new();
+        for len in [100, 0, 255, 3].iter() {
+            ciphertexts.push(
+                encryptor
+                    .encrypt(&msg[..*len], &tweak[..], &mut rng)
+                    .unwrap(),
+            );
+        }
+        encryptor.set_padding(32).unwrap();
+        ciphertexts.push(encryptor.encrypt(&msg[..40], &tweak[..], &mut rng).unwrap());
+        let multi =
+            encrypt_to_many(&[(pk, signing_key)], &msg[..77], &tweak[..], &mut rng).unwrap();
+        ciphertexts.push(multi.for_recipient(0).unwrap());
+        let mut bad = ciphertexts[0].clone();
+        bad[60] ^= 1;
+        ciphertexts.push(bad);
+        ciphertexts.push(Vec::new());
+
+        let mut out = Vec::new();
+        for enc in ciphertexts.iter() {
+            let expected = decryptor.decrypt(enc, &tweak[..]);
+            let ok = decryptor.decrypt_into(enc, &tweak[..], &mut out);
+            assert_eq!(ok, expected.is_some());
+            assert_eq!(out, expected.unwrap_or_default());
+
+            // Fail, and make sure we cleared the buffer.
+            assert!(!decryptor.decrypt_into(enc, b"wrong tweak", &mut out));
+            assert!(out.is_empty());
+        }
+
+        let aad_enc = encryptor
+            .encrypt_with_aad(&msg[..5], &tweak[..], b"aad", &mut rng)
+            .unwrap();
+        assert!(decryptor.decrypt_into_with_aad(&aad_enc, &tweak[..], b"aad", &mut out));
+        assert_eq!(out, msg[..5].to_vec());
+        assert!(!decryptor.decrypt_into(&aad_enc, &tweak[..], &mut out));
+
+        let chacha_enc = ChaChaEncryptor::new(&pk, &signing_key)
+            .encrypt(&msg[..9], &tweak[..], &mut rng)
+            .unwrap();
+        let chacha = ChaChaDecryptor::new(&sk, &signing_key);
+        assert!(chacha.decrypt_into(&chacha_enc, &tweak[..], &mut out));
+        assert_eq!(out, msg[..9].to_vec());
+    }
+
+    #[test]
+    fn padding() {
+        use rand::{ChaChaRng, SeedableRng};
+        let tweak = b"tweak for padding";
+        let mut rng = OsRng::new().unwrap();
+        let signing_key = [15; SIGNING_PUBLIC_LEN]; // not actually used to sign
+        let sk = keygen::curve25519_seckey_gen(&mut rng);
+        let pk = sk.public();
+        let mut encryptor = PrivcountEncryptor::new(&pk, &signing_key);
+        let decryptor = PrivcountDecryptor::new(&sk, &signing_key);
+        assert!(encryptor.set_padding(0).is_err());
+        encryptor.set_padding(64).unwrap();
+
+        // Up to 56 bytes fit in one block, with the 8-byte length.
+        let msg = Vec::from_iter(1..=200u8);
+        for &(len, blocks) in [
+            (0, 1),
+            (1, 1),
+            (30, 1),
+            (56, 1),
+            (57, 2),
+            (120, 2),
+            (121, 3),
+        ]
+        .iter()
+        {
+            let enc = encryptor
+                .encrypt(&msg[..len], &tweak[..], &mut rng)
+                .unwrap();
+            assert_eq!(enc[0], PADDED_FORMAT_VERSION);
+            assert_eq!(enc.len(), ENCRYPTED_OVERHEAD + 64 * blocks);
+            assert_eq!(
+                decryptor.decrypt(&enc, &tweak[..]),
+                Some(msg[..len].to_vec())
+            );
+            assert!(decryptor.verify_mac(&enc, &tweak[..]));
+
+            let mut b = decryptor.decrypt_buffer(&tweak[..]);
+            for chunk in enc.chunks(10) {
+                b.update(chunk);
+            }
+            assert_eq!(b.finalize(), Some(msg[..len].to_vec()));
+        }
+
+        // The padding and the length are authenticated.
+        let enc = encryptor.encrypt(&msg[..30], &tweak[..], &mut rng).unwrap();
+        for pos in [
+            VERSION_LEN + PK_PUBLIC_LEN + SALT_LEN + 40,
+            enc.len() - MAC_OUT_LEN - 1,
+        ]
+        .iter()
+        {
+            let mut bad = enc.clone();
+            bad[*pos] ^= 1;
+            assert_eq!(decryptor.decrypt(&bad, &tweak[..]), None);
+        }
+        // The ChaCha decryptor doesn't accept padded AES messages.
+        let chacha = ChaChaDecryptor::new(&sk, &signing_key);
+        assert_eq!(chacha.decrypt(&enc, &tweak[..]), None);
+
+        // Chunked encryption pads the same way.
+        let seed: &[u32] = &[7, 7];
+        let one_shot = encryptor
+            .encrypt(&msg[..77], &tweak[..], &mut ChaChaRng::from_seed(seed))
+            .unwrap();
+        let mut chunked = Vec::new();
+        {
+            let mut w = encryptor
+                .encrypt_writer(&tweak[..], &mut ChaChaRng::from_seed(seed), &mut chunked)
+                .unwrap();
+            for chunk in msg[..77].chunks(20) {
+                w.update(chunk).unwrap();
+            }
+            w.finalize().unwrap();
+        }
+        assert_eq!(chunked, one_shot);
+    }
+
+    #[test]
+    fn encrypt_error() {
+        fn is_send_sync<T: Send + Sync>() {}
+        is_send_sync::<EncryptError>();
+
+        assert_eq!(EncryptError::MacTooLong.to_string(), "MAC output too long.");
+        assert_eq!(
+            EncryptError::RngFailure.to_string(),
+            "Random number generator failed."
+        );
+        assert_eq!(
+            EncryptError::WriteFailed.to_string(),
+            "Couldn't write ciphertext."
+        );
+        let e: &'static str = EncryptError::MacTooLong.into();
+        assert_eq!(e, "MAC output too long.");
+        let boxed: Box<dyn Error + Send + Sync> = Box::new(EncryptError::RngFailure);
+        assert_eq!(boxed.to_string(), "Random number generator failed.");
+    }
 }
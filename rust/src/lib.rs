@@ -24,27 +24,47 @@
 //    missing_copy_implementations,
 //    missing_debug_implementations,
 //)]
-// Enable i128 on nightly
-#![cfg_attr(feature = "nightly", feature(i128_type))]
 
 extern crate byteorder;
 extern crate crypto;
+#[cfg(feature = "getrandom")]
+extern crate getrandom;
 extern crate num;
 extern crate rand;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
 
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
 
+#[cfg(all(test, feature = "serde"))]
+extern crate bincode;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
 #[cfg(test)]
 extern crate primal;
 
 mod math;
 pub mod shamir;
+pub use math::hash_to_nonzero_fe;
 pub use math::FE;
 pub use math::PRIME_ORDER;
+pub use math::{field_bits, field_modulus, max_secret};
 
 pub mod client;
 pub mod data;
 pub mod encrypt;
+pub mod noise;
+pub mod protocol;
+pub mod rng;
 pub mod server;
+pub mod wide;
+
+#[cfg(feature = "testing")]
+pub mod testing;
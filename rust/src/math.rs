@@ -5,13 +5,19 @@
 //!
 //! Certain constraints are placed on A and B, see below.
 
+use byteorder::{ByteOrder, LittleEndian, NetworkEndian};
+use crypto::digest::Digest;
+use crypto::sha3::Sha3;
 use num::traits::{Num, One, Zero};
 use rand::{Rand, Rng};
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std;
-use std::cmp::{Eq, PartialEq};
-use std::convert::From;
+use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
+use std::convert::{From, TryFrom};
 use std::fmt::{self, Display, Formatter, LowerHex, UpperHex};
 use std::hash::{Hash, Hasher};
+use std::iter::{Product, Sum};
 use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
 use std::ops::{AddAssign, DivAssign, MulAssign, RemAssign, SubAssign};
 
@@ -31,6 +37,24 @@ const OFFSET_BIT: u64 = 30;
 /// All the arithmetic on field elements prime is done modulo this value.
 pub const PRIME_ORDER: u64 = (1 << N_BITS) - (1 << OFFSET_BIT) - 1;
 
+/// Return the order of the prime field: the same as `PRIME_ORDER`.
+pub const fn field_modulus() -> u64 {
+    PRIME_ORDER
+}
+
+/// Return the number of bits needed to hold any field element.
+pub const fn field_bits() -> u32 {
+    N_BITS as u32
+}
+
+/// Return the largest value that a field element can hold.
+///
+/// Counter totals (and any other secrets) that exceed this value wrap
+/// around modulo `PRIME_ORDER`.
+pub const fn max_secret() -> u64 {
+    PRIME_ORDER - 1
+}
+
 // There are some constraints on those constants, as described here:
 //
 // 2^N_BITS - (2^OFFSET_BIT + 1) must be prime; we do all of our
@@ -40,6 +64,92 @@ pub const PRIME_ORDER: u64 = (1 << N_BITS) - (1 << OFFSET_BIT) - 1;
 // Our recip() implementation requires OFFSET_BIT != 2.
 // Choose N_BITS even, and no more than 64 - 2, and no less than 34.
 
+/// Compute `a * b mod m`, without overflow.
+const fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+/// Compute `base ^ exp mod m`.
+const fn pow_mod(base: u64, exp: u64, m: u64) -> u64 {
+    let mut result = 1 % m;
+    let mut base = base % m;
+    let mut exp = exp;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Return true if `n` is prime.
+///
+/// This is a Miller-Rabin test using the first twelve primes as bases,
+/// which is deterministic for every u64.  It's a `const fn` so that we can
+/// check our modulus at compile time.
+const fn is_prime(n: u64) -> bool {
+    const BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    let mut i = 0;
+    while i < BASES.len() {
+        if n.is_multiple_of(BASES[i]) {
+            return n == BASES[i];
+        }
+        i += 1;
+    }
+
+    // Write n - 1 as d * 2^s, with d odd.
+    let mut d = n - 1;
+    let mut s = 0;
+    while d.is_multiple_of(2) {
+        d /= 2;
+        s += 1;
+    }
+
+    let mut i = 0;
+    while i < BASES.len() {
+        let mut x = pow_mod(BASES[i], d, n);
+        i += 1;
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        let mut r = 1;
+        let mut witness = true;
+        while r < s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                witness = false;
+                break;
+            }
+            r += 1;
+        }
+        if witness {
+            return false;
+        }
+    }
+    true
+}
+
+// Everything in this module depends on PRIME_ORDER being prime: if it
+// isn't, some elements have no inverse, and recip() gives wrong answers.
+// So if somebody changes N_BITS or OFFSET_BIT to a bad combination, refuse
+// to compile.
+const _: () = assert!(
+    is_prime(PRIME_ORDER),
+    "N_BITS and OFFSET_BIT must make 2^N_BITS - 2^OFFSET_BIT - 1 prime."
+);
+
+// FE::sqrt() relies on PRIME_ORDER being 3 modulo 4.  (It always is, since
+// N_BITS and OFFSET_BIT are at least 2.)
+const _: () = assert!(
+    PRIME_ORDER % 4 == 3,
+    "FE::sqrt() needs PRIME_ORDER = 3 mod 4."
+);
+
 // READ THIS TO UNDERSTAND:
 //
 //  We represent values mod P in four different u64-based forms.
@@ -77,7 +187,7 @@ const MAX_EXCESS: u64 = (1 << REMAINING_BITS) - 1;
 const FE_VAL_MAX: u64 = FULL_BITS_MASK + (MAX_EXCESS << OFFSET_BIT) + MAX_EXCESS;
 
 /// A member of the prime field used for Privcount.
-#[derive(Debug, Copy, Clone)]
+#[derive(Copy, Clone)]
 pub struct FE {
     // This value is stored in a bit-reduced form: it will be in range
     // 0..FE_VAL_MAX.  It is equivalent modulo PRIME_ORDER to the
@@ -101,6 +211,25 @@ fn bit_reduce_once(v: u64) -> u64 {
     result
 }
 
+/// Given the product of two values in range 0..FE_VAL_MAX, return the
+/// FE that it represents.
+fn reduce_product(product: u128) -> FE {
+    // Here's our bit-reduction algorithm once again, this time
+    // taking a u128 as input.
+    fn bit_reduce_once_128(v: u128) -> u128 {
+        let low = v & (FULL_BITS_MASK as u128);
+        let high = v >> N_BITS;
+        low + (high << OFFSET_BIT) + high
+    }
+
+    // The first two bit-reduces are sufficient to make the produce
+    // less than 2^64.  Once we've done that, FE::new can accept it
+    // (and do another bit-reduction).
+    let result = bit_reduce_once_128(bit_reduce_once_128(product));
+    debug_assert!(result < (1 << 64));
+    FE::new(result as u64)
+}
+
 /// Subtract PRIME_ORDER from `v` if it is greater than PRIME_ORDER.
 ///
 /// In other words, this function returns
@@ -117,6 +246,55 @@ fn reduce_by_p(v: u64) -> u64 {
     (mask & v) | ((!mask) & difference)
 }
 
+/// Return 1 if `v` is zero, and 0 otherwise, without branching on `v`.
+fn ct_u64_is_zero(v: u64) -> u64 {
+    // If v is nonzero, then at least one of v and -v has its high bit set.
+    let nonzero = (v | v.wrapping_neg()) >> 63;
+    nonzero ^ 1
+}
+
+/// Hash `bytes` to a nonzero field element.
+///
+/// We compute SHA3-256 over `bytes` and a 32-bit big-endian counter
+/// (starting at zero), and reduce the first 8 bytes of the digest modulo
+/// PRIME_ORDER.  If the result is zero, we increment the counter and try
+/// again.
+///
+/// Only one value in PRIME_ORDER is zero, so we'll need to retry with
+/// probability about 2^-62: in practice, never.  But since X coordinates
+/// must never be zero, we'd rather guarantee it than hope for it.
+///
+/// # Examples
+/// ```
+/// use privcount::hash_to_nonzero_fe;
+/// let x = hash_to_nonzero_fe(b"some key");
+/// assert_ne!(x.value(), 0);
+/// assert_eq!(x, hash_to_nonzero_fe(b"some key"));
+/// ```
+pub fn hash_to_nonzero_fe(bytes: &[u8]) -> FE {
+    let mut counter: u32 = 0;
+    loop {
+        let v = hash_to_fe_with_counter(bytes, counter);
+        if !v.is_zero() {
+            return v;
+        }
+        counter += 1;
+    }
+}
+
+/// Helper: hash `bytes` and `counter` to a field element, which may be
+/// zero.
+fn hash_to_fe_with_counter(bytes: &[u8], counter: u32) -> FE {
+    let mut counter_bytes = [0; 4];
+    NetworkEndian::write_u32(&mut counter_bytes, counter);
+    let mut d = Sha3::sha3_256();
+    d.input(bytes);
+    d.input(&counter_bytes);
+    let mut digest = [0; 32];
+    d.result(&mut digest);
+    FE::new(NetworkEndian::read_u64(&digest[..8]))
+}
+
 impl FE {
     /// Construct a new FE value.
     ///
@@ -135,18 +313,22 @@ impl FE {
     pub fn new(v: u64) -> Self {
         // This bit_reduce_once ensures that the value is in range
         // 0..FE_VAL_MAX.
-        FE {
+        let result = FE {
             val: bit_reduce_once(v),
-        }
+        };
+        result.assert_invariant();
+        result
     }
     /// Construct a random FE from a random u64, discarding biased values.
     ///
     /// Construct a new FE value from a u64 value, such that if the
     /// inputs to this function are uniform random u64s, then all of
     /// the non-None outputs of this function are uniform random FEs.
-    //
+    ///
     /// The implementation should try to return a non-None value for
-    /// the majority of inputs.
+    /// the majority of inputs.  When it returns None, the caller should
+    /// draw another u64 and try again, as `Seed::counter_masks()` does:
+    /// reducing the input instead would bias the output.
     ///
     /// # Examples
     /// ```
@@ -186,12 +368,84 @@ impl FE {
     /// ```
     pub fn from_reduced(v: u64) -> Option<Self> {
         if v < PRIME_ORDER {
-            Some(FE { val: v })
+            let result = FE { val: v };
+            result.assert_invariant();
+            Some(result)
         } else {
             None
         }
     }
 
+    /// Return the 8-byte big-endian encoding of this FE's value.
+    ///
+    /// This is the wire encoding for field elements: the PrivCount
+    /// protocol always uses big-endian.  See `from_bytes()`.
+    pub fn to_bytes(self) -> [u8; 8] {
+        let mut result = [0; 8];
+        NetworkEndian::write_u64(&mut result, self.value());
+        result
+    }
+
+    /// Decode an FE from its 8-byte big-endian encoding, as produced by
+    /// `to_bytes()`.
+    ///
+    /// Only the canonical encoding is accepted: the encoded value must be
+    /// in range 0..PRIME_ORDER-1.  If it is not, return None.  Use this
+    /// function (or `from_reduced`) for any value that arrives from an
+    /// untrusted source.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use privcount::{FE, PRIME_ORDER};
+    ///
+    /// assert_eq!(FE::from_bytes(&[0, 0, 0, 0, 0, 0, 1, 0]), Some(FE::new(256)));
+    /// assert_eq!(FE::from_bytes(&FE::new(99).to_bytes()), Some(FE::new(99)));
+    ///
+    /// // Not canonical.
+    /// assert_eq!(FE::from_bytes(&(PRIME_ORDER + 1).to_be_bytes()), None);
+    /// ```
+    pub fn from_bytes(b: &[u8; 8]) -> Option<Self> {
+        FE::from_reduced(NetworkEndian::read_u64(b))
+    }
+
+    /// Return the 8-byte little-endian encoding of this FE's value.
+    ///
+    /// This is *not* the PrivCount wire encoding (that's `to_bytes()`):
+    /// it's here for interoperating with other systems and test vectors
+    /// that use little-endian.
+    pub fn to_le_bytes(self) -> [u8; 8] {
+        let mut result = [0; 8];
+        LittleEndian::write_u64(&mut result, self.value());
+        result
+    }
+
+    /// Decode an FE from its 8-byte little-endian encoding, as produced
+    /// by `to_le_bytes()`.
+    ///
+    /// As with `from_bytes()`, the encoded value must be in range
+    /// 0..PRIME_ORDER-1.  If it is not, return None.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use privcount::FE;
+    ///
+    /// assert_eq!(FE::from_le_bytes(&[0, 1, 0, 0, 0, 0, 0, 0]), Some(FE::new(256)));
+    /// assert_eq!(FE::from_le_bytes(&[0xff; 8]), None);
+    /// ```
+    pub fn from_le_bytes(b: &[u8; 8]) -> Option<Self> {
+        FE::from_reduced(LittleEndian::read_u64(b))
+    }
+
+    /// Check the invariant that every FE's stored value is bit-reduced
+    /// at least once, so that the arithmetic functions can't overflow.
+    ///
+    /// This is only checked in debug builds.
+    fn assert_invariant(&self) {
+        debug_assert!(self.val <= FE_VAL_MAX);
+    }
+
     /// Construct a new FE value from a u32 input.
     ///
     /// Because every u32 is smaller than the PRIME_ORDER, this
@@ -211,47 +465,266 @@ impl FE {
         reduce_by_p(bit_reduce_once(self.val))
     }
 
-    /// Compute the reciprocal of this value.
+    /// Return the value of this FE as a signed integer, in range
+    /// -(PRIME_ORDER-1)/2 ..= (PRIME_ORDER-1)/2.
+    ///
+    /// This is the inverse of `FE::from(i64)` for values in that range.
+    /// It's useful for totals that might be negative, like noise.
+    pub fn signed_value(self) -> i64 {
+        let v = self.value();
+        if v > PRIME_ORDER / 2 {
+            -((PRIME_ORDER - v) as i64)
+        } else {
+            v as i64
+        }
+    }
+
+    /// Return true if this FE is zero, without any data-dependent branches
+    /// or comparisons.
+    ///
+    /// Use this, rather than `is_zero()` or `== FE::zero()`, when testing
+    /// secret values: those are convenient, but they compare with `==`,
+    /// which the compiler is free to turn into a branch.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::{FE, PRIME_ORDER};
+    /// assert!(FE::new(PRIME_ORDER).ct_is_zero());
+    /// assert!(!FE::new(1).ct_is_zero());
+    /// ```
+    pub fn ct_is_zero(self) -> bool {
+        ct_u64_is_zero(self.value()) == 1
+    }
+
+    /// Return true if this value is equal to `other`, without branching
+    /// on either value.
+    ///
+    /// Use this, rather than `==`, when comparing secret values.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::{FE, PRIME_ORDER};
+    /// assert!(FE::new(PRIME_ORDER + 3).ct_eq(FE::new(3)));
+    /// assert!(!FE::new(3).ct_eq(FE::new(4)));
+    /// ```
+    pub fn ct_eq(self, other: FE) -> bool {
+        ct_u64_is_zero(self.value() ^ other.value()) == 1
+    }
+
+    /// Return `a` if `choice` is true, and `b` otherwise, without
+    /// branching on `choice`.
+    ///
+    /// This only hides `choice` from timing if the caller computed it
+    /// without branching too: `ct_eq()` and `ct_is_zero()` are good
+    /// sources.  A `choice` that came from `==` or `if` has probably
+    /// leaked already.
     ///
     /// # Examples
     /// ```
     /// use privcount::FE;
-    /// let n = FE::new(1337);
-    /// let m = n.recip();
-    /// assert_eq!(FE::new(1), n * m);
+    /// let (a, b) = (FE::new(3), FE::new(4));
+    /// assert_eq!(FE::conditional_select(a, b, true), a);
+    /// assert_eq!(FE::conditional_select(a, b, a.ct_eq(b)), b);
     /// ```
-    pub fn recip(self) -> Self {
-        debug_assert_ne!(self, FE::new_raw(0));
+    pub fn conditional_select(a: FE, b: FE, choice: bool) -> FE {
+        // mask is all ones if choice is true, and zero if not.
+        let mask = 0u64.wrapping_sub(choice as u64);
+        FE {
+            val: (a.val & mask) | (b.val & !mask),
+        }
+    }
 
-        // To compute the reciprical, we need to compute
-        // self^E where E = (PRIME_ORDER-2).
-        //
-        // Since OFFSET_BIT != 2, E has every bit in (0..N_BITS-1)
-        // set, except for bits 1 and OFFSET_BIT.  In other words,
-        // it looks like 0b11111111..11101111..01
+    /// Return this value plus itself.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::FE;
+    /// assert_eq!(FE::new(21).double(), FE::new(42));
+    /// ```
+    pub fn double(self) -> Self {
+        // self.val is at most FE_VAL_MAX < 2^63, so this can't overflow.
+        // The FE::new call will bit-reduce the result.
+        FE::new(self.val << 1)
+    }
 
-        // Simple version of exponention-by-squaring algorithm.
+    /// Return this value times itself.
+    ///
+    /// Our multiplication is a single 64x64 multiply, so there are no
+    /// cross terms to save: this is the same work as `self * self`, but
+    /// it says what we mean.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::FE;
+    /// assert_eq!(FE::new(12).square(), FE::new(144));
+    /// ```
+    pub fn square(self) -> Self {
+        let v = self.val as u128;
+        reduce_product(v * v)
+    }
+
+    /// Compute this value raised to the power `exp`.
+    ///
+    /// Any value (including zero) raised to the power 0 is 1.
+    ///
+    /// This function always does the same sequence of multiplications,
+    /// regardless of `self` or `exp`, and uses masking rather than
+    /// branches to decide which products to keep.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::{FE, PRIME_ORDER};
+    /// assert_eq!(FE::new(3).pow(4), FE::new(81));
+    /// assert_eq!(FE::new(0).pow(0), FE::new(1));
+    /// assert_eq!(FE::new(1337).pow(PRIME_ORDER - 1), FE::new(1));
+    /// ```
+    pub fn pow(self, exp: u64) -> Self {
+        // Exponentiation by squaring, from the low bit up.
         let mut x = self;
         let mut y = FE::new(1);
+        for bit in 0..64 {
+            let product = x * y;
+            // mask is all ones if this bit of exp is set, and zero if not.
+            let mask = 0u64.wrapping_sub((exp >> bit) & 1);
+            y = FE {
+                val: (product.val & mask) | (y.val & !mask),
+            };
+            x = x.square();
+        }
+        y
+    }
+
+    /// Return true if this value is a square (that is, a quadratic
+    /// residue, or zero).
+    ///
+    /// This uses Euler's criterion, and is cheaper than `sqrt()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::FE;
+    /// assert!(FE::new(9).is_square());
+    /// assert!(FE::new(0).is_square());
+    /// assert!(!(-FE::new(1)).is_square());
+    /// ```
+    pub fn is_square(self) -> bool {
+        self.legendre() != -1
+    }
+
+    /// Return the Legendre symbol of this value: 0 for zero, 1 for a
+    /// nonzero square, and -1 for a non-square.
+    ///
+    /// This does not branch on the value.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::FE;
+    /// assert_eq!(FE::new(0).legendre(), 0);
+    /// assert_eq!(FE::new(4).legendre(), 1);
+    /// assert_eq!((-FE::new(4)).legendre(), -1);
+    /// ```
+    pub fn legendre(self) -> i32 {
+        // By Euler's criterion, self^((p-1)/2) is 1 for nonzero squares,
+        // p-1 for non-squares, and 0 for zero.
+        let euler = self.pow((PRIME_ORDER - 1) / 2).value();
+        let is_zero = ct_u64_is_zero(euler) as i32;
+        let is_minus_one = ct_u64_is_zero(euler ^ (PRIME_ORDER - 1)) as i32;
+        1 - is_zero - 2 * is_minus_one
+    }
+
+    /// Return a square root of this value, or None if it has none.
+    ///
+    /// Every nonzero square has two roots, `r` and `-r`; we return the
+    /// one whose `value()` is smaller.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::FE;
+    /// assert_eq!(FE::new(9).sqrt(), Some(FE::new(3)));
+    /// assert_eq!((-FE::new(1)).sqrt(), None);
+    /// ```
+    pub fn sqrt(self) -> Option<Self> {
+        // In general, we would need Tonelli-Shanks here, and we'd have to
+        // factor the powers of two out of p-1.  But since p = 3 mod 4,
+        // p-1 = 2 * (odd), and Tonelli-Shanks collapses to a single
+        // exponentiation: if self is a square, then
+        // self^((p+1)/4) is one of its roots.
+        let root = self.pow((PRIME_ORDER + 1) / 4);
+        if root.square() != self {
+            return None;
+        }
+        let other = -root;
+        if other.value() < root.value() {
+            Some(other)
+        } else {
+            Some(root)
+        }
+    }
+
+    /// Replace every nonzero element of `xs` with its reciprocal.
+    ///
+    /// Zero has no reciprocal, so zero elements are left unchanged.
+    ///
+    /// This uses Montgomery's trick: it computes one `recip()` for the
+    /// whole slice, and three multiplications for each element, which is
+    /// much faster than calling `recip()` on each element.  Like
+    /// `recip()`, it does not branch on the values in `xs`.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::FE;
+    /// let mut xs = [FE::new(2), FE::new(0), FE::new(1337)];
+    /// FE::batch_recip(&mut xs);
+    /// assert_eq!(xs, [FE::new(2).recip(), FE::new(0), FE::new(1337).recip()]);
+    /// ```
+    pub fn batch_recip(xs: &mut [FE]) {
+        // prefix[i] is the product of all the (nonzero) elements before i.
+        let mut prefix = Vec::with_capacity(xs.len());
+        let mut product = FE::new(1);
+        for x in xs.iter() {
+            prefix.push(product);
+            product *= x.one_if_zero();
+        }
 
-        // Bit 0 is set.
-        y = x * y;
-        x = x * x;
-        // Bit 1 is clear.
-        x = x * x;
-        // Bits 2 through offset_bit-1 are set.
-        for _ in 2..(OFFSET_BIT) {
-            y = x * y;
-            x = x * x;
+        // At the top of each iteration, inv is the reciprocal of the
+        // product of all the (nonzero) elements up to and including the
+        // current one.  Multiplying by prefix cancels out everything but
+        // the current element; multiplying by the current element drops
+        // it from inv for the next iteration.
+        let mut inv = product.recip();
+        for (x, before) in xs.iter_mut().zip(prefix).rev() {
+            let x_or_one = x.one_if_zero();
+            let recip = inv * before;
+            inv *= x_or_one;
+            // Keep zero elements as they are.
+            let mask = 0u64.wrapping_sub(ct_u64_is_zero(x.value()));
+            x.val = (x.val & mask) | (recip.val & !mask);
         }
-        // OFFSET_BIT is clear
-        x = x * x;
-        // OFFSET_BIT + 1 through N_BITS-2
-        for _ in (OFFSET_BIT + 1)..(N_BITS - 1) {
-            y = x * y;
-            x = x * x;
+    }
+
+    /// Helper: return this value if it is nonzero, and 1 if it is zero,
+    /// without branching.
+    fn one_if_zero(self) -> Self {
+        let mask = 0u64.wrapping_sub(ct_u64_is_zero(self.value()));
+        FE {
+            val: (1 & mask) | (self.val & !mask),
         }
-        x * y
+    }
+
+    /// Compute the reciprocal of this value.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::FE;
+    /// let n = FE::new(1337);
+    /// let m = n.recip();
+    /// assert_eq!(FE::new(1), n * m);
+    /// ```
+    pub fn recip(self) -> Self {
+        debug_assert_ne!(self, FE::new_raw(0));
+
+        // By Fermat's little theorem, self^(PRIME_ORDER-1) == 1, so
+        // self^(PRIME_ORDER-2) is the reciprocal.
+        self.pow(PRIME_ORDER - 2)
     }
 }
 
@@ -272,6 +745,36 @@ impl From<u32> for FE {
     }
 }
 
+// Signed conversions use the standard embedding of the integers into the
+// field: a negative value -v maps to PRIME_ORDER - v.
+impl From<i32> for FE {
+    fn from(v: i32) -> FE {
+        FE::from(v as i64)
+    }
+}
+impl From<i64> for FE {
+    fn from(v: i64) -> FE {
+        // unsigned_abs() is correct even for i64::MIN.
+        let magnitude = FE::new(v.unsigned_abs());
+        if v < 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+}
+
+/// Convert a u64 to an FE, if it is in range 0..PRIME_ORDER-1.
+///
+/// Unlike `FE::new()`, this does not reduce its input: use it when the
+/// input should already be a field element, and anything else means that
+/// it was corrupted.
+impl TryFrom<u64> for FE {
+    type Error = &'static str;
+    fn try_from(v: u64) -> Result<FE, Self::Error> {
+        FE::from_reduced(v).ok_or("Value is larger than PRIME_ORDER - 1.")
+    }
+}
 impl From<FE> for u64 {
     fn from(v: FE) -> u64 {
         v.value()
@@ -281,6 +784,10 @@ impl Zero for FE {
     fn zero() -> FE {
         FE::new_raw(0)
     }
+    /// Return true if this FE is zero.
+    ///
+    /// This is not guaranteed to run in constant time: for secret values,
+    /// use `FE::ct_is_zero()`.
     fn is_zero(&self) -> bool {
         self.value() == 0
     }
@@ -316,6 +823,8 @@ impl Sub for FE {
     }
 }
 
+/// Note that `==` is not guaranteed to run in constant time: to compare
+/// secret values, use `FE::ct_eq()`.
 impl PartialEq for FE {
     fn eq(&self, rhs: &Self) -> bool {
         self.value() == rhs.value()
@@ -323,6 +832,22 @@ impl PartialEq for FE {
 }
 impl Eq for FE {}
 
+/// FEs are ordered by their canonical values, in range 0..PRIME_ORDER-1.
+///
+/// This ordering exists so that FEs can be sorted, or used as keys in a
+/// BTreeMap.  It has no algebraic meaning (the field isn't ordered), and
+/// it isn't a constant-time comparison.
+impl PartialOrd for FE {
+    fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+impl Ord for FE {
+    fn cmp(&self, rhs: &Self) -> Ordering {
+        self.value().cmp(&rhs.value())
+    }
+}
+
 impl Hash for FE {
     fn hash<H: Hasher>(&self, hasher: &mut H) {
         hasher.write_u64(self.value())
@@ -346,6 +871,14 @@ impl Display for FE {
     }
 }
 
+// Debug output shows the canonical value, so that FEs that are equal
+// always look the same.
+impl fmt::Debug for FE {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_tuple("FE").field(&self.value()).finish()
+    }
+}
+
 impl UpperHex for FE {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         UpperHex::fmt(&self.value(), f)
@@ -364,118 +897,57 @@ impl Default for FE {
     }
 }
 
-impl Mul for FE {
-    type Output = Self;
+/// With the `serde` feature, FEs serialize as their canonical values, in
+/// range 0..PRIME_ORDER-1.
+#[cfg(feature = "serde")]
+impl Serialize for FE {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.value())
+    }
+}
 
-    // Implement multiplication. We have separate implementations
-    // depending on whether we have u128 support or not.
+/// With the `serde` feature, FEs deserialize from u64s, which must be in
+/// range 0..PRIME_ORDER-1.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for FE {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let v = u64::deserialize(deserializer)?;
+        FE::from_reduced(v).ok_or_else(|| de::Error::custom("Field element is not reduced."))
+    }
+}
 
-    #[cfg(not(feature = "nightly"))]
-    fn mul(self, rhs: Self) -> Self {
-        // This is the version of multiplication without u128 support:
-        // we have to use a few 32x32 multiplies rather than a full
-        // 64x64 multiply.
-
-        // We require below that HALF_BITS <= 31
-        const HALF_BITS: u64 = N_BITS / 2;
-        const MASK: u64 = (1 << HALF_BITS) - 1;
-
-        // Reduce the input values an extra time, so that they are in
-        // range 0..FULL_BITS_MASK.  This ensures that we can split
-        // each into a high and low set of HALF_BITS-length values,
-        // with no bits left over.
-        let a = bit_reduce_once(self.val);
-        let b = bit_reduce_once(rhs.val);
-
-        // The 'lo' values and 'hi' values here are in range 0..MASK.
-        let a_lo = a & MASK;
-        let a_hi = a >> HALF_BITS;
-        let b_lo = b & MASK;
-        let b_hi = b >> HALF_BITS;
-
-        // Okay, it's Karatsuba multiplication time.
-        // We want to compute
-        //        (a_lo+Base*a_hi) * (b_lo+Base*b_hi)
-        //      = z0 + z1 * Base + z2 * Base * Base
-        // for Base == 2^HALF_BITS.
-        //  So we compute z0 = a_lo * b_lo,
-        //                z2 = a_hi * b_hi,
-        //                z1 = (a_lo + a_hi) * (b_lo + b_hi) - z0 - z2
-        //
-        // Let's show this doesn't overflow.  We will have:
-        //   z0 <= MASK^2.
-        //   z2 <= MASK^2
-        //   a_lo + a_hi <= 2 * MASK == 2^(HALF_BITS+1) - 2
-        //   b_lo + b_hi <= 2 * MASK == 2^(HALF_BITS+1) - 2
-        // And given P = (a_lo + a_hi) * (b_lo + b_hi),
-        //   P <= 2^(2*HALF_BITS + 2) - 2^(HALF_BITS+2) + 4
-        // Since HALF_BITS <= 31, we have:
-        //   P <= 2^64 - 2^34 + 4,
-        // so, the multiplication in z1 does not overflow.
-        let z0 = a_lo * b_lo;
-        let z2 = a_hi * b_hi;
-        let z1 = (a_lo + a_hi) * (b_lo + b_hi) - z0 - z2;
-
-        // Split z1 into high and low parts.
-        let z1_lo = z1 & MASK;
-        let z1_hi = z1 >> HALF_BITS;
-
-        // The product is now given by:
-        //      z0 + Base * z1 + Base2^2 * z2 ==
-        //      (z0 + z1_lo * Base) + (z2 + z1_hi) * Base^2
-
-        // (XXX Do we really need to bit-reduce z1_lo and z1_hi here?)
-
-        // z0 is already < 2^N_BITS, so we don't need to bit-reduce it before
-        // we add.
-        let product_low = z0 + bit_reduce_once(z1_lo << HALF_BITS);
-        // z2 is already < 2^N_BITS, so we don't need to bit-reduce it before
-        // we add.  z1_hi is less than 2^HALF_BITS.
-        let product_hi = bit_reduce_once(z2 + bit_reduce_once(z1_hi));
-
-        // Now the product is product_low + 2^N_BITS * product_hi.
-        // Modulo PRIME_GROUP, we have 2^N_BITS === 2^OFFSET_BIT + 1,
-        // so the final product is:
-        //     product_low + product_hi + product_hi << OFFSET_BIT.
-        //
-        // Computing product_hi << OFFSET_BIT could overflow, so we're
-        // splitting it again.
-
-        const NB: u64 = N_BITS - OFFSET_BIT;
-        let product_hi_lo = product_hi & ((1 << NB) - 1);
-        let product_hi_hi = product_hi >> NB;
-
-        // There are some redundant reductions here, maybe? XXXX
-        FE::new(product_low)
-            + FE::new(product_hi)
-            + FE::new(product_hi_lo << OFFSET_BIT)
-            + FE::new(product_hi_hi)
-            + FE::new(product_hi_hi << OFFSET_BIT)
-    }
-
-    #[cfg(feature = "nightly")]
-    fn mul(self, rhs: Self) -> Self {
-        // If we have u128, we are much happier.
+impl Sum for FE {
+    fn sum<I: Iterator<Item = FE>>(iter: I) -> FE {
+        iter.fold(FE::zero(), |acc, x| acc + x)
+    }
+}
 
-        // Here's our bit-reduction algorithm once again, this time
-        // taking a u128 as input.
-        fn bit_reduce_once_128(v: u128) -> u128 {
-            let low = v & (FULL_BITS_MASK as u128);
-            let high = v >> N_BITS;
-            low + (high << OFFSET_BIT) + high
-        }
+impl<'a> Sum<&'a FE> for FE {
+    fn sum<I: Iterator<Item = &'a FE>>(iter: I) -> FE {
+        iter.fold(FE::zero(), |acc, x| acc + *x)
+    }
+}
 
+impl Product for FE {
+    fn product<I: Iterator<Item = FE>>(iter: I) -> FE {
+        iter.fold(FE::one(), |acc, x| acc * x)
+    }
+}
+
+impl<'a> Product<&'a FE> for FE {
+    fn product<I: Iterator<Item = &'a FE>>(iter: I) -> FE {
+        iter.fold(FE::one(), |acc, x| acc * *x)
+    }
+}
+
+impl Mul for FE {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
         // This product is is most FE_VAL_MAX^2; FE_VAL_MAX is less
         // than 2^63, so this value is less than 2^126.  No overflow
         // here!
-        let product = (self.val as u128) * (rhs.val as u128);
-
-        // The first two bit-reduces are sufficient to make the produce
-        // less than 2^64.  Once we've done that, FE::new can accept it
-        // (and do another bit-reduction).
-        let result = bit_reduce_once_128(bit_reduce_once_128(product));
-        debug_assert!(result < (1 << 64));
-        FE::new(result as u64)
+        reduce_product((self.val as u128) * (rhs.val as u128))
     }
 }
 
@@ -561,6 +1033,33 @@ impl<'a> Rem<&'a FE> for FE {
     }
 }
 
+// Multiplication by integer scalars: the scalar is taken modulo
+// PRIME_ORDER, as with `FE::new()`.
+impl Mul<u32> for FE {
+    type Output = FE;
+    fn mul(self, rhs: u32) -> FE {
+        self * FE::from(rhs)
+    }
+}
+impl Mul<u64> for FE {
+    type Output = FE;
+    fn mul(self, rhs: u64) -> FE {
+        self * FE::new(rhs)
+    }
+}
+impl Mul<FE> for u32 {
+    type Output = FE;
+    fn mul(self, rhs: FE) -> FE {
+        rhs * self
+    }
+}
+impl Mul<FE> for u64 {
+    type Output = FE;
+    fn mul(self, rhs: FE) -> FE {
+        rhs * self
+    }
+}
+
 impl Num for FE {
     type FromStrRadixErr = &'static str;
     fn from_str_radix(s: &str, radix: u32) -> Result<Self, &'static str> {
@@ -590,11 +1089,165 @@ mod tests {
         assert!(OFFSET_BIT != 2);
     }
     #[test]
+    fn public_constants() {
+        assert_eq!(field_modulus(), PRIME_ORDER);
+        assert_eq!(max_secret(), field_modulus() - 1);
+        assert_eq!(FE::new(max_secret()).value(), max_secret());
+        assert!(field_modulus() < 1 << field_bits());
+        assert!(field_modulus() > 1 << (field_bits() - 1));
+    }
+    #[test]
     fn prime_is_prime() {
         use primal;
         assert!(primal::is_prime(PRIME_ORDER));
     }
     #[test]
+    fn const_is_prime() {
+        use primal;
+        for n in 0..2000 {
+            assert_eq!(is_prime(n), primal::is_prime(n), "{}", n);
+        }
+        for n in (PRIME_ORDER - 2000)..(PRIME_ORDER + 2000) {
+            assert_eq!(is_prime(n), primal::is_prime(n), "{}", n);
+        }
+        // Some strong pseudoprimes to small bases.
+        assert!(!is_prime(3215031751));
+        assert!(!is_prime(3825123056546413051));
+        assert!(is_prime(u64::MAX - 58));
+        // Other choices of OFFSET_BIT.
+        for b in 1..N_BITS {
+            let p = (1 << N_BITS) - (1 << b) - 1;
+            assert_eq!(is_prime(p), primal::is_prime(p), "{}", b);
+        }
+    }
+    #[test]
+    fn hash_to_fe() {
+        let a = hash_to_nonzero_fe(b"hello");
+        assert_eq!(a, hash_to_nonzero_fe(b"hello"));
+        assert_eq!(a, hash_to_fe_with_counter(b"hello", 0));
+        assert_ne!(a, hash_to_nonzero_fe(b"hellp"));
+        assert_ne!(a, hash_to_fe_with_counter(b"hello", 1));
+        assert!(!hash_to_nonzero_fe(b"").is_zero());
+    }
+    #[test]
+    fn unbiased_bounds() {
+        assert_eq!(
+            FE::from_u64_unbiased(PRIME_ORDER - 1),
+            Some(FE::new(PRIME_ORDER - 1))
+        );
+        assert_eq!(FE::from_u64_unbiased(0), Some(FE::new(0)));
+        assert_eq!(FE::from_u64_unbiased(PRIME_ORDER), None);
+        assert_eq!(FE::from_u64_unbiased(FULL_BITS_MASK), None);
+        assert_eq!(FE::from_u64_unbiased(u64::MAX), None);
+    }
+    #[test]
+    fn signed_conversions() {
+        assert_eq!(FE::from(-1i64), FE::zero() - FE::new(1));
+        assert_eq!(FE::from(-5i32) + FE::from(5i32), FE::zero());
+        assert_eq!(FE::from(0i64), FE::zero());
+        assert_eq!(FE::from(1337i32), FE::new(1337));
+        assert_eq!(FE::from(i32::MIN), -FE::new(1 << 31));
+        assert_eq!(FE::from(i64::MAX), FE::new(i64::MAX as u64));
+        assert_eq!(FE::from(i64::MIN), -FE::new(1 << 63));
+        assert_eq!(FE::from(i64::MIN) + FE::from(i64::MAX), FE::from(-1i64));
+        for v in [0, 1, -1, 12345, -12345, (PRIME_ORDER / 2) as i64].iter() {
+            assert_eq!(FE::from(*v).signed_value(), *v);
+            assert_eq!(FE::from(-*v).signed_value(), -*v);
+        }
+        assert_eq!(
+            FE::new(PRIME_ORDER / 2 + 1).signed_value(),
+            -((PRIME_ORDER / 2) as i64)
+        );
+    }
+    #[test]
+    fn try_from_u64() {
+        assert_eq!(FE::try_from(0u64), Ok(FE::new(0)));
+        assert_eq!(FE::try_from(PRIME_ORDER - 1), Ok(FE::new(PRIME_ORDER - 1)));
+        assert!(FE::try_from(PRIME_ORDER).is_err());
+        assert!(FE::try_from(u64::MAX).is_err());
+    }
+    #[test]
+    fn reduced_bounds() {
+        assert_eq!(FE::from_reduced(0), Some(FE::new(0)));
+        assert_eq!(
+            FE::from_reduced(PRIME_ORDER - 1),
+            Some(FE::new(PRIME_ORDER - 1))
+        );
+        assert_eq!(FE::from_reduced(PRIME_ORDER), None);
+        assert_eq!(FE::from_reduced(PRIME_ORDER + 1), None);
+        assert_eq!(FE::from_reduced(u64::MAX), None);
+    }
+    #[test]
+    fn bytes_roundtrip() {
+        for v in [0, 1, 256, 1 << 40, PRIME_ORDER - 1].iter() {
+            let fe = FE::new(*v);
+            assert_eq!(FE::from_bytes(&fe.to_bytes()), Some(fe));
+        }
+        assert_eq!(
+            FE::new(PRIME_ORDER - 1).to_bytes(),
+            (PRIME_ORDER - 1).to_be_bytes()
+        );
+        assert_eq!(maxrep().to_bytes(), maxrep().value().to_be_bytes());
+        assert_eq!(FE::from_bytes(&PRIME_ORDER.to_be_bytes()), None);
+        assert_eq!(FE::from_bytes(&[0xff; 8]), None);
+    }
+    #[test]
+    fn le_bytes_roundtrip() {
+        for v in [0, 1, 256, 1 << 40, PRIME_ORDER - 1].iter() {
+            let fe = FE::new(*v);
+            assert_eq!(FE::from_le_bytes(&fe.to_le_bytes()), Some(fe));
+            assert_eq!(fe.to_le_bytes(), v.to_le_bytes());
+
+            let mut reversed = fe.to_bytes();
+            reversed.reverse();
+            assert_eq!(fe.to_le_bytes(), reversed);
+        }
+        assert_eq!(maxrep().to_le_bytes(), maxrep().value().to_le_bytes());
+        assert_eq!(FE::from_le_bytes(&PRIME_ORDER.to_le_bytes()), None);
+        assert_eq!(FE::from_le_bytes(&[0xff; 8]), None);
+    }
+    #[test]
+    fn legendre() {
+        use rand::Rng;
+        assert_eq!(FE::new(0).legendre(), 0);
+        assert_eq!(FE::new(1).legendre(), 1);
+        assert_eq!((FE::new(12345) * FE::new(12345)).legendre(), 1);
+        assert_eq!((-FE::new(1)).legendre(), -1);
+
+        // About half of the nonzero elements are squares.
+        let mut rng = ::rand::thread_rng();
+        let n = 2000;
+        let mut squares = 0;
+        for _ in 0..n {
+            let a: FE = rng.gen();
+            match a.legendre() {
+                1 => squares += 1,
+                -1 => {}
+                _ => assert!(a.is_zero()),
+            }
+        }
+        assert!(squares > n * 2 / 5 && squares < n * 3 / 5);
+    }
+    #[test]
+    fn sum_and_product() {
+        let xs = vec![
+            FE::new(3),
+            FE::new(PRIME_ORDER - 1),
+            maxrep(),
+            FE::new(1 << 40),
+        ];
+        let sum = xs.iter().fold(FE::new(0), |acc, x| acc + *x);
+        let product = xs.iter().fold(FE::new(1), |acc, x| acc * *x);
+        assert_eq!(xs.iter().sum::<FE>(), sum);
+        assert_eq!(xs.clone().into_iter().sum::<FE>(), sum);
+        assert_eq!(xs.iter().product::<FE>(), product);
+        assert_eq!(xs.into_iter().product::<FE>(), product);
+
+        let empty: Vec<FE> = Vec::new();
+        assert_eq!(empty.iter().sum::<FE>(), FE::new(0));
+        assert_eq!(empty.iter().product::<FE>(), FE::new(1));
+    }
+    #[test]
     fn test_values() {
         assert_eq!(FE::new(0).value(), 0);
         assert_eq!(FE::new(1337).value(), 1337);
@@ -614,6 +1267,98 @@ mod tests {
         assert_eq!(FE::new(PRIME_ORDER - 50), FE::new(PRIME_ORDER * 4 - 50));
         assert_eq!(maxrep(), FE::new(FE_VAL_MAX - PRIME_ORDER));
     }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        use bincode;
+        use serde_json;
+        for fe in [
+            FE::new(0),
+            FE::new(1337),
+            FE::new(PRIME_ORDER - 1),
+            maxrep(),
+        ]
+        .iter()
+        {
+            let json = serde_json::to_string(fe).unwrap();
+            assert_eq!(json, fe.value().to_string());
+            assert_eq!(serde_json::from_str::<FE>(&json).unwrap(), *fe);
+
+            let encoded = bincode::serialize(fe).unwrap();
+            assert_eq!(encoded, bincode::serialize(&fe.value()).unwrap());
+            assert_eq!(bincode::deserialize::<FE>(&encoded).unwrap(), *fe);
+        }
+
+        // maxrep() must not leak its unreduced representation.
+        assert_ne!(
+            serde_json::to_string(&maxrep()).unwrap(),
+            FE_VAL_MAX.to_string()
+        );
+
+        assert!(serde_json::from_str::<FE>(&PRIME_ORDER.to_string()).is_err());
+        assert!(serde_json::from_str::<FE>(&u64::MAX.to_string()).is_err());
+        let encoded = bincode::serialize(&PRIME_ORDER).unwrap();
+        assert!(bincode::deserialize::<FE>(&encoded).is_err());
+    }
+    #[test]
+    fn ct_eq() {
+        assert!(maxrep().ct_eq(FE::new(FE_VAL_MAX - PRIME_ORDER)));
+        assert!(fullbits().ct_eq(FE::new(FULL_BITS_MASK)));
+        assert!(FE::new(0).ct_eq(FE::new(PRIME_ORDER)));
+        assert!(!FE::new(0).ct_eq(FE::new(PRIME_ORDER - 1)));
+        assert!(!FE::new(1 << 61).ct_eq(FE::new(0)));
+    }
+    #[test]
+    fn debug() {
+        let a = maxrep();
+        let b = FE::new(FE_VAL_MAX - PRIME_ORDER);
+        assert_ne!(a.val, b.val);
+        assert_eq!(format!("{:?}", a), format!("{:?}", b));
+        assert_eq!(format!("{:?}", FE::new(PRIME_ORDER + 5)), "FE(5)");
+        assert_eq!(format!("{:?}", Some(FE::new(7))), "Some(FE(7))");
+    }
+    #[test]
+    fn conditional_select() {
+        let values = [
+            FE::new(0),
+            FE::new(1),
+            FE::new(PRIME_ORDER - 1),
+            maxrep(),
+            fullbits(),
+        ];
+        for &a in values.iter() {
+            for &b in values.iter() {
+                for &choice in [false, true].iter() {
+                    let selected = FE::conditional_select(a, b, choice);
+                    selected.assert_invariant();
+                    let expected = if choice { a } else { b };
+                    assert_eq!(selected.val, expected.val);
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_ordering() {
+        use std::collections::BTreeMap;
+        // maxrep() and FE::new(FE_VAL_MAX - PRIME_ORDER) are the same
+        // element, stored differently.
+        let a = maxrep();
+        let b = FE::new(FE_VAL_MAX - PRIME_ORDER);
+        assert_ne!(a.val, b.val);
+        assert_eq!(a.cmp(&b), Ordering::Equal);
+        assert_eq!(fullbits().cmp(&FE::new(FULL_BITS_MASK)), Ordering::Equal);
+
+        assert!(FE::new(PRIME_ORDER) < FE::new(1));
+        assert!(FE::new(PRIME_ORDER - 1) > FE::new(PRIME_ORDER + 5));
+        let mut v = vec![FE::new(7), maxrep(), FE::new(PRIME_ORDER + 2), FE::new(0)];
+        v.sort();
+        assert_eq!(v, vec![FE::new(0), FE::new(2), FE::new(7), maxrep()]);
+
+        let mut m = BTreeMap::new();
+        m.insert(a, 1);
+        m.insert(b, 2);
+        assert_eq!(m.len(), 1);
+    }
     #[test]
     fn test_add_sub() {
         assert_eq!(FE::new(0) - FE::new(100), FE::new(PRIME_ORDER - 100));
@@ -697,6 +1442,34 @@ mod tests {
         assert_eq!(FE::from_u64_unbiased(PRIME_ORDER + hibit * 2), None);
     }
 
+    #[test]
+    fn ct_zero() {
+        assert!(FE::zero().ct_is_zero());
+        assert!(FE::new(PRIME_ORDER).ct_is_zero());
+        assert!(FE::new(PRIME_ORDER * 2).ct_is_zero());
+        assert!((maxrep() - maxrep()).ct_is_zero());
+        assert!(!FE::new(1).ct_is_zero());
+        assert!(!FE::new(PRIME_ORDER - 1).ct_is_zero());
+        assert!(!maxrep().ct_is_zero());
+        assert!(!fullbits().ct_is_zero());
+
+        assert_eq!(ct_u64_is_zero(0), 1);
+        assert_eq!(ct_u64_is_zero(1), 0);
+        assert_eq!(ct_u64_is_zero(1 << 63), 0);
+        assert_eq!(ct_u64_is_zero(!0), 0);
+    }
+
+    #[test]
+    fn scalar_mul() {
+        let a = FE::new(PRIME_ORDER - 7);
+        assert_eq!(a * 3u32, a + a + a);
+        assert_eq!(a * 3u64, a + a + a);
+        assert_eq!(3u32 * a, a + a + a);
+        assert_eq!(3u64 * a, a + a + a);
+        assert_eq!(a * PRIME_ORDER, FE::new(0));
+        assert_eq!(a * (PRIME_ORDER + 1), a);
+    }
+
     fn mul_slow(a: FE, b: FE) -> FE {
         use num::bigint::BigUint;
         use num::traits::cast::FromPrimitive;
@@ -719,6 +1492,20 @@ mod tests {
             a * b == mul_slow(a,b)
         }
 
+        fn p_scalar_mul(a : FE, s : u32, t : u64) -> bool {
+            a * s == a * FE::from(s) && s * a == a * s
+                && a * t == a * FE::new(t) && t * a == a * t
+                && a * !t == a * FE::new(!t)
+        }
+
+        fn p_double(a : FE) -> bool {
+            a.double() == a + a && maxrep().double() == maxrep() + maxrep()
+        }
+
+        fn p_square(a : FE) -> bool {
+            a.square() == a * a && a.square() == mul_slow(a, a)
+        }
+
         fn p_recip(a : FE) -> bool {
             // println!("1 / {:?}", a);
             a * a.recip() == FE::new(1)
@@ -727,5 +1514,93 @@ mod tests {
         fn p_div(a : FE, b : FE) -> bool {
             (a / b) * b == a
         }
+
+        fn p_batch_recip(xs : Vec<FE>) -> bool {
+            let mut xs = xs;
+            // Make sure we have some zeros.
+            if xs.len() > 2 {
+                xs[1] = FE::new(0);
+            }
+            let mut batched = xs.clone();
+            FE::batch_recip(&mut batched);
+            xs.iter().zip(batched.iter()).all(|(x, r)| {
+                if x.is_zero() {
+                    r.is_zero()
+                } else {
+                    *r == x.recip()
+                }
+            })
+        }
+
+        fn p_sqrt(a : FE) -> bool {
+            let sq = a * a;
+            let root = sq.sqrt();
+            sq.is_square() && (root == Some(a) || root == Some(-a))
+                && root.unwrap().value() <= (-root.unwrap()).value()
+        }
+
+        fn p_sqrt_nonresidue(a : FE) -> bool {
+            // -1 is not a square, since p = 3 mod 4, so -a^2 is never a
+            // square unless it's zero.
+            let nonresidue = -(a * a);
+            a.is_zero() || (!nonresidue.is_square() && nonresidue.sqrt().is_none())
+        }
+
+        fn p_pow(a : FE, exp : u8) -> bool {
+            let mut expected = FE::new(1);
+            for _ in 0..exp {
+                expected *= a;
+            }
+            a.pow(u64::from(exp)) == expected
+        }
+
+        fn p_pow_fermat(a : FE) -> bool {
+            a.is_zero() || a.pow(PRIME_ORDER - 1) == FE::new(1)
+        }
+
+        fn p_ct_eq(a : FE, b : FE) -> bool {
+            let boundary = [FE::new(0), FE::new(PRIME_ORDER - 1), maxrep(), fullbits()];
+            a.ct_eq(b) == (a == b)
+                && a.ct_eq(a)
+                && boundary.iter().all(|c| a.ct_eq(*c) == (a == *c) && c.ct_eq(b) == (*c == b))
+        }
+
+        fn p_conditional_select(a : FE, b : FE, choice : bool) -> bool {
+            FE::conditional_select(a, b, choice) == if choice { a } else { b }
+        }
+
+        fn p_ct_is_zero(a : FE) -> bool {
+            a.ct_is_zero() == a.is_zero()
+        }
+
+        fn p_decode_u64(v : u64) -> bool {
+            // quickcheck only generates small u64s, so try some large
+            // values near it too.
+            let candidates = [v, !v, PRIME_ORDER.wrapping_add(v), PRIME_ORDER.wrapping_sub(v),
+                              v.rotate_right(8)];
+            candidates.iter().all(|&v| {
+                let mut encoded = [0; 8];
+                NetworkEndian::write_u64(&mut encoded, v);
+                let decoded_ok = match FE::from_bytes(&encoded) {
+                    Some(fe) => {
+                        fe.assert_invariant();
+                        fe.val == v && fe.val < PRIME_ORDER && fe.to_bytes() == encoded
+                    }
+                    None => v >= PRIME_ORDER,
+                };
+                let unbiased_ok = match FE::from_u64_unbiased(v) {
+                    Some(fe) => {
+                        fe.assert_invariant();
+                        fe.val < PRIME_ORDER
+                    }
+                    None => v & FULL_BITS_MASK >= PRIME_ORDER,
+                };
+                let le_ok = match FE::from_le_bytes(&v.to_le_bytes()) {
+                    Some(fe) => fe.val == v && fe.to_le_bytes() == v.to_le_bytes(),
+                    None => v >= PRIME_ORDER,
+                };
+                decoded_ok && unbiased_ok && le_ok
+            })
+        }
     }
 }
@@ -0,0 +1,249 @@
+//! Differential-privacy noise for privcount counters.
+//!
+//! Privcount protects individual users by adding random noise to every
+//! counter, so that the published totals don't reveal whether any one
+//! user's activity was counted.  There is no trusted party to add that
+//! noise at the end, so each client adds its own share of it when it
+//! creates its counters: the TRs only ever see the sum.
+//!
+//! That means the noise has to be calibrated for the *aggregate*.  Each
+//! client samples from a distribution chosen so that, once the noise
+//! from all the clients is added together, the total has the
+//! distribution that the privacy analysis calls for.  For Gaussian
+//! noise this is easy, since a sum of independent Gaussians is Gaussian:
+//! if each of `n` clients adds noise with standard deviation
+//! `sigma / sqrt(n)`, the total noise has standard deviation `sigma`.
+//!
+//! The counters are integers, so we use the *discrete* Gaussian, which
+//! only takes integer values.  A sum of discrete Gaussians isn't exactly
+//! a discrete Gaussian, but once each client's standard deviation is
+//! about 1 or more, the difference is far too small to matter: see
+//! Kairouz, Liu, and Steinke, "The Distributed Discrete Gaussian
+//! Mechanism for Federated Learning with Secure Aggregation" (2021).
+//!
+//! Note that if fewer clients report than you planned for, the total
+//! noise is smaller than intended, and the privacy guarantee is weaker.
+//! Err on the side of underestimating the number of clients.
+
+use math::FE;
+use rand::Rng;
+
+/// A source of noise to add to each counter.
+///
+/// The noise is a signed integer, mapped into the field: see
+/// `FE::signed_value()` for how to get it back out of the total.
+pub trait NoiseSource {
+    /// Return a single noise value, drawn using `rng`.
+    fn sample(&self, rng: &mut dyn Rng) -> FE;
+}
+
+/// No noise at all.
+///
+/// **This gives the users no differential privacy.**  Use it only for
+/// tests, or for counters whose totals are fine to publish exactly.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoNoise;
+
+impl NoiseSource for NoNoise {
+    fn sample(&self, _rng: &mut dyn Rng) -> FE {
+        FE::new(0)
+    }
+}
+
+/// Discrete Gaussian noise.
+///
+/// Each sample is an integer `y`, drawn with probability proportional
+/// to `exp(-y^2 / (2 sigma^2))`.  We use the rejection sampler from
+/// Canonne, Kamath, and Steinke, "The Discrete Gaussian for Differential
+/// Privacy" (2020), which never rounds a floating-point sample; its
+/// Bernoulli trials use f64 arithmetic, though, so their probabilities
+/// are only as exact as an f64.
+#[derive(Clone, Debug)]
+pub struct GaussianNoise {
+    /// The standard deviation of the noise each client adds.
+    sigma: f64,
+}
+
+impl GaussianNoise {
+    /// Create a new GaussianNoise that adds noise with standard
+    /// deviation `sigma` to each counter, for each client.
+    pub fn new(sigma: f64) -> Result<Self, &'static str> {
+        if !(sigma.is_finite() && sigma >= 0.0) {
+            return Err("Invalid standard deviation.");
+        }
+        Ok(GaussianNoise { sigma })
+    }
+
+    /// Create a new GaussianNoise that a single client should use, so
+    /// that the noise summed over `n_clients` clients has standard
+    /// deviation `aggregate_sigma`.
+    pub fn for_clients(aggregate_sigma: f64, n_clients: u32) -> Result<Self, &'static str> {
+        if n_clients == 0 {
+            return Err("Invalid number of clients.");
+        }
+        GaussianNoise::new(aggregate_sigma / f64::from(n_clients).sqrt())
+    }
+
+    /// Create a new GaussianNoise that a single client should use, so
+    /// that the noise summed over `n_clients` clients gives
+    /// (`epsilon`, `delta`)-differential privacy for a counter whose
+    /// value any one user can change by at most `sensitivity`.
+    ///
+    /// This uses the classic calibration for the Gaussian mechanism,
+    /// `sigma = sensitivity * sqrt(2 ln(1.25/delta)) / epsilon`, which
+    /// only holds for `epsilon < 1`.
+    pub fn for_privacy(
+        sensitivity: f64,
+        epsilon: f64,
+        delta: f64,
+        n_clients: u32,
+    ) -> Result<Self, &'static str> {
+        if !(sensitivity.is_finite() && sensitivity > 0.0) {
+            return Err("Invalid sensitivity.");
+        }
+        if !(epsilon > 0.0 && epsilon < 1.0) {
+            return Err("Epsilon must be between 0 and 1.");
+        }
+        if !(delta > 0.0 && delta < 1.0) {
+            return Err("Delta must be between 0 and 1.");
+        }
+        let sigma = sensitivity * (2.0 * (1.25 / delta).ln()).sqrt() / epsilon;
+        GaussianNoise::for_clients(sigma, n_clients)
+    }
+
+    /// Return the standard deviation of the noise each client adds.
+    pub fn sigma(&self) -> f64 {
+        self.sigma
+    }
+}
+
+impl NoiseSource for GaussianNoise {
+    fn sample(&self, rng: &mut dyn Rng) -> FE {
+        if self.sigma == 0.0 {
+            return FE::new(0);
+        }
+        // Sample from a discrete Laplace distribution with scale t, and
+        // keep the sample with the probability that makes the result a
+        // discrete Gaussian.  This choice of t makes the expected number
+        // of tries small.
+        let t = self.sigma.floor() + 1.0;
+        let var = self.sigma * self.sigma;
+        loop {
+            let y = discrete_laplace(rng, t as u64);
+            let d = (y.unsigned_abs() as f64) - var / t;
+            if bernoulli_exp(rng, d * d / (2.0 * var)) {
+                return FE::from(y);
+            }
+        }
+    }
+}
+
+/// Return true with probability `p`.
+fn bernoulli(mut rng: &mut dyn Rng, p: f64) -> bool {
+    (&mut rng).gen::<f64>() < p
+}
+
+/// Return true with probability `exp(-gamma)`, for `gamma >= 0`.
+fn bernoulli_exp(rng: &mut dyn Rng, gamma: f64) -> bool {
+    bernoulli(rng, (-gamma).exp())
+}
+
+/// Return an integer `y`, drawn with probability proportional to
+/// `exp(-|y| / t)`, for `t >= 1`.
+fn discrete_laplace(mut rng: &mut dyn Rng, t: u64) -> i64 {
+    loop {
+        // Split the magnitude as u + t*v, where u is in [0, t) and v is
+        // geometric.
+        let u = (&mut rng).gen_range(0, t);
+        if !bernoulli_exp(rng, u as f64 / t as f64) {
+            continue;
+        }
+        let mut v = 0;
+        while bernoulli_exp(rng, 1.0) {
+            v += 1;
+        }
+        let magnitude = (u + t * v) as i64;
+        // Zero would be twice as likely as it should be if we let it
+        // take either sign.
+        let negative = (&mut rng).gen::<bool>();
+        if negative && magnitude == 0 {
+            continue;
+        }
+        return if negative { -magnitude } else { magnitude };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use noise::*;
+    use rand;
+    use std::iter::FromIterator;
+
+    #[test]
+    fn parameters() {
+        assert!(GaussianNoise::new(-1.0).is_err());
+        assert!(GaussianNoise::new(f64::NAN).is_err());
+        assert!(GaussianNoise::for_clients(10.0, 0).is_err());
+        assert!(GaussianNoise::for_privacy(0.0, 0.5, 1e-6, 10).is_err());
+        assert!(GaussianNoise::for_privacy(1.0, 1.5, 1e-6, 10).is_err());
+        assert!(GaussianNoise::for_privacy(1.0, 0.5, 0.0, 10).is_err());
+
+        let g = GaussianNoise::for_clients(10.0, 4).unwrap();
+        assert!((g.sigma() - 5.0).abs() < 1e-9);
+        let g = GaussianNoise::for_privacy(1.0, 0.5, 1e-6, 1).unwrap();
+        assert!((g.sigma() - 10.597).abs() < 1e-3);
+    }
+
+    #[test]
+    fn no_noise() {
+        let mut rng = rand::thread_rng();
+        let g = GaussianNoise::new(0.0).unwrap();
+        for _ in 0..100 {
+            assert_eq!(g.sample(&mut rng), FE::new(0));
+        }
+    }
+
+    #[test]
+    fn no_noise_source() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            assert_eq!(NoNoise.sample(&mut rng), FE::new(0));
+        }
+    }
+
+    #[test]
+    fn discrete_shape() {
+        // With sigma = 1, a discrete Gaussian is zero with probability
+        // 1 / (sum of exp(-k^2/2) over all integers k), or about 0.399,
+        // and is 1 or -1 with probability about 0.242 each.
+        let mut rng = rand::thread_rng();
+        let g = GaussianNoise::new(1.0).unwrap();
+        let n = 20000;
+        let mut counts = [0; 3];
+        for _ in 0..n {
+            let v = g.sample(&mut rng).signed_value();
+            if v.abs() <= 1 {
+                counts[(v + 1) as usize] += 1;
+            }
+        }
+        // The standard errors here are about 0.0035.
+        let freq = |c: usize| c as f64 / n as f64;
+        assert!((freq(counts[1]) - 0.399).abs() < 0.02);
+        assert!((freq(counts[0]) - 0.242).abs() < 0.02);
+        assert!((freq(counts[2]) - 0.242).abs() < 0.02);
+    }
+
+    #[test]
+    fn gaussian_stats() {
+        let mut rng = rand::thread_rng();
+        let g = GaussianNoise::new(20.0).unwrap();
+        let n = 10000;
+        let samples = Vec::from_iter((0..n).map(|_| g.sample(&mut rng).signed_value() as f64));
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let var = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / n as f64;
+        // The standard error of the mean is 0.2 here, and of the
+        // variance, about 6.
+        assert!(mean.abs() < 1.5);
+        assert!((var - 400.0).abs() < 50.0);
+    }
+}
@@ -0,0 +1,116 @@
+//! A driver that runs the whole privcount protocol in one process.
+//!
+//! In a real deployment, the clients and the TRs are separate parties,
+//! and the data between them goes over the network.  For tests and
+//! demos, though, it's handy to run every step in one place: this module
+//! plays all the parts, using the same public APIs that real clients and
+//! TRs would use.  Read `run()` as the reference for how those APIs fit
+//! together.
+
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+use client::{CounterSet, SecureRng};
+use data::{ClientKeyPair, CtrId};
+use noise::NoNoise;
+use server::{self, ServerKeys};
+
+/// Run the protocol from start to finish, and return the totals that
+/// the TRs reconstruct.
+///
+/// There is one client for each entry in `client_increments`, which
+/// maps counter IDs to the amount that client adds to each one.  Every
+/// client tracks all the counters in `counter_ids`, and shares them among
+/// the TRs in `server_keys` so that any `k` of them can find the totals.
+/// Each client gets a fresh `ClientKeyPair`.  The clients add no noise,
+/// so the totals are exact.
+///
+/// Every TR decodes its data from every client and sums it; then the
+/// first `k` TRs combine their sums.  The result has a total for every
+/// counter in `counter_ids`, and for `CLIENT_COUNT_ID`.
+///
+/// # Errors
+///
+/// Gives an error if a client increments a counter that isn't in
+/// `counter_ids`, or if any step of the protocol fails: for example,
+/// because `k` is zero or greater than the number of TRs.
+///
+/// # Examples
+/// ```
+/// extern crate privcount;
+/// extern crate rand;
+/// use privcount::data::*;
+/// use privcount::encrypt::keygen::curve25519_seckey_gen;
+/// use privcount::protocol;
+/// use privcount::server::ServerKeys;
+/// use std::collections::HashMap;
+/// # fn main() -> Result<(), &'static str> {
+/// let mut rng = rand::os::OsRng::new().unwrap();
+/// let server_keys: Vec<_> = (0..3u8)
+///     .map(|i| {
+///         let enc_secret = curve25519_seckey_gen(&mut rng);
+///         ServerKeys {
+///             public: TrKeys { enc_key: enc_secret.public(), signing_key: [i; 32] },
+///             enc_secret,
+///         }
+///     })
+///     .collect();
+///
+/// let mut a = HashMap::new();
+/// a.insert(CtrId(1), 10);
+/// let mut b = HashMap::new();
+/// b.insert(CtrId(1), 5);
+/// b.insert(CtrId(2), 7);
+///
+/// let totals = protocol::run(&mut rng, &[CtrId(1), CtrId(2)], &[a, b], &server_keys, 2)?;
+/// assert_eq!(totals[&CtrId(1)], 15);
+/// assert_eq!(totals[&CtrId(2)], 7);
+/// assert_eq!(totals[&CLIENT_COUNT_ID], 2);
+/// # Ok(())
+/// # }
+/// ```
+pub fn run<R: SecureRng>(
+    rng: &mut R,
+    counter_ids: &[CtrId],
+    client_increments: &[HashMap<CtrId, u64>],
+    server_keys: &[ServerKeys],
+    k: u32,
+) -> Result<HashMap<CtrId, u64>, &'static str> {
+    let tr_keys = Vec::from_iter(server_keys.iter().map(|sk| sk.public.clone()));
+
+    // Each client counts, and then sends a TrData to each TR.
+    let mut clients = Vec::with_capacity(client_increments.len());
+    for increments in client_increments.iter() {
+        let client_keys = ClientKeyPair::generate(rng);
+        let mut ctrs = CounterSet::new(rng, counter_ids, &tr_keys, k, NoNoise)?;
+        for (id, v) in increments.iter() {
+            ctrs.ctr(*id)
+                .ok_or("Client incremented an unknown counter.")?
+                .inc_u64(*v);
+        }
+        let data = ctrs.finalize(rng, &client_keys)?;
+        clients.push((client_keys, data));
+    }
+
+    // Each TR decodes its data from every client, and sums it.
+    let mut contributions = Vec::with_capacity(server_keys.len());
+    for keys in server_keys.iter() {
+        let mut decoded = Vec::with_capacity(clients.len());
+        for (client_keys, data) in clients.iter() {
+            let mine = data
+                .tr_data
+                .iter()
+                .find(|d| d.keys == keys.public)
+                .ok_or("No data for TR.")?;
+            decoded.push(keys.decode_from(client_keys.public(), &data.counter_ids, mine)?);
+        }
+        contributions.push((keys.public.get_x_coord(), server::sum_shares(&decoded)));
+    }
+
+    // Any k TRs can find the totals: use the first k.
+    let k = k as usize;
+    if k > contributions.len() {
+        return Err("Not enough TRs.");
+    }
+    server::reconstruct_totals(&contributions[..k])
+}
@@ -0,0 +1,125 @@
+//! Choosing a default random number generator.
+//!
+//! Almost every function in this crate that needs randomness takes an
+//! `Rng` from the caller.  For callers who don't want to pick one
+//! themselves, this module provides `default_rng()`, whose backend is
+//! chosen with cargo features:
+//!
+//!   * `os` (the default): use `rand::os::OsRng`.
+//!   * `getrandom`: use the `getrandom` crate, which supports more
+//!     platforms, including WASM.  This takes precedence over `os`.
+//!   * `supplied`: provide no default RNG at all, so that callers must
+//!     always pass in their own.
+//!
+//! Whichever backend you pick, it must be cryptographically secure:
+//! privcount's blinding and secret sharing are only as good as the
+//! randomness behind them.  The backends here are all secure, and all
+//! implement `SecureRng`.
+
+#[cfg(feature = "getrandom")]
+use client::SecureRng;
+#[cfg(feature = "getrandom")]
+use rand::Rng;
+
+/// An RNG that draws its output from the `getrandom` crate.
+#[cfg(feature = "getrandom")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GetrandomRng;
+
+#[cfg(feature = "getrandom")]
+impl Rng for GetrandomRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // Like OsRng, we have no way to report an error here, and no
+        // safe way to continue without randomness.
+        ::getrandom::getrandom(dest).expect("getrandom failed");
+    }
+}
+
+#[cfg(feature = "getrandom")]
+impl SecureRng for GetrandomRng {}
+
+/// The type of RNG returned by `default_rng()`.
+#[cfg(all(feature = "getrandom", not(feature = "supplied")))]
+pub type DefaultRng = GetrandomRng;
+
+/// The type of RNG returned by `default_rng()`.
+#[cfg(all(feature = "os", not(feature = "getrandom"), not(feature = "supplied")))]
+pub type DefaultRng = ::rand::os::OsRng;
+
+/// Return a new instance of the default secure RNG.
+///
+/// # Examples
+/// ```
+/// extern crate privcount;
+/// extern crate rand;
+/// use rand::Rng;
+/// # fn main() -> Result<(), &'static str> {
+/// let mut rng = privcount::rng::default_rng()?;
+/// let _x: u64 = rng.gen();
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(all(feature = "getrandom", not(feature = "supplied")))]
+pub fn default_rng() -> Result<DefaultRng, &'static str> {
+    Ok(GetrandomRng)
+}
+
+/// Return a new instance of the default secure RNG.
+///
+/// # Examples
+/// ```
+/// extern crate privcount;
+/// extern crate rand;
+/// use rand::Rng;
+/// # fn main() -> Result<(), &'static str> {
+/// let mut rng = privcount::rng::default_rng()?;
+/// let _x: u64 = rng.gen();
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(all(feature = "os", not(feature = "getrandom"), not(feature = "supplied")))]
+pub fn default_rng() -> Result<DefaultRng, &'static str> {
+    ::rand::os::OsRng::new().map_err(|_| "Couldn't open the OS RNG.")
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "os", feature = "getrandom"))]
+    #[cfg(not(feature = "supplied"))]
+    #[test]
+    fn default_rng_works() {
+        use client::SecureRng;
+        use rand::Rng;
+        use rng::*;
+
+        fn is_secure<R: SecureRng>(_: &R) {}
+
+        let mut rng = default_rng().unwrap();
+        is_secure(&rng);
+        let a: [u64; 4] = rng.gen();
+        let b: [u64; 4] = rng.gen();
+        assert_ne!(a, b);
+    }
+
+    #[cfg(feature = "getrandom")]
+    #[test]
+    fn getrandom_rng() {
+        use rand::Rng;
+        use rng::GetrandomRng;
+
+        let mut buf = [0; 64];
+        GetrandomRng.fill_bytes(&mut buf);
+        assert!(buf.iter().any(|b| *b != 0));
+        assert_ne!(GetrandomRng.next_u64(), GetrandomRng.next_u64());
+    }
+}
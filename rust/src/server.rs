@@ -5,7 +5,6 @@
 //! those shares to the other tally reporters so they can reconstruct
 //! the true sum.
 
-use byteorder::{ByteOrder, NetworkEndian};
 use num::Zero;
 use std::collections::HashMap;
 use std::iter::FromIterator;
@@ -13,12 +12,15 @@ use std::u32;
 
 use data::*;
 use encrypt::hybrid::PrivcountDecryptor;
+use encrypt::keygen::{ratchet_curve25519_seckey, Curve25519Secret};
 use encrypt::Decryptor;
 use math::FE;
+use shamir::{self, LagrangeInterpolator, Share};
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
 
 /// The data a TR recovers from a single client
 pub struct ClientData {
-    #[allow(dead_code)]
     client_key: ClientKey,
     shares: Vec<(CtrId, FE)>,
 }
@@ -26,24 +28,58 @@ pub struct ClientData {
 /// The keys that a TR uses for itself.
 pub struct ServerKeys {
     /// The secret curve25519 private key used to decryption.
-    pub enc_secret: [u8; 32],
+    pub enc_secret: Curve25519Secret,
     /// The public keys for this TR
     pub public: TrKeys,
 }
 
+/// With the `zeroize` feature, ServerKeys erase their secret key when
+/// they're dropped.
+#[cfg(feature = "zeroize")]
+impl Zeroize for ServerKeys {
+    fn zeroize(&mut self) {
+        self.enc_secret.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for ServerKeys {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl ServerKeys {
     /// Decrypt a TrData (as sent by a client) into a TrData (which we will use).
+    ///
+    /// The TrData must be signed by `client`, over the counter IDs in
+    /// `counters`: otherwise, we give an error.
     pub fn decode_from(
         &self,
         client: &ClientKey,
         counters: &[CtrId],
         data: &TrData,
+    ) -> Result<ClientData, &'static str> {
+        self.decode_from_with_x(client, counters, data, self.public.get_x_coord())
+    }
+
+    /// As `decode_from()`, but expect the TrData to use the X coordinate
+    /// `x`, rather than the one derived from our keys.
+    ///
+    /// Use this when the client was built with
+    /// `CounterSet::new_with_x_coords()`.
+    pub fn decode_from_with_x(
+        &self,
+        client: &ClientKey,
+        counters: &[CtrId],
+        data: &TrData,
+        x: FE,
     ) -> Result<ClientData, &'static str> {
         // Is this for us?
-        if &data.keys != &self.public {
+        if !data.keys.ct_eq(&self.public) {
             return Err("Keys aren't our key.");
         }
-        if data.x != self.public.get_x_coord() {
+        if data.x != x {
             return Err("Wrong X coordinate.");
         }
         // XX  Use try_from once it's stable
@@ -51,6 +87,12 @@ impl ServerKeys {
             return Err("Too many counters.");
         }
         let n_counters: u32 = counters.len() as u32;
+        if data.n_counters != n_counters {
+            return Err("Wrong number of counters.");
+        }
+        if !data.verify_signature(counters, client) {
+            return Err("Bad signature.");
+        }
 
         // It is for us.  Recover the encrypted things.
         let dec = PrivcountDecryptor::new(&self.enc_secret, &self.public.signing_key);
@@ -62,23 +104,22 @@ impl ServerKeys {
             .decrypt(&data.encrypted_counters, Y_ENCRYPTION_TWEAK)
             .ok_or("Counter decryption failed.")?;
 
+        if n_counters > MAX_COUNTERS {
+            return Err("Too many counters.");
+        }
         let seed = Seed::from_bytes(&seedval)?;
-        let masks = seed.counter_masks(n_counters)?;
-        if ctrs.len() != masks.len() * 8 {
+        if ctrs.len() != n_counters as usize * 8 {
             return Err("Wrong number of counters.");
         }
-        let mut u64s = Vec::with_capacity(masks.len());
-        u64s.resize(masks.len(), 0);
-        NetworkEndian::read_u64_into(&ctrs, &mut u64s);
-
-        let mut yvals = Vec::new();
-        for u in u64s {
-            yvals.push(FE::from_reduced(u).ok_or("BadFE")?);
+        let mut yvals = Vec::with_capacity(n_counters as usize);
+        for chunk in ctrs.chunks(8) {
+            let mut encoded = [0; 8];
+            encoded.copy_from_slice(chunk);
+            yvals.push(FE::from_bytes(&encoded).ok_or("BadFE")?);
         }
         let shares = Vec::from_iter(
             counters.iter().map(|c| *c).zip(
-                masks
-                    .into_iter()
+                seed.mask_iter()
                     .zip(yvals.into_iter())
                     .map(|(mask, y)| mask + y),
             ),
@@ -89,14 +130,261 @@ impl ServerKeys {
             shares,
         })
     }
+
+    /// Decode many TrData objects, each with its client's key and
+    /// counter IDs, as `decode_from()` would.
+    ///
+    /// The results are in the same order as `inputs`.  With the
+    /// `parallel` feature, we decode the inputs on rayon's thread pool;
+    /// otherwise, this is just a loop.  Either way, the output is the
+    /// same.
+    ///
+    /// # Errors
+    ///
+    /// If any input fails to decode, gives the error for the first one
+    /// that failed.
+    pub fn decode_from_many(
+        &self,
+        inputs: &[(&ClientKey, &[CtrId], &TrData)],
+    ) -> Result<Vec<ClientData>, &'static str> {
+        self.decode_many_impl(inputs)
+    }
+
+    /// Helper: implement `decode_from_many()` with a single thread.
+    #[cfg(not(feature = "parallel"))]
+    fn decode_many_impl(
+        &self,
+        inputs: &[(&ClientKey, &[CtrId], &TrData)],
+    ) -> Result<Vec<ClientData>, &'static str> {
+        inputs
+            .iter()
+            .map(|&(client, counters, data)| self.decode_from(client, counters, data))
+            .collect()
+    }
+
+    /// Helper: implement `decode_from_many()` on rayon's thread pool.
+    #[cfg(feature = "parallel")]
+    fn decode_many_impl(
+        &self,
+        inputs: &[(&ClientKey, &[CtrId], &TrData)],
+    ) -> Result<Vec<ClientData>, &'static str> {
+        use rayon::prelude::*;
+        // Collecting an indexed parallel iterator keeps the input order,
+        // so the output doesn't depend on timing.  We collect the results
+        // before looking for errors, so that the error we give is the
+        // first one, not whichever one a thread happened to find first.
+        let results: Vec<_> = inputs
+            .par_iter()
+            .map(|&(client, counters, data)| self.decode_from(client, counters, data))
+            .collect();
+        results.into_iter().collect()
+    }
+
+    /// Given a set of ClientData that this TR has decoded, compute this
+    /// TR's share of the sum for each counter, as a `Share` that is
+    /// ready to publish to the other TRs.
+    ///
+    /// This is the same as `sum_shares()`, except that every share
+    /// carries this TR's X coordinate.
+    pub fn sum_as_shares(&self, client_data: &[ClientData]) -> HashMap<CtrId, Share<FE>> {
+        let x = self.public.get_x_coord();
+        sum_shares(client_data)
+            .into_iter()
+            .map(|(id, y)| (id, Share { x, y }))
+            .collect()
+    }
+}
+
+/// A TR's encryption keys for a sequence of reporting periods, with
+/// forward secrecy.
+///
+/// A TR's signing key (and therefore its X coordinate) is long-term, but
+/// if its encryption key were long-term too, then anybody who compromised
+/// it could decrypt every report they had ever recorded.  Instead, the TR
+/// keeps a separate encryption key for each reporting period.  The key
+/// for period `p + 1` is derived from the key for period `p` with
+/// `keygen::ratchet_curve25519_seckey()`, which is one-way.
+///
+/// At the start of each period, the TR publishes the public keys from
+/// `server_keys().public`, and clients encrypt to those keys as usual.
+/// Since the public keys can't be derived from one another, clients
+/// must use the published keys for the right period; reports encrypted
+/// for any other period won't decrypt.
+///
+/// # Deleting old keys
+///
+/// Forward secrecy only holds if the old keys are really gone.  When a
+/// period ends, the TR must decrypt all of its reports, call `advance()`,
+/// and drop every `ServerKeys` it got from `server_keys()` for that
+/// period.  `advance()` overwrites the stored key, but it cannot erase
+/// copies that the caller made, or that were written to disk or swap.
+/// Clients, for their part, should not keep their plaintext counters
+/// after they've sent their reports.
+pub struct PeriodKeyRatchet {
+    /// The current reporting period.
+    period: u64,
+    /// The secret curve25519 key for `period`.
+    enc_secret: Curve25519Secret,
+    /// The TR's long-term signing key.
+    signing_key: [u8; 32],
+}
+
+impl PeriodKeyRatchet {
+    /// Start a new ratchet at `period`, with `enc_secret` as the secret
+    /// key for that period.
+    ///
+    /// `enc_secret` should come from `keygen::curve25519_seckey_gen()`.
+    pub fn new(enc_secret: Curve25519Secret, signing_key: [u8; 32], period: u64) -> Self {
+        PeriodKeyRatchet {
+            period,
+            enc_secret,
+            signing_key,
+        }
+    }
+
+    /// Return the current reporting period.
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Return the keys to use during the current reporting period.
+    pub fn server_keys(&self) -> ServerKeys {
+        ServerKeys {
+            enc_secret: self.enc_secret.clone(),
+            public: TrKeys {
+                enc_key: self.enc_secret.public(),
+                signing_key: self.signing_key,
+            },
+        }
+    }
+
+    /// Move on to the next reporting period, overwriting the current
+    /// period's secret key.
+    pub fn advance(&mut self) -> Result<(), &'static str> {
+        let next_period = self.period.checked_add(1).ok_or("Out of periods.")?;
+        self.enc_secret = ratchet_curve25519_seckey(&self.enc_secret, self.period);
+        self.period = next_period;
+        Ok(())
+    }
+
+    /// Move on to reporting period `period`, overwriting the secret keys
+    /// for every period before it.
+    ///
+    /// It is an error to move backwards: the keys for earlier periods are
+    /// gone.
+    pub fn advance_to(&mut self, period: u64) -> Result<(), &'static str> {
+        if period < self.period {
+            return Err("Can't ratchet backwards.");
+        }
+        while self.period < period {
+            self.advance()?;
+        }
+        Ok(())
+    }
+}
+
+/// The reconstructed tally for a round.
+pub struct TallyResult {
+    /// The total for every counter, including `CLIENT_COUNT_ID`.
+    totals: HashMap<CtrId, u64>,
+}
+
+impl TallyResult {
+    /// Wrap the reconstructed totals from `reconstruct_totals()` or
+    /// `Coordinator::tally()`.
+    pub fn new(totals: HashMap<CtrId, u64>) -> Self {
+        TallyResult { totals }
+    }
+
+    /// Return the number of clients that contributed to this tally, or
+    /// None if the tally has no client count.
+    pub fn client_count(&self) -> Option<u64> {
+        self.get(CLIENT_COUNT_ID)
+    }
+
+    /// Return the total for the counter `id`, if there is one.
+    pub fn get(&self, id: CtrId) -> Option<u64> {
+        self.totals.get(&id).cloned()
+    }
+
+    /// Return the total for every counter, including the client count.
+    pub fn totals(&self) -> &HashMap<CtrId, u64> {
+        &self.totals
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for PeriodKeyRatchet {
+    fn drop(&mut self) {
+        self.enc_secret.zeroize();
+    }
 }
 
 /// Given a set of ClientData from different clients, compute the sum
-/// for each distinct counter in those ClientDara objects.
+/// for each distinct counter in those ClientData objects.
+///
+/// The result is this TR's share of each counter's total.  With the
+/// sums from any K TRs, `reconstruct_totals()` can find the totals.
+///
+/// # Examples
+/// ```
+/// extern crate privcount;
+/// extern crate rand;
+/// use privcount::client::CounterSet;
+/// use privcount::data::*;
+/// use privcount::encrypt::keygen::curve25519_seckey_gen;
+/// use privcount::noise::NoNoise;
+/// use privcount::server::{self, ServerKeys};
+/// # fn main() {
+/// let mut rng = rand::os::OsRng::new().unwrap();
+/// let enc_secret = curve25519_seckey_gen(&mut rng);
+/// let tr = ServerKeys {
+///     public: TrKeys { enc_key: enc_secret.public(), signing_key: [7; 32] },
+///     enc_secret,
+/// };
+///
+/// let mut decoded = Vec::new();
+/// for v in [10, 20, 30].iter() {
+///     let client_keys = ClientKeyPair::generate(&mut rng);
+///     let mut ctrs = CounterSet::new(&mut rng, &[CtrId(1)], &[tr.public.clone()], 1, NoNoise).unwrap();
+///     ctrs.ctr(CtrId(1)).unwrap().inc(*v);
+///     let data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+///     let cd = tr.decode_from(client_keys.public(), &data.counter_ids, &data.tr_data[0]);
+///     decoded.push(cd.unwrap());
+/// }
+///
+/// let sums = server::sum_shares(&decoded);
+/// let totals = server::reconstruct_totals(&[(tr.public.get_x_coord(), sums)]).unwrap();
+/// assert_eq!(totals[&CtrId(1)], 60);
+/// assert_eq!(totals[&CLIENT_COUNT_ID], 3);
+/// # }
+/// ```
 pub fn sum_shares(client_data: &[ClientData]) -> HashMap<CtrId, FE> {
+    sum_shares_subset(client_data, |_| true)
+}
+
+/// As `sum_shares()`, but only include the clients whose keys match
+/// `client_filter`.
+///
+/// This is a debugging tool: if a tally looks wrong, the TRs can each
+/// sum over the same subsets of clients, and the coordinator can
+/// reconstruct each subset's tally separately, to narrow down which
+/// client is misbehaving.
+///
+/// Be careful: a subset's tally reveals the total of that subset's
+/// counters.  A subset of one client reveals everything that client
+/// reported, so the TRs should refuse to sum over subsets smaller than
+/// they'd be willing to publish.
+pub fn sum_shares_subset<F>(client_data: &[ClientData], client_filter: F) -> HashMap<CtrId, FE>
+where
+    F: Fn(&ClientKey) -> bool,
+{
     let mut result = HashMap::new();
 
-    for cd in client_data.iter() {
+    for cd in client_data
+        .iter()
+        .filter(|cd| client_filter(&cd.client_key))
+    {
         for &(id, val) in cd.shares.iter() {
             let counter = result.entry(id).or_insert(FE::zero());
             *counter += val;
@@ -105,3 +393,582 @@ pub fn sum_shares(client_data: &[ClientData]) -> HashMap<CtrId, FE> {
 
     result
 }
+
+/// A running sum of the shares that a TR has received from its clients.
+///
+/// This computes the same thing as `sum_shares()`, but without having to
+/// hold on to every client's ClientData until the end of the collection
+/// period: add each one as it arrives, and call `finish()` at the end.
+pub struct Tally {
+    /// The sum so far of every counter we expect.
+    sums: HashMap<CtrId, FE>,
+    /// The IDs of every counter we expect, in order.
+    counter_ids: Vec<CtrId>,
+}
+
+impl Tally {
+    /// Create a new Tally to sum clients whose counters have the IDs in
+    /// `counter_ids`.
+    ///
+    /// These are the same counter IDs that the clients were configured
+    /// with; `CLIENT_COUNT_ID` is always included.
+    pub fn new(counter_ids: &[CtrId]) -> Self {
+        let mut ids = counter_ids.to_vec();
+        ids.push(CLIENT_COUNT_ID);
+        ids.sort_by_key(|id| id.0);
+        ids.dedup();
+        let sums = HashMap::from_iter(ids.iter().map(|id| (*id, FE::zero())));
+        Tally {
+            sums,
+            counter_ids: ids,
+        }
+    }
+
+    /// Add the shares from a single client to this Tally.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error, and adds nothing, if the client's counters aren't
+    /// exactly the ones this Tally expects.
+    pub fn add(&mut self, cd: &ClientData) -> Result<(), &'static str> {
+        let mut ids = Vec::from_iter(cd.shares.iter().map(|&(id, _)| id));
+        ids.sort_by_key(|id| id.0);
+        if ids != self.counter_ids {
+            return Err("Client has the wrong set of counters.");
+        }
+        for &(id, val) in cd.shares.iter() {
+            *self.sums.get_mut(&id).unwrap() += val;
+        }
+        Ok(())
+    }
+
+    /// Return this TR's sum for every counter.
+    pub fn finish(self) -> HashMap<CtrId, FE> {
+        self.sums
+    }
+}
+
+/// Reconstruct the value of the counter `id` from a set of TR sums, or
+/// return None if any of them is missing that counter, or if their X
+/// coordinates aren't usable.
+fn recover_counter(contributions: &[(FE, HashMap<CtrId, FE>)], id: &CtrId) -> Option<FE> {
+    let shares: Option<Vec<_>> = contributions
+        .iter()
+        .map(|&(x, ref sums)| sums.get(id).map(|y| Share { x, y: *y }))
+        .collect();
+    shares.and_then(|s| shamir::try_recover_secret(&s).ok())
+}
+
+/// Return the ID of every counter that appears in any of `contributions`,
+/// in order.
+fn all_counter_ids(contributions: &[(FE, HashMap<CtrId, FE>)]) -> Vec<CtrId> {
+    let mut ids = Vec::new();
+    for (_, sums) in contributions.iter() {
+        ids.extend(sums.keys().cloned());
+    }
+    ids.sort_by_key(|id| id.0);
+    ids.dedup();
+    ids
+}
+
+/// Given the X coordinate and summed shares from each of several TRs,
+/// reconstruct the total for every counter.
+///
+/// Every contribution is used, so pass exactly K of them (or more, if
+/// you're sure they're all consistent).
+///
+/// # Errors
+///
+/// Gives an error if there are no contributions, if any X coordinate is
+/// zero or repeated, or if any contribution is missing a counter that
+/// another one has.
+pub fn reconstruct_totals(
+    contributions: &[(FE, HashMap<CtrId, FE>)],
+) -> Result<HashMap<CtrId, u64>, &'static str> {
+    if contributions.is_empty() {
+        return Err("No contributions to reconstruct from.");
+    }
+    let xs = Vec::from_iter(contributions.iter().map(|&(x, _)| x));
+    let interp = LagrangeInterpolator::new(&xs)?;
+    let mut totals = HashMap::new();
+    for id in all_counter_ids(contributions) {
+        let ys: Option<Vec<_>> = contributions
+            .iter()
+            .map(|(_, sums)| sums.get(&id).cloned())
+            .collect();
+        let ys = ys.ok_or("Contribution is missing a counter.")?;
+        totals.insert(id, interp.recover(&ys).value());
+    }
+    Ok(totals)
+}
+
+/// A Coordinator collects the summed shares from the TRs at the end of a
+/// round, and reconstructs the final tally from them.
+///
+/// Each TR computes its sums with `sum_shares()`, and sends them along
+/// with its X coordinate.  Once at least K distinct TRs have contributed,
+/// the coordinator can compute the tally.
+pub struct Coordinator {
+    /// The number of contributions needed to reconstruct.
+    k: usize,
+    /// The contributions we've received so far, in the order they arrived.
+    contributions: Vec<(FE, HashMap<CtrId, FE>)>,
+}
+
+impl Coordinator {
+    /// Create a new Coordinator for a round in which any `k` TRs can
+    /// reconstruct the tally.
+    pub fn new(k: u32) -> Result<Self, &'static str> {
+        if k == 0 {
+            return Err("Invalid parameters: k == 0.");
+        }
+        Ok(Coordinator {
+            k: k as usize,
+            contributions: Vec::new(),
+        })
+    }
+
+    /// Add the summed shares `sums` from the TR with X coordinate `x`.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if `x` is zero, or if we already have a
+    /// contribution for `x`.
+    pub fn add_contribution(
+        &mut self,
+        x: FE,
+        sums: HashMap<CtrId, FE>,
+    ) -> Result<(), &'static str> {
+        if x.is_zero() {
+            return Err("No X coordinate may be zero.");
+        }
+        if self.contributions.iter().any(|&(x2, _)| x2 == x) {
+            return Err("Duplicate X coordinate.");
+        }
+        self.contributions.push((x, sums));
+        Ok(())
+    }
+
+    /// Return the X coordinates of the TRs that have contributed so far,
+    /// in the order they arrived.
+    pub fn contributors(&self) -> Vec<FE> {
+        Vec::from_iter(self.contributions.iter().map(|&(x, _)| x))
+    }
+
+    /// Return true if we have enough contributions to reconstruct the
+    /// tally.
+    pub fn is_ready(&self) -> bool {
+        self.contributions.len() >= self.k
+    }
+
+    /// Reconstruct the tally for every counter, using the first K
+    /// contributions.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if we have fewer than K contributions, or if any of
+    /// the ones we use is missing a counter.
+    pub fn tally(&self) -> Result<HashMap<CtrId, u64>, &'static str> {
+        if !self.is_ready() {
+            return Err("Not enough contributions to reconstruct.");
+        }
+        reconstruct_totals(&self.contributions[..self.k])
+    }
+
+    /// As `tally()`, but wrap the result in a TallyResult.
+    pub fn tally_result(&self) -> Result<TallyResult, &'static str> {
+        self.tally().map(TallyResult::new)
+    }
+}
+
+/// Given the X coordinate and summed shares from each of several TRs,
+/// reconstruct every counter twice, from two different subsets of `k`
+/// TRs, and make sure that the results agree.
+///
+/// We use the first `k` contributions and the last `k` contributions.
+/// If there are at least `2k` contributions, these subsets are
+/// disjoint; otherwise they overlap, and a TR that is in both subsets
+/// can misbehave without being detected.
+///
+/// On success, returns the agreed-upon tally for each counter.  On
+/// failure, returns the IDs of the counters that we could not verify,
+/// in order.  A counter fails verification if the two reconstructions
+/// disagree, or if any TR in either subset has no share for it.  If
+/// there are not more than `k` contributions, we can't cross-check
+/// anything, and every counter fails.
+pub fn cross_check_tally(
+    contributions: &[(FE, HashMap<CtrId, FE>)],
+    k: usize,
+) -> Result<HashMap<CtrId, u64>, Vec<CtrId>> {
+    let ids = all_counter_ids(contributions);
+
+    let n = contributions.len();
+    if k == 0 || n <= k {
+        return Err(ids);
+    }
+    let first = &contributions[..k];
+    let second = &contributions[n - k..];
+
+    let mut tally = HashMap::new();
+    let mut failed = Vec::new();
+    for id in ids {
+        match (recover_counter(first, &id), recover_counter(second, &id)) {
+            (Some(a), Some(b)) if a == b => {
+                tally.insert(id, a.value());
+            }
+            _ => failed.push(id),
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(tally)
+    } else {
+        Err(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use client::CounterSet;
+    use encrypt::keygen::curve25519_seckey_gen;
+    use noise::NoNoise;
+    use rand;
+    use server::*;
+    use shamir::ParamBuilder;
+    use std::slice;
+
+    /// Make keys for `n_trs` TRs, each with its own signing key.
+    fn server_keys(n_trs: u8) -> Vec<ServerKeys> {
+        let mut rng = rand::os::OsRng::new().unwrap();
+        Vec::from_iter((0..n_trs).map(|i| {
+            let enc_secret = curve25519_seckey_gen(&mut rng);
+            ServerKeys {
+                public: TrKeys {
+                    enc_key: enc_secret.public(),
+                    signing_key: [i; 32],
+                },
+                enc_secret,
+            }
+        }))
+    }
+
+    /// Have one client for each entry in `increments` report to the TRs
+    /// in `tr_keys`, K-of-N.  Each client tracks the counters in `counters`,
+    /// adding `increments[i][j]` to `counters[j]`.
+    ///
+    /// Return each client's keypair, along with its report.
+    fn reports(
+        tr_keys: &[TrKeys],
+        k: u32,
+        counters: &[CtrId],
+        increments: &[Vec<u64>],
+    ) -> Vec<(ClientKeyPair, CounterData)> {
+        let mut rng = rand::os::OsRng::new().unwrap();
+        Vec::from_iter(increments.iter().enumerate().map(|(i, incs)| {
+            let client = ClientKeyPair::from_seed(&[i as u8; 32]);
+            let mut ctrs = CounterSet::new(&mut rng, counters, tr_keys, k, NoNoise).unwrap();
+            for (id, v) in counters.iter().zip(incs.iter()) {
+                ctrs.ctr(*id).unwrap().inc_u64(*v);
+            }
+            let data = ctrs.finalize(&mut rng, &client).unwrap();
+            (client, data)
+        }))
+    }
+
+    /// Make `n_trs` TRs and some clients, as `server_keys()` and
+    /// `reports()` would, and have every TR decode every client's report.
+    ///
+    /// Return the TRs' keys, and for each TR, its ClientData from each
+    /// client.
+    fn fixture(
+        n_trs: u8,
+        k: u32,
+        counters: &[CtrId],
+        increments: &[Vec<u64>],
+    ) -> (Vec<ServerKeys>, Vec<Vec<ClientData>>) {
+        let keys = server_keys(n_trs);
+        let tr_keys = Vec::from_iter(keys.iter().map(|k| k.public.clone()));
+        let reports = reports(&tr_keys, k, counters, increments);
+        let decoded = Vec::from_iter(keys.iter().map(|tr| {
+            Vec::from_iter(reports.iter().map(|(client, data)| {
+                let mine = data.tr_data.iter().find(|d| d.keys == tr.public).unwrap();
+                tr.decode_from(client.public(), &data.counter_ids, mine)
+                    .unwrap()
+            }))
+        }));
+        (keys, decoded)
+    }
+
+    /// Share the values in `tally` among `n` TRs, K-of-N, and return each
+    /// TR's contribution.
+    fn contributions(tally: &[(CtrId, u64)], k: u32, n: u32) -> Vec<(FE, HashMap<CtrId, FE>)> {
+        let mut rng = rand::thread_rng();
+        let mut pb = ParamBuilder::new(k, n).unwrap();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let mut result: Vec<(FE, HashMap<CtrId, FE>)> = Vec::new();
+        for &(id, v) in tally.iter() {
+            for (idx, share) in p.share_secret(FE::new(v), &mut rng).into_iter().enumerate() {
+                if result.len() <= idx {
+                    result.push((share.x, HashMap::new()));
+                }
+                result[idx].1.insert(id, share.y);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn period_ratchet() {
+        let mut rng = rand::os::OsRng::new().unwrap();
+        let mut ratchet = PeriodKeyRatchet::new(curve25519_seckey_gen(&mut rng), [9; 32], 5);
+        let old_keys = ratchet.server_keys();
+
+        let reports = reports(
+            slice::from_ref(&old_keys.public),
+            1,
+            &[CtrId(1)],
+            &[vec![3]],
+        );
+        let (ref client_keys, ref data) = reports[0];
+        let client = client_keys.public();
+        assert!(old_keys
+            .decode_from(client, &data.counter_ids, &data.tr_data[0])
+            .is_ok());
+
+        assert!(ratchet.advance_to(4).is_err());
+        ratchet.advance().unwrap();
+        assert_eq!(ratchet.period(), 6);
+        let new_keys = ratchet.server_keys();
+        assert_eq!(new_keys.public.signing_key, old_keys.public.signing_key);
+        assert_eq!(new_keys.public.get_x_coord(), old_keys.public.get_x_coord());
+        assert_ne!(new_keys.enc_secret, old_keys.enc_secret);
+        assert!(new_keys
+            .decode_from(client, &data.counter_ids, &data.tr_data[0])
+            .is_err());
+
+        // Ratcheting is deterministic, so another TR process holding the
+        // same key agrees with us.
+        let mut other = PeriodKeyRatchet::new(old_keys.enc_secret.clone(), [9; 32], 5);
+        other.advance_to(8).unwrap();
+        ratchet.advance_to(8).unwrap();
+        assert_eq!(
+            other.server_keys().enc_secret,
+            ratchet.server_keys().enc_secret
+        );
+    }
+
+    #[test]
+    fn decode_many() {
+        let keys = server_keys(1).remove(0);
+        // Clients with different numbers of counters.
+        let ids = Vec::from_iter((0..4).map(CtrId));
+        let mut all = Vec::new();
+        for n in 1..5 {
+            let increments = Vec::from_iter((0..5).map(|i| vec![i; n]));
+            all.extend(reports(
+                slice::from_ref(&keys.public),
+                1,
+                &ids[..n],
+                &increments,
+            ));
+        }
+        let inputs = Vec::from_iter(
+            all.iter()
+                .map(|(c, r)| (c.public(), &r.counter_ids[..], &r.tr_data[0])),
+        );
+
+        let many = keys.decode_from_many(&inputs).unwrap();
+        assert_eq!(many.len(), inputs.len());
+        for (cd, &(client, counters, data)) in many.iter().zip(inputs.iter()) {
+            let one = keys.decode_from(client, counters, data).unwrap();
+            assert_eq!(cd.client_key, one.client_key);
+            assert_eq!(cd.shares, one.shares);
+        }
+        assert!(keys.decode_from_many(&[]).unwrap().is_empty());
+
+        // The first bad input gives the error.
+        let mut bad = inputs.clone();
+        bad[13].0 = all[0].0.public();
+        bad[17].1 = &[];
+        assert_eq!(keys.decode_from_many(&bad).err(), Some("Bad signature."));
+    }
+
+    #[test]
+    fn shares_from_sums() {
+        let ids = [CtrId(1), CtrId(2)];
+        let increments = [vec![7, 1], vec![80, 1], vec![900, 1]];
+        let (keys, decoded) = fixture(3, 2, &ids, &increments);
+
+        let shares = Vec::from_iter(
+            keys.iter()
+                .zip(decoded.iter())
+                .map(|(tr, d)| tr.sum_as_shares(d)),
+        );
+        for (tr, s) in keys.iter().zip(shares.iter()) {
+            assert_eq!(s.len(), 3);
+            assert!(s.values().all(|sh| sh.x == tr.public.get_x_coord()));
+        }
+
+        for &(id, expected) in [(CtrId(1), 987), (CtrId(2), 3), (CLIENT_COUNT_ID, 3)].iter() {
+            let two = [shares[0][&id].clone(), shares[2][&id].clone()];
+            assert_eq!(shamir::recover_secret(&two).value(), expected);
+        }
+    }
+
+    #[test]
+    fn streaming_tally() {
+        let ids = [CtrId(4), CtrId(2), CtrId(9)];
+        let increments = Vec::from_iter((0..10).map(|i| vec![0, i, 100]));
+        let (_, mut decoded) = fixture(1, 1, &ids, &increments);
+        let decoded = decoded.remove(0);
+
+        let mut tally = Tally::new(&ids);
+        for cd in decoded.iter() {
+            tally.add(cd).unwrap();
+        }
+        assert_eq!(tally.finish(), sum_shares(&decoded));
+
+        // Clients with the wrong counters are rejected.
+        let mut tally = Tally::new(&ids[..2]);
+        assert!(tally.add(&decoded[0]).is_err());
+        let mut tally = Tally::new(&[CtrId(4), CtrId(2), CtrId(9), CtrId(10)]);
+        assert!(tally.add(&decoded[0]).is_err());
+        let mut dup = ClientData {
+            client_key: decoded[0].client_key.clone(),
+            shares: decoded[0].shares.clone(),
+        };
+        dup.shares[0].0 = dup.shares[1].0;
+        let mut tally = Tally::new(&ids);
+        assert!(tally.add(&dup).is_err());
+        assert!(tally.finish().values().all(|v| *v == FE::zero()));
+    }
+
+    #[test]
+    fn subset_sums() {
+        let (keys, decoded) = fixture(2, 2, &[CtrId(1)], &[vec![3], vec![40], vec![500]]);
+        let clients = Vec::from_iter(decoded[0].iter().map(|cd| cd.client_key.clone()));
+
+        let tally = |filter: &dyn Fn(&ClientKey) -> bool| {
+            let contribs = Vec::from_iter(
+                keys.iter()
+                    .zip(decoded.iter())
+                    .map(|(k, cds)| (k.public.get_x_coord(), sum_shares_subset(cds, filter))),
+            );
+            reconstruct_totals(&contribs).unwrap()
+        };
+        let all = tally(&|_| true);
+        assert_eq!(all[&CtrId(1)], 543);
+        assert_eq!(all[&CLIENT_COUNT_ID], 3);
+        let some = tally(&|c| *c != clients[1]);
+        assert_eq!(some[&CtrId(1)], 503);
+        assert_eq!(some[&CLIENT_COUNT_ID], 2);
+        assert!(tally(&|_| false).is_empty());
+    }
+
+    #[test]
+    fn reject_unreduced_counter() {
+        use byteorder::{ByteOrder, NetworkEndian};
+        use encrypt::hybrid::PrivcountEncryptor;
+        use encrypt::Encryptor;
+        use math::PRIME_ORDER;
+
+        let mut rng = rand::os::OsRng::new().unwrap();
+        let keys = server_keys(1).remove(0);
+        let (client_keys, mut data) =
+            reports(slice::from_ref(&keys.public), 1, &[CtrId(1)], &[vec![0]]).remove(0);
+        let client = client_keys.public();
+        assert!(keys
+            .decode_from(client, &data.counter_ids, &data.tr_data[0])
+            .is_ok());
+
+        // Replace the first Y coordinate with one that isn't reduced.
+        let mut ys = vec![0; 16];
+        NetworkEndian::write_u64(&mut ys[..8], PRIME_ORDER);
+        let enc = PrivcountEncryptor::new(&keys.public.enc_key, &keys.public.signing_key);
+        data.tr_data[0].encrypted_counters =
+            enc.encrypt(&ys, Y_ENCRYPTION_TWEAK, &mut rng).unwrap();
+        assert_eq!(
+            keys.decode_from(client, &data.counter_ids, &data.tr_data[0])
+                .err(),
+            Some("Bad signature.")
+        );
+        data.tr_data[0].sign(&data.counter_ids, &client_keys);
+        assert_eq!(
+            keys.decode_from(client, &data.counter_ids, &data.tr_data[0])
+                .err(),
+            Some("BadFE")
+        );
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_server_keys() {
+        let mut keys = server_keys(1).remove(0);
+        keys.zeroize();
+        assert_eq!(keys.enc_secret.as_bytes(), &[0; 32]);
+        drop(keys);
+    }
+
+    #[test]
+    fn coordinator() {
+        let tally = [(CtrId(1), 10), (CtrId(7), 0), (CtrId(3), 999)];
+        let contribs = contributions(&tally, 3, 5);
+        let expected: HashMap<_, _> = tally.iter().cloned().collect();
+
+        assert!(Coordinator::new(0).is_err());
+        let mut c = Coordinator::new(3).unwrap();
+        assert!(c.tally().is_err());
+        for (x, sums) in contribs[1..4].iter().cloned() {
+            assert!(!c.is_ready());
+            c.add_contribution(x, sums).unwrap();
+        }
+        assert!(c.is_ready());
+        assert_eq!(
+            c.contributors(),
+            vec![contribs[1].0, contribs[2].0, contribs[3].0]
+        );
+        assert_eq!(c.tally(), Ok(expected.clone()));
+
+        // Duplicates and zeros are rejected.
+        let (x, sums) = contribs[2].clone();
+        assert!(c.add_contribution(x, sums.clone()).is_err());
+        assert!(c.add_contribution(FE::new(0), sums).is_err());
+        assert_eq!(c.contributors().len(), 3);
+
+        // reconstruct_totals on its own.
+        assert_eq!(reconstruct_totals(&contribs[2..5]), Ok(expected));
+        assert!(reconstruct_totals(&[]).is_err());
+        let mut missing = contribs.clone();
+        missing[0].1.remove(&CtrId(7));
+        assert!(reconstruct_totals(&missing[0..3]).is_err());
+    }
+
+    #[test]
+    fn cross_check() {
+        let tally = [(CtrId(1), 10), (CtrId(2), 0), (CtrId(3), 12345)];
+        let mut contribs = contributions(&tally, 3, 6);
+
+        let expected: HashMap<_, _> = tally.iter().cloned().collect();
+        assert_eq!(cross_check_tally(&contribs, 3), Ok(expected.clone()));
+        assert_eq!(cross_check_tally(&contribs[..4], 3), Ok(expected));
+
+        // Not enough TRs to cross-check.
+        assert_eq!(
+            cross_check_tally(&contribs[..3], 3),
+            Err(vec![CtrId(1), CtrId(2), CtrId(3)])
+        );
+
+        // One misbehaving TR.
+        *contribs[5].1.get_mut(&CtrId(2)).unwrap() += FE::new(1);
+        assert_eq!(cross_check_tally(&contribs, 3), Err(vec![CtrId(2)]));
+
+        // One TR missing a counter.
+        contribs[0].1.remove(&CtrId(3));
+        assert_eq!(
+            cross_check_tally(&contribs, 3),
+            Err(vec![CtrId(2), CtrId(3)])
+        );
+    }
+}
@@ -21,6 +21,23 @@
 //! things that support addition, subtraction, multiplication, and
 //! division, with the properties that you'd want.)
 //!
+//! # Verifiability
+//!
+//! Nothing here lets a shareholder check that its share is consistent
+//! with everyone else's: a dealer can hand out shares of different
+//! polynomials, and the shareholders can't tell.
+//!
+//! Feldman-style verifiable secret sharing would fix that, by publishing
+//! `g^a` for each coefficient `a` of the polynomial.  But it needs a
+//! group of prime order `PRIME_ORDER` in which discrete logarithms are
+//! hard, so that the commitments' exponents follow the same arithmetic
+//! as the shares.  Our field doesn't provide one: powers of an FE
+//! generator have exponents modulo `PRIME_ORDER - 1`, not `PRIME_ORDER`,
+//! so honest shares wouldn't verify.  And discrete logarithms in a 62-bit
+//! field are easy, so the commitments would reveal the coefficients
+//! anyway.  Adding VSS means adding an elliptic-curve group whose order
+//! matches the field, which is a bigger change than it sounds.
+//!
 //! # Examples
 //! ```
 //! extern crate rand;
@@ -67,8 +84,13 @@
 //! # Ok(())
 //! # }
 
+use math::FE;
 use num::traits::NumRef;
 use rand::{Rand, Rng};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::hash::Hash;
 use std::iter::FromIterator;
 use std::ops::Sub;
 
@@ -92,10 +114,13 @@ pub struct Params<N> {
     k: u32,
     n: u32,
     x_coordinates: Vec<N>,
+    /// X coordinates whose shares must be present in any reconstruction
+    /// that `can_reconstruct()` will approve.
+    mandatory_x_coordinates: Vec<N>,
 }
 
 /// A Share: one of the N split shares of a secret.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Share<N> {
     /// The X coordinate for this share.
     ///
@@ -105,9 +130,53 @@ pub struct Share<N> {
     pub y: N,
 }
 
+/// One party's shares of many secrets: the Y coordinates of every
+/// secret's share at a single X coordinate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VecShare<N> {
+    /// The X coordinate for these shares.
+    pub x: N,
+    /// The Y coordinate for each secret's share, in the same order as the
+    /// secrets.
+    pub ys: Vec<N>,
+}
+
+impl Share<FE> {
+    /// Encode this share as 16 bytes: the big-endian encoding of X,
+    /// followed by that of Y.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut result = [0; 16];
+        result[..8].copy_from_slice(&self.x.to_bytes());
+        result[8..].copy_from_slice(&self.y.to_bytes());
+        result
+    }
+
+    /// Decode a share from the 16-byte encoding produced by `to_bytes()`.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if `b` is the wrong length, if either coordinate
+    /// isn't a canonical FE encoding, or if X is zero.
+    pub fn from_bytes(b: &[u8]) -> Result<Share<FE>, &'static str> {
+        if b.len() != 16 {
+            return Err("Wrong length for encoded share.");
+        }
+        let mut x = [0; 8];
+        let mut y = [0; 8];
+        x.copy_from_slice(&b[..8]);
+        y.copy_from_slice(&b[8..]);
+        let x = FE::from_bytes(&x).ok_or("X coordinate out of range.")?;
+        let y = FE::from_bytes(&y).ok_or("Y coordinate out of range.")?;
+        if x.value() == 0 {
+            return Err("No X coordinate may be zero.");
+        }
+        Ok(Share { x, y })
+    }
+}
+
 impl<N> ParamBuilder<N>
 where
-    N: NumRef + Clone + Rand,
+    N: NumRef + Clone + Rand + Hash + Eq,
 {
     /// Create a new ParamBuilder.
     pub fn new(k: u32, n: u32) -> Result<Self, &'static str> {
@@ -122,6 +191,7 @@ where
                 k,
                 n,
                 x_coordinates: Vec::new(),
+                mandatory_x_coordinates: Vec::new(),
             },
         })
     }
@@ -136,6 +206,29 @@ where
         self.p.x_coordinates.push(x.clone());
     }
 
+    /// Add every X coordinate in `xs`, in order.
+    ///
+    /// As with `add_x_coordinate()`, nothing is checked until
+    /// `finalize()`: if this leaves us with more than N X coordinates,
+    /// `finalize()` will give an error.
+    pub fn add_x_coordinates(&mut self, xs: &[N]) {
+        self.p.x_coordinates.extend_from_slice(xs);
+    }
+
+    /// Mark an X coordinate as mandatory: its share will need to be present
+    /// in any set of shares that `Params::can_reconstruct()` approves.
+    ///
+    /// This is useful when (for example) a designated TR must take part
+    /// in every reconstruction.  The X coordinate must also be added with
+    /// `add_x_coordinate()`.
+    ///
+    /// Note that this is a policy layer, not a cryptographic guarantee:
+    /// the sharing itself is unchanged, so any K colluding shareholders
+    /// can still reconstruct the secret without the mandatory ones.
+    pub fn add_mandatory_x_coordinate(&mut self, x: &N) {
+        self.p.mandatory_x_coordinates.push(x.clone());
+    }
+
     /// Fill in the X coordinates randomly
     pub fn fill_x_coordinates<R: Rng>(&mut self, rng: &mut R) {
         while self.p.x_coordinates.len() < self.p.n as usize {
@@ -148,12 +241,25 @@ where
 
     /// Convert a ParamBuilder to a Params.
     ///
-    /// Requires that the X coordinates have been filled with nonzero values.
+    /// Requires that the X coordinates have been filled with distinct
+    /// nonzero values.
     pub fn finalize(self) -> Result<Params<N>, &'static str> {
+        let distinct: HashSet<&N> = self.p.x_coordinates.iter().collect();
         if self.p.x_coordinates.contains(&N::zero()) {
             Err("No X coordinate may be zero.")
+        } else if distinct.len() != self.p.x_coordinates.len() {
+            Err("Duplicate X coordinate.")
         } else if self.p.x_coordinates.len() != self.p.n as usize {
             Err("Length mismatch in finalize.")
+        } else if self.p.mandatory_x_coordinates.len() > self.p.k as usize {
+            Err("More mandatory X coordinates than k.")
+        } else if self
+            .p
+            .mandatory_x_coordinates
+            .iter()
+            .any(|x| !self.p.x_coordinates.contains(x))
+        {
+            Err("Mandatory X coordinate is not in use.")
         } else {
             Ok(self.p)
         }
@@ -173,6 +279,21 @@ impl<N> Params<N>
 where
     N: NumRef + Rand + Clone,
 {
+    /// Return the number of shares needed to reconstruct a secret.
+    pub fn k(&self) -> u32 {
+        self.k
+    }
+
+    /// Return the number of shares that we generate.
+    pub fn n(&self) -> u32 {
+        self.n
+    }
+
+    /// Return the X coordinates of the shares that we generate, in order.
+    pub fn x_coordinates(&self) -> &[N] {
+        &self.x_coordinates
+    }
+
     /// Split a secret 'N' according to the given parameters.
     ///
     /// (The security of this scheme is only as good as the RNG you use.)
@@ -191,17 +312,265 @@ where
             y: evaluate_poly_at(&poly, &x),
         }))
     }
+
+    /// Split a secret according to the given parameters, and blind each
+    /// share by subtracting `masks[i] + blind` from its Y coordinate.
+    ///
+    /// This is the blinding that privcount clients use: each TR's share
+    /// is hidden by a mask that only that TR can regenerate, and by a
+    /// common blind that the client adds back in (along with its
+    /// increments) when it's done counting.  Adding `masks[i]` and
+    /// `blind` back to the Y coordinate of the i'th share gives a share
+    /// of `secret`.
+    ///
+    /// (Because the Lagrange weights always sum to one, adding or
+    /// subtracting the same value from every share adds or subtracts it
+    /// from the secret.  So if every mask is the same value `m`, the
+    /// blinded shares are shares of `secret - m - blind`.)
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is not exactly one mask per share.
+    pub fn share_secret_blinded<R: Rng>(
+        &self,
+        secret: N,
+        masks: &[N],
+        blind: N,
+        rng: &mut R,
+    ) -> Vec<Share<N>> {
+        let shares = self.share_secret(secret, rng);
+        self.blind_shares(shares, masks, &blind)
+    }
+
+    /// Blind a set of shares that we generated, by subtracting
+    /// `masks[i] + blind` from the Y coordinate of the i'th share.
+    ///
+    /// This is the second half of `share_secret_blinded()`, for callers
+    /// that generate their shares some other way: with `share_many()`,
+    /// for example.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is not exactly one share and one mask for each of
+    /// our X coordinates.
+    pub fn blind_shares(&self, shares: Vec<Share<N>>, masks: &[N], blind: &N) -> Vec<Share<N>> {
+        assert_eq!(shares.len(), self.x_coordinates.len());
+        assert_eq!(masks.len(), self.x_coordinates.len());
+        Vec::from_iter(
+            shares
+                .into_iter()
+                .zip(masks.iter())
+                .map(|(share, mask)| Share {
+                    x: share.x,
+                    y: share.y - mask - blind,
+                }),
+        )
+    }
+
+    /// Split every secret in `secrets` according to the given parameters.
+    ///
+    /// The result has one entry per secret, containing the shares of that
+    /// secret in the same order that `share_secret()` would give them.
+    /// Every secret gets its own independent polynomial, so this is
+    /// exactly as secure as calling `share_secret()` on each one; in fact,
+    /// given the same RNG, it produces the same shares.
+    ///
+    /// This is `share_secrets()`, with the results grouped by secret
+    /// instead of by X coordinate.
+    ///
+    /// (The security of this scheme is only as good as the RNG you use.)
+    pub fn share_many<R: Rng>(&self, secrets: &[N], rng: &mut R) -> Vec<Vec<Share<N>>> {
+        let by_x = self.share_secrets(secrets, rng);
+        let mut result = Vec::from_iter(secrets.iter().map(|_| Vec::with_capacity(by_x.len())));
+        for (x, ys) in by_x {
+            for (y, shares) in ys.into_iter().zip(result.iter_mut()) {
+                shares.push(Share { x: x.clone(), y });
+            }
+        }
+        result
+    }
+
+    /// Split every secret in `secrets`, grouping the results by X
+    /// coordinate.
+    ///
+    /// The result has one `(x, ys)` entry per X coordinate, in the same
+    /// order as our parameters.  `ys` holds that X coordinate's share of
+    /// every secret, in the same order as `secrets`.  That's the layout a
+    /// privcount client wants, since each TR gets all of its shares
+    /// together.
+    ///
+    /// Given the same RNG, the Y values are the same ones that calling
+    /// `share_secret()` on each secret in turn would give.  We reuse a
+    /// single polynomial buffer across all the secrets, though.
+    ///
+    /// (The security of this scheme is only as good as the RNG you use.)
+    pub fn share_secrets<R: Rng>(&self, secrets: &[N], rng: &mut R) -> Vec<(N, Vec<N>)> {
+        let mut result = Vec::from_iter(
+            self.x_coordinates
+                .iter()
+                .map(|x| (x.clone(), Vec::with_capacity(secrets.len()))),
+        );
+        let mut poly = Vec::with_capacity(self.k as usize);
+        for secret in secrets.iter() {
+            poly.clear();
+            for _ in 1..(self.k) {
+                poly.push(rng.gen());
+            }
+            poly.push(secret.clone());
+            for (x, ys) in result.iter_mut() {
+                ys.push(evaluate_poly_at(&poly, x));
+            }
+        }
+        result
+    }
+
+    /// Split every secret in `secrets`, returning one VecShare per X
+    /// coordinate.
+    ///
+    /// This is the same as `share_secrets()`, but with the results in
+    /// VecShare objects that `recover_secret_vec()` can take.
+    ///
+    /// (The security of this scheme is only as good as the RNG you use.)
+    pub fn share_secret_vec<R: Rng>(&self, secrets: &[N], rng: &mut R) -> Vec<VecShare<N>> {
+        Vec::from_iter(
+            self.share_secrets(secrets, rng)
+                .into_iter()
+                .map(|(x, ys)| VecShare { x, ys }),
+        )
+    }
+
+    /// Refresh a set of shares, so that they still share the same secret
+    /// but have new, independently random Y coordinates.
+    ///
+    /// We do this by sharing zero with a fresh random polynomial, and
+    /// adding its value at each share's X coordinate to that share.  The
+    /// X coordinates stay the same.  Old shares and refreshed shares
+    /// can't be combined with one another, so an attacker who learns
+    /// fewer than K shares before a refresh and fewer than K after it
+    /// still learns nothing about the secret.
+    ///
+    /// (In a real deployment, each shareholder needs to receive its
+    /// delta from the others, and then throw its old share away.  This
+    /// function just does the arithmetic.)
+    pub fn refresh_shares<R: Rng>(&self, existing: &[Share<N>], rng: &mut R) -> Vec<Share<N>> {
+        // Generate a random polynomial with Y intercept of zero.
+        let mut poly = Vec::with_capacity(self.k as usize);
+        for _ in 1..(self.k) {
+            poly.push(rng.gen());
+        }
+        poly.push(N::zero());
+        debug_assert_eq!(poly.len(), self.k as usize);
+
+        Vec::from_iter(existing.iter().map(|share| Share {
+            x: share.x.clone(),
+            y: share.y.clone() + evaluate_poly_at(&poly, &share.x),
+        }))
+    }
+
+    /// Return true if our policy allows reconstructing a secret from
+    /// `shares`.
+    ///
+    /// That is: there must be at least K shares with distinct X
+    /// coordinates from these parameters, and every mandatory X
+    /// coordinate must be among them.
+    pub fn can_reconstruct(&self, shares: &[Share<N>]) -> bool {
+        let mut distinct: Vec<&N> = Vec::new();
+        for sh in shares.iter() {
+            if self.x_coordinates.contains(&sh.x) && !distinct.contains(&&sh.x) {
+                distinct.push(&sh.x);
+            }
+        }
+        distinct.len() >= self.k as usize
+            && self
+                .mandatory_x_coordinates
+                .iter()
+                .all(|x| distinct.contains(&x))
+    }
+}
+
+/// An error from `try_recover_secret()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoverError {
+    /// There were no shares at all.
+    Empty,
+    /// Two shares had the same X coordinate.
+    DuplicateX,
+    /// A share's X coordinate was zero, which collides with the secret
+    /// itself.
+    ZeroX,
+}
+
+impl RecoverError {
+    /// Return a description of this error.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            RecoverError::Empty => "No shares to recover from.",
+            RecoverError::DuplicateX => "Duplicate X coordinate.",
+            RecoverError::ZeroX => "No X coordinate may be zero.",
+        }
+    }
+}
+
+impl Display for RecoverError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<RecoverError> for &'static str {
+    fn from(e: RecoverError) -> &'static str {
+        e.as_str()
+    }
 }
 
 /// Reconstruct a secret from any K of its shares.
 ///
 /// (If the number of shares is not the same K used to split the
 /// secret, the output will be wrong.)
+///
+/// # Panics
+///
+/// Panics if `shares` is empty, or if any two shares have the same X
+/// coordinate, or if any X coordinate is zero.  Use
+/// `try_recover_secret()` if the shares aren't already known to be valid.
 pub fn recover_secret<'a, N>(shares: &'a [Share<N>]) -> N
 where
     &'a N: Sub<&'a N, Output = N>,
     N: NumRef + 'a,
 {
+    match try_recover_secret(shares) {
+        Ok(secret) => secret,
+        Err(e) => panic!("Invalid shares: {}", e),
+    }
+}
+
+/// Reconstruct a secret from any K of its shares, checking that the
+/// shares are usable.
+///
+/// (If the number of shares is not the same K used to split the
+/// secret, the output will still be wrong.)
+///
+/// # Errors
+///
+/// Gives an error if `shares` is empty, if any two shares have the same
+/// X coordinate, or if any X coordinate is zero.
+pub fn try_recover_secret<'a, N>(shares: &'a [Share<N>]) -> Result<N, RecoverError>
+where
+    &'a N: Sub<&'a N, Output = N>,
+    N: NumRef + 'a,
+{
+    if shares.is_empty() {
+        return Err(RecoverError::Empty);
+    }
+    for (i, sh) in shares.iter().enumerate() {
+        if sh.x.is_zero() {
+            return Err(RecoverError::ZeroX);
+        }
+        if shares[..i].iter().any(|sh2| sh2.x == sh.x) {
+            return Err(RecoverError::DuplicateX);
+        }
+    }
+
     let mut accumulator = N::zero();
     for (i, sh) in shares.iter().enumerate() {
         let mut numerator = N::one();
@@ -216,24 +585,1484 @@ where
         }
         accumulator = accumulator + (numerator * &sh.y) / denominator;
     }
-    accumulator
+    Ok(accumulator)
 }
 
-#[cfg(test)]
-mod tests {
-    use math::*;
-    use rand;
-    use shamir::*;
-    #[test]
-    fn demo() {
-        let mut pb = ParamBuilder::new(3, 5).unwrap();
-        let mut rng = rand::thread_rng();
-        pb.fill_x_coordinates(&mut rng);
-        let p = pb.finalize().unwrap();
-        let shares = p.share_secret(FE::new(12345), &mut rng);
-        assert_eq!(shares.len(), 5);
-        println!("{:?}", shares);
-        let result = recover_secret(&shares[0..3]);
-        assert_eq!(result, FE::new(12345));
+/// Reconstruct a secret from any K of its shares, checking that we never
+/// divide by zero.
+///
+/// This is `try_recover_secret()` with its error converted to a string.
+/// A Lagrange denominator is zero exactly when two shares have the same X
+/// coordinate; `try_recover_secret()` rejects those shares up front.
+///
+/// (If the number of shares is not the same K used to split the
+/// secret, the output will still be wrong.)
+pub fn recover_secret_safe<'a, N>(shares: &'a [Share<N>]) -> Result<N, &'static str>
+where
+    &'a N: Sub<&'a N, Output = N>,
+    N: NumRef + 'a,
+{
+    try_recover_secret(shares).map_err(|e| e.as_str())
+}
+
+/// An error from `recover_secret_correcting()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// K was zero, or there were fewer than K shares.
+    TooFewShares,
+    /// Two shares had the same X coordinate.
+    DuplicateX,
+    /// A share's X coordinate was zero.
+    ZeroX,
+    /// Too many shares were wrong for us to correct them.
+    TooManyErrors,
+}
+
+impl DecodeError {
+    /// Return a description of this error.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DecodeError::TooFewShares => "Not enough shares to decode.",
+            DecodeError::DuplicateX => "Duplicate X coordinate.",
+            DecodeError::ZeroX => "No X coordinate may be zero.",
+            DecodeError::TooManyErrors => "Too many corrupted shares.",
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<DecodeError> for &'static str {
+    fn from(e: DecodeError) -> &'static str {
+        e.as_str()
+    }
+}
+
+/// Reconstruct a secret that was split with threshold `k`, correcting
+/// any shares that have been corrupted.
+///
+/// Shamir shares are the codewords of a Reed-Solomon code, so given `n`
+/// shares, we can find the right polynomial even if up to `(n-k)/2` of
+/// them are wrong.  We use the Berlekamp-Welch algorithm: we solve for
+/// an "error locator" polynomial E of degree `e = (n-k)/2` that is zero
+/// at every bad share, and a polynomial Q of degree less than `k+e` with
+/// `Q(x) = y*E(x)` at every share.  Then the sharing polynomial is `Q/E`.
+///
+/// This costs a Gaussian elimination over `n` equations, so it's much
+/// slower than `recover_secret()`.  Use it when some shareholders might
+/// be faulty or malicious, and you have extra shares to spare.
+///
+/// # Errors
+///
+/// Gives an error if `k` is zero, if there are fewer than `k` shares, if
+/// any two shares have the same X coordinate, or if any X coordinate is
+/// zero.  Gives `DecodeError::TooManyErrors` if more than `(n-k)/2`
+/// shares disagree with the polynomial we find.  (If the shares are
+/// badly enough corrupted, though, they may happen to decode to the
+/// wrong secret instead.)
+pub fn recover_secret_correcting<N>(shares: &[Share<N>], k: usize) -> Result<N, DecodeError>
+where
+    N: NumRef + Clone,
+{
+    let n = shares.len();
+    if k == 0 || n < k {
+        return Err(DecodeError::TooFewShares);
+    }
+    for (i, sh) in shares.iter().enumerate() {
+        if sh.x.is_zero() {
+            return Err(DecodeError::ZeroX);
+        }
+        if shares[..i].iter().any(|sh2| sh2.x == sh.x) {
+            return Err(DecodeError::DuplicateX);
+        }
+    }
+
+    // All the polynomials here are stored from the 0th-order term up,
+    // which is the opposite of `evaluate_poly_at()`.
+    let e = (n - k) / 2;
+    let n_unknowns = k + 2 * e;
+
+    // One equation per share, with unknowns q_0..q_{k+e-1} and
+    // e_0..e_{e-1}, where E is monic:
+    //     sum(q_j * x^j) - y * sum(e_j * x^j) = y * x^e
+    let mut rows = Vec::from_iter(shares.iter().map(|sh| {
+        let mut row = Vec::with_capacity(n_unknowns + 1);
+        let mut power = N::one();
+        for _ in 0..(k + e) {
+            row.push(power.clone());
+            power = power * &sh.x;
+        }
+        let mut power = N::one();
+        for _ in 0..e {
+            row.push(N::zero() - sh.y.clone() * &power);
+            power = power * &sh.x;
+        }
+        row.push(sh.y.clone() * &power);
+        row
+    }));
+    let solution = solve_linear_system(&mut rows, n_unknowns).ok_or(DecodeError::TooManyErrors)?;
+
+    let mut locator = solution[k + e..].to_vec();
+    locator.push(N::one());
+    let (poly, remainder) = divide_poly_by_monic(&solution[..k + e], &locator);
+    if remainder.iter().any(|c| !c.is_zero()) {
+        return Err(DecodeError::TooManyErrors);
+    }
+
+    let n_wrong = shares
+        .iter()
+        .filter(|sh| {
+            let y = poly
+                .iter()
+                .rev()
+                .fold(N::zero(), |acc: N, t: &N| acc * &sh.x + t);
+            y != sh.y
+        })
+        .count();
+    if n_wrong > e {
+        return Err(DecodeError::TooManyErrors);
+    }
+    Ok(poly[0].clone())
+}
+
+/// Helper: Solve a system of linear equations by Gauss-Jordan
+/// elimination.
+///
+/// Each row holds the coefficients of `n_vars` unknowns, followed by the
+/// right-hand side.  The rows are clobbered.  If there are many
+/// solutions, we set every free variable to zero; if there are none, we
+/// return None.
+fn solve_linear_system<N>(rows: &mut [Vec<N>], n_vars: usize) -> Option<Vec<N>>
+where
+    N: NumRef + Clone,
+{
+    let mut pivot_cols = Vec::new();
+    for col in 0..n_vars {
+        let r = pivot_cols.len();
+        let found = (r..rows.len()).find(|&i| !rows[i][col].is_zero());
+        let p = match found {
+            Some(p) => p,
+            None => continue,
+        };
+        rows.swap(r, p);
+
+        let inverse = N::one() / rows[r][col].clone();
+        for c in rows[r].iter_mut() {
+            *c = c.clone() * &inverse;
+        }
+        let pivot_row = rows[r].clone();
+        for (i, row) in rows.iter_mut().enumerate() {
+            if i == r || row[col].is_zero() {
+                continue;
+            }
+            let factor = row[col].clone();
+            for (c, pc) in row.iter_mut().zip(pivot_row.iter()) {
+                *c = c.clone() - pc.clone() * &factor;
+            }
+        }
+        pivot_cols.push(col);
+        if pivot_cols.len() == rows.len() {
+            break;
+        }
+    }
+
+    // Any row without a pivot is now all zeros on the left, so it had
+    // better be zero on the right too.
+    if rows[pivot_cols.len()..]
+        .iter()
+        .any(|row| !row[n_vars].is_zero())
+    {
+        return None;
+    }
+    let mut solution = vec![N::zero(); n_vars];
+    for (row, col) in rows.iter().zip(pivot_cols.iter()) {
+        solution[*col] = row[n_vars].clone();
+    }
+    Some(solution)
+}
+
+/// Helper: Divide the polynomial `num` by the monic polynomial `den`, and
+/// return the quotient and the remainder.  Both inputs are stored from
+/// the 0th-order term up, and so are both outputs.
+fn divide_poly_by_monic<N>(num: &[N], den: &[N]) -> (Vec<N>, Vec<N>)
+where
+    N: NumRef + Clone,
+{
+    debug_assert!(den.len() <= num.len());
+    let mut remainder = num.to_vec();
+    let mut quotient = vec![N::zero(); num.len() + 1 - den.len()];
+    for i in (0..quotient.len()).rev() {
+        let coefficient = remainder[i + den.len() - 1].clone();
+        for (j, d) in den.iter().enumerate() {
+            remainder[i + j] = remainder[i + j].clone() - coefficient.clone() * d;
+        }
+        quotient[i] = coefficient;
+    }
+    remainder.truncate(den.len() - 1);
+    (quotient, remainder)
+}
+
+/// Reconstructs many secrets whose shares all have the same X
+/// coordinates.
+///
+/// The Lagrange weights for a set of X coordinates don't depend on the Y
+/// coordinates at all, so when (for example) we're reconstructing every
+/// counter from the same K TRs, we can compute the weights once and
+/// reuse them.  Each reconstruction is then just a dot product: K
+/// multiplications, rather than `recover_secret()`'s `2k^2`
+/// multiplications and K inversions.
+///
+/// # Examples
+/// ```
+/// extern crate rand;
+/// extern crate privcount;
+/// use privcount::{FE, shamir};
+/// # fn main() -> Result<(), &'static str> {
+/// let mut rng = rand::os::OsRng::new().unwrap();
+/// let mut builder = shamir::ParamBuilder::new(2, 3)?;
+/// builder.fill_x_coordinates(&mut rng);
+/// let params = builder.finalize()?;
+/// let a = params.share_secret(FE::new(10), &mut rng);
+/// let b = params.share_secret(FE::new(20), &mut rng);
+///
+/// let interp = shamir::LagrangeInterpolator::new(&[a[0].x, a[2].x])?;
+/// assert_eq!(interp.recover(&[a[0].y, a[2].y]), FE::new(10));
+/// assert_eq!(interp.recover(&[b[0].y, b[2].y]), FE::new(20));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct LagrangeInterpolator<N> {
+    /// The X coordinates that we interpolate from.
+    xs: Vec<N>,
+    /// The Lagrange weight for each X coordinate, evaluated at zero.
+    weights: Vec<N>,
+}
+
+impl<N> LagrangeInterpolator<N>
+where
+    N: NumRef + Clone,
+{
+    /// Create a new LagrangeInterpolator for shares with the X
+    /// coordinates `xs`, in that order.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if `xs` is empty, if any two X coordinates are the
+    /// same, or if any X coordinate is zero.
+    pub fn new(xs: &[N]) -> Result<Self, RecoverError> {
+        if xs.is_empty() {
+            return Err(RecoverError::Empty);
+        }
+        for (i, x) in xs.iter().enumerate() {
+            if x.is_zero() {
+                return Err(RecoverError::ZeroX);
+            }
+            if xs[..i].contains(x) {
+                return Err(RecoverError::DuplicateX);
+            }
+        }
+
+        let weights = Vec::from_iter(xs.iter().enumerate().map(|(i, x)| {
+            let mut numerator = N::one();
+            let mut denominator = N::one();
+            for (j, x2) in xs.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+                numerator = numerator * x2;
+                denominator = denominator * (x2.clone() - x);
+            }
+            numerator / denominator
+        }));
+        Ok(LagrangeInterpolator {
+            xs: xs.to_vec(),
+            weights,
+        })
+    }
+
+    /// Return the X coordinates that this interpolator expects.
+    pub fn x_coordinates(&self) -> &[N] {
+        &self.xs
+    }
+
+    /// Reconstruct a secret from the Y coordinates of its shares.
+    /// `ys[i]` must be the Y coordinate for the i'th X coordinate passed
+    /// to `new()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there isn't exactly one Y coordinate per X coordinate.
+    pub fn recover(&self, ys: &[N]) -> N {
+        assert_eq!(ys.len(), self.weights.len());
+        self.weights
+            .iter()
+            .zip(ys.iter())
+            .fold(N::zero(), |acc, (w, y)| acc + w.clone() * y)
+    }
+}
+
+/// Reconstruct every secret from any K VecShares, as generated by
+/// `Params::share_secret_vec()`.
+///
+/// This computes the Lagrange weights once, and reuses them for every
+/// secret.
+///
+/// # Panics
+///
+/// Panics if `shares` is empty, if any two shares have the same X
+/// coordinate, if any X coordinate is zero, or if the shares hold
+/// different numbers of Y coordinates.
+pub fn recover_secret_vec<N>(shares: &[VecShare<N>]) -> Vec<N>
+where
+    N: NumRef + Clone,
+{
+    let xs = Vec::from_iter(shares.iter().map(|s| s.x.clone()));
+    let interp = match LagrangeInterpolator::new(&xs) {
+        Ok(interp) => interp,
+        Err(e) => panic!("Invalid shares: {}", e),
+    };
+    let n_secrets = shares[0].ys.len();
+    assert!(
+        shares.iter().all(|s| s.ys.len() == n_secrets),
+        "Shares hold different numbers of secrets."
+    );
+
+    let mut ys = Vec::with_capacity(shares.len());
+    Vec::from_iter((0..n_secrets).map(|i| {
+        ys.clear();
+        ys.extend(shares.iter().map(|s| s.ys[i].clone()));
+        interp.recover(&ys)
+    }))
+}
+
+/// Reconstructs a secret from shares that arrive one at a time.
+///
+/// Where `recover_secret()` uses Lagrange interpolation, and so has to
+/// start over whenever a share is added, this type keeps the
+/// interpolating polynomial in Newton's divided-difference form.  Adding
+/// the m'th share updates that form with O(m) field operations, and the
+/// current secret is always available for free.
+///
+/// Once K shares have been added, the secret is fully determined: adding
+/// more consistent shares won't change it.  (With fewer than K shares,
+/// `current_secret()` is meaningless.)
+///
+/// # Examples
+/// ```
+/// extern crate rand;
+/// extern crate privcount;
+/// use privcount::{FE, shamir};
+/// # fn main() -> Result<(), &'static str> {
+/// let mut rng = rand::os::OsRng::new().unwrap();
+/// let mut builder = shamir::ParamBuilder::new(3, 5)?;
+/// builder.fill_x_coordinates(&mut rng);
+/// let shares = builder.finalize()?.share_secret(FE::new(99), &mut rng);
+///
+/// let mut r = shamir::IncrementalReconstructor::new();
+/// for share in shares {
+///     r.add_share(share)?;
+///     if r.n_shares() >= 3 {
+///         assert_eq!(r.current_secret(), FE::new(99));
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct IncrementalReconstructor<N> {
+    /// The X coordinates of the shares so far, in the order they arrived.
+    xs: Vec<N>,
+    /// The last row of the divided-difference table: entry `j` is the
+    /// divided difference over the last `j+1` X coordinates.
+    row: Vec<N>,
+    /// The value of the current interpolating polynomial at zero.
+    secret: N,
+    /// The product of `(0 - x)` over all the X coordinates so far.
+    basis_at_zero: N,
+}
+
+impl<N> IncrementalReconstructor<N>
+where
+    N: NumRef + Clone,
+{
+    /// Create a new IncrementalReconstructor with no shares.
+    pub fn new() -> Self {
+        IncrementalReconstructor {
+            xs: Vec::new(),
+            row: Vec::new(),
+            secret: N::zero(),
+            basis_at_zero: N::one(),
+        }
+    }
+
+    /// Add a share to this reconstructor, and update the secret.
+    ///
+    /// # Errors
+    ///
+    /// Gives an error if we already have a share with the same X
+    /// coordinate.
+    pub fn add_share(&mut self, share: Share<N>) -> Result<(), &'static str> {
+        if self.xs.contains(&share.x) {
+            return Err("Duplicate X coordinate.");
+        }
+
+        // Compute the new last row of the divided-difference table, from
+        // the old last row and the new point.
+        let mut new_row = Vec::with_capacity(self.row.len() + 1);
+        new_row.push(share.y);
+        for (j, old) in self.row.iter().enumerate() {
+            let x_j = &self.xs[self.xs.len() - 1 - j];
+            let diff = (new_row[j].clone() - old) / (share.x.clone() - x_j);
+            new_row.push(diff);
+        }
+
+        // The last entry is the new Newton coefficient: it multiplies
+        // the product of (x - x_i) over all the previous X coordinates.
+        let coefficient = new_row[new_row.len() - 1].clone();
+        self.secret = self.secret.clone() + coefficient * &self.basis_at_zero;
+        self.basis_at_zero = self.basis_at_zero.clone() * (N::zero() - &share.x);
+
+        self.xs.push(share.x);
+        self.row = new_row;
+        Ok(())
+    }
+
+    /// Return the number of shares that have been added so far.
+    pub fn n_shares(&self) -> usize {
+        self.xs.len()
+    }
+
+    /// Return the value of the polynomial through every share so far at
+    /// zero.  Once K shares have been added, this is the secret.
+    pub fn current_secret(&self) -> N {
+        self.secret.clone()
+    }
+}
+
+impl<N> Default for IncrementalReconstructor<N>
+where
+    N: NumRef + Clone,
+{
+    fn default() -> Self {
+        IncrementalReconstructor::new()
+    }
+}
+
+/// Return the number of field operations that `recover_secret` will
+/// perform when given `k` shares.
+///
+/// Reconstruction is quadratic in `k`: for each share, we do two
+/// multiplications for every other share (one for the numerator, one
+/// for the denominator), then one more to multiply by Y, and finally a
+/// division (a multiplication and an inversion).  That comes to `2k^2`
+/// multiplications and `k` inversions, and this function returns their
+/// sum.
+///
+/// Note that an inversion costs far more than a multiplication: for FE,
+/// it's an exponentiation costing over a hundred multiplications.
+///
+/// # Examples
+/// ```
+/// use privcount::shamir::reconstruction_cost;
+/// assert_eq!(reconstruction_cost(3), 21);
+/// // Doubling k roughly quadruples the cost.
+/// assert!(reconstruction_cost(200) > 3 * reconstruction_cost(100));
+/// ```
+pub fn reconstruction_cost(k: usize) -> usize {
+    let multiplications = 2 * k * k;
+    let inversions = k;
+    multiplications + inversions
+}
+
+/// Reconstruct a secret from the first `k` Y coordinates in `ys`, in the
+/// special case where the shares' X coordinates are 1, 2, 3, and so on.
+///
+/// That is, `ys[0]` must be the Y coordinate of the share with X = 1,
+/// `ys[1]` the Y coordinate of the share with X = 2, and so forth: this
+/// function can't check that, so be careful.
+///
+/// With those X coordinates, the Lagrange weight for the share at X = i
+/// works out to `(-1)^(i-1) * binomial(k, i)`, so we can skip the pairwise
+/// products that `recover_secret()` needs.  This takes O(k)
+/// multiplications and a single inversion.
+///
+/// # Panics
+///
+/// Panics if `k` is greater than `ys.len()`.
+///
+/// # Examples
+/// ```
+/// extern crate rand;
+/// extern crate privcount;
+/// use privcount::{FE, shamir};
+/// # fn main() -> Result<(), &'static str> {
+/// let mut rng = rand::os::OsRng::new().unwrap();
+/// let mut builder = shamir::ParamBuilder::new(3, 5)?;
+/// for x in 1..6 {
+///     builder.add_x_coordinate(&FE::new(x));
+/// }
+/// let shares = builder.finalize()?.share_secret(FE::new(1234), &mut rng);
+/// let ys: Vec<FE> = shares.iter().map(|s| s.y).collect();
+/// assert_eq!(shamir::recover_secret_sequential(&ys, 3), FE::new(1234));
+/// # Ok(())
+/// # }
+/// ```
+pub fn recover_secret_sequential(ys: &[FE], k: usize) -> FE {
+    assert!(k <= ys.len());
+    let ys = &ys[..k];
+    if k == 0 {
+        return FE::new(0);
+    }
+
+    // factorials[i] = i!
+    let mut factorials = Vec::with_capacity(k + 1);
+    factorials.push(FE::new(1));
+    for i in 1..(k + 1) {
+        let f = factorials[i - 1] * FE::new(i as u64);
+        factorials.push(f);
+    }
+    // inv_factorials[i] = 1 / i!, computed from the top down.
+    let mut inv_factorials = vec![FE::new(0); k + 1];
+    inv_factorials[k] = factorials[k].recip();
+    for i in (1..(k + 1)).rev() {
+        inv_factorials[i - 1] = inv_factorials[i] * FE::new(i as u64);
+    }
+    // If the chain of multiplications is right, it ends at 1 / 0! = 1.
+    debug_assert_eq!(inv_factorials[0], FE::new(1));
+
+    ys.iter()
+        .enumerate()
+        .map(|(idx, y)| {
+            let i = idx + 1;
+            let binomial = factorials[k] * inv_factorials[i] * inv_factorials[k - i];
+            let term = binomial * *y;
+            if i % 2 == 1 {
+                term
+            } else {
+                -term
+            }
+        })
+        .sum()
+}
+
+/// Reconstruct a secret from any K of its shares, given as `(x, y)` pairs
+/// of integers.
+///
+/// This is the integer-facing counterpart to `recover_secret`, for
+/// callers (such as FFI code) that hold their shares as plain numbers
+/// rather than as `Share<FE>` objects.
+///
+/// # Errors
+///
+/// Gives an error if there are no shares, if any coordinate is not in
+/// range 0..PRIME_ORDER-1, or if any X coordinate is zero or repeated.
+pub fn recover_secret_u64(shares: &[(u64, u64)]) -> Result<u64, &'static str> {
+    let mut fe_shares = Vec::with_capacity(shares.len());
+    for &(x, y) in shares.iter() {
+        let x = FE::from_reduced(x).ok_or("X coordinate out of range.")?;
+        let y = FE::from_reduced(y).ok_or("Y coordinate out of range.")?;
+        fe_shares.push(Share { x, y });
+    }
+    Ok(try_recover_secret(&fe_shares)?.value())
+}
+
+/// Number of bytes of a secret that we put in each FE limb when splitting
+/// byte strings.  (7 bytes is 56 bits, which always fits in an FE.)
+pub const BYTES_PER_LIMB: usize = 7;
+
+/// Split a byte string according to the given parameters.
+///
+/// The secret is broken into limbs of BYTES_PER_LIMB bytes each (the last
+/// one padded with zeros), and each limb is shared separately.  The
+/// result has one entry per limb, holding the N shares of that limb.
+/// The caller will need to remember the length of the secret to
+/// reconstruct it with `combine_bytes()`.
+///
+/// (The security of this scheme is only as good as the RNG you use.)
+pub fn split_bytes<R: Rng>(params: &Params<FE>, secret: &[u8], rng: &mut R) -> Vec<Vec<Share<FE>>> {
+    let limbs = Vec::from_iter(secret.chunks(BYTES_PER_LIMB).map(|chunk| {
+        let mut v = 0u64;
+        for i in 0..BYTES_PER_LIMB {
+            v = (v << 8) | u64::from(*chunk.get(i).unwrap_or(&0));
+        }
+        FE::new(v)
+    }));
+    params.share_many(&limbs, rng)
+}
+
+/// Reconstruct a byte string of length `original_len` from the shares
+/// of its limbs, as generated by `split_bytes()` with threshold `k`.
+///
+/// Every limb must have the same shares (at least `k` of them), at the
+/// same X coordinates.
+///
+/// # Errors
+///
+/// Gives an error if `k` is zero, if the number of limbs doesn't match
+/// `original_len`, if any limb has fewer than `k` shares or a different
+/// number of shares than the others, if any two shares of a limb have
+/// the same X coordinate or an X coordinate is zero, or if any limb
+/// reconstructs to a value that doesn't fit in BYTES_PER_LIMB bytes.
+pub fn combine_bytes(
+    shares_per_limb: &[Vec<Share<FE>>],
+    original_len: usize,
+    k: usize,
+) -> Result<Vec<u8>, &'static str> {
+    if k == 0 {
+        return Err("K may not be zero.");
+    }
+    let n_limbs = original_len.div_ceil(BYTES_PER_LIMB);
+    if shares_per_limb.len() != n_limbs {
+        return Err("Wrong number of limbs.");
+    }
+    let n_shares = shares_per_limb.first().map_or(0, |s| s.len());
+    let mut result = Vec::with_capacity(n_limbs * BYTES_PER_LIMB);
+    for shares in shares_per_limb.iter() {
+        if shares.len() < k {
+            return Err("Too few shares for limb.");
+        }
+        if shares.len() != n_shares {
+            return Err("Limbs have different numbers of shares.");
+        }
+        let v = try_recover_secret(shares)?.value();
+        if v >> (8 * BYTES_PER_LIMB) != 0 {
+            return Err("Limb out of range.");
+        }
+        for i in (0..BYTES_PER_LIMB).rev() {
+            result.push((v >> (8 * i)) as u8);
+        }
+    }
+    result.truncate(original_len);
+    Ok(result)
+}
+
+/// Share a byte string of any length according to the given parameters,
+/// grouping the shares by party.
+///
+/// Unlike `split_bytes()`, this keeps track of the secret's length for
+/// you: the result has one entry per party (in the same order as
+/// `params.x_coordinates()`), holding that party's share of the length
+/// followed by its share of each BYTES_PER_LIMB-byte limb.  Give any K
+/// of those entries, along with K, to `recover_bytes()` to get the
+/// secret back.
+///
+/// (The security of this scheme is only as good as the RNG you use.)
+pub fn share_bytes<R: Rng>(secret: &[u8], params: &Params<FE>, rng: &mut R) -> Vec<Vec<Share<FE>>> {
+    let len_shares = params.share_secret(FE::new(secret.len() as u64), rng);
+    let limbs = split_bytes(params, secret, rng);
+    Vec::from_iter(len_shares.into_iter().enumerate().map(|(i, len_share)| {
+        let mut mine = Vec::with_capacity(limbs.len() + 1);
+        mine.push(len_share);
+        mine.extend(limbs.iter().map(|limb| limb[i].clone()));
+        mine
+    }))
+}
+
+/// Reconstruct a byte string from the shares of at least `k` parties, as
+/// generated by `share_bytes()` with threshold `k`.
+///
+/// # Errors
+///
+/// Gives an error if `k` is zero, if there are fewer than `k` parties,
+/// if any party has no shares, a different number of shares than the
+/// others, or shares at more than one X coordinate, if two parties have
+/// the same X coordinate, or if the recovered length doesn't match the
+/// number of limbs.
+pub fn recover_bytes(
+    shares_per_party: &[Vec<Share<FE>>],
+    k: usize,
+) -> Result<Vec<u8>, &'static str> {
+    if k == 0 {
+        return Err("K may not be zero.");
+    }
+    if shares_per_party.len() < k {
+        return Err("Too few parties.");
+    }
+    let n_shares = shares_per_party[0].len();
+    if n_shares == 0 {
+        return Err("Missing length share.");
+    }
+    for shares in shares_per_party.iter() {
+        if shares.len() != n_shares {
+            return Err("Parties have different numbers of shares.");
+        }
+        if shares.iter().any(|s| s.x != shares[0].x) {
+            return Err("Party has shares at different X coordinates.");
+        }
+    }
+
+    let len_shares = Vec::from_iter(shares_per_party.iter().map(|shares| shares[0].clone()));
+    let len = try_recover_secret(&len_shares)?.value();
+    let len = usize::try_from(len).map_err(|_| "Bad length.")?;
+    let shares_per_limb = Vec::from_iter(
+        (1..n_shares).map(|i| Vec::from_iter(shares_per_party.iter().map(|s| s[i].clone()))),
+    );
+    combine_bytes(&shares_per_limb, len, k)
+}
+
+#[cfg(test)]
+mod tests {
+    use math::*;
+    use rand;
+    use shamir::*;
+    #[test]
+    fn demo() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let shares = p.share_secret(FE::new(12345), &mut rng);
+        assert_eq!(shares.len(), 5);
+        println!("{:?}", shares);
+        let result = recover_secret(&shares[0..3]);
+        assert_eq!(result, FE::new(12345));
+    }
+
+    #[test]
+    fn many() {
+        use rand::{ChaChaRng, SeedableRng};
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let secrets = [
+            FE::new(0),
+            FE::new(1),
+            FE::new(12345),
+            FE::new(PRIME_ORDER - 1),
+        ];
+        let many = p.share_many(&secrets, &mut rng);
+        assert_eq!(many.len(), secrets.len());
+        for (shares, secret) in many.iter().zip(secrets.iter()) {
+            assert_eq!(shares.len(), 5);
+            assert_eq!(recover_secret(&shares[0..3]), *secret);
+            assert_eq!(recover_secret(&shares[2..5]), *secret);
+        }
+        assert!(p.share_many(&[], &mut rng).is_empty());
+
+        // Same RNG, same shares as calling share_secret in a loop.
+        let mut rng1 = ChaChaRng::from_seed(&[1, 2, 3][..]);
+        let mut rng2 = ChaChaRng::from_seed(&[1, 2, 3][..]);
+        let many = p.share_many(&secrets, &mut rng1);
+        for (shares, secret) in many.iter().zip(secrets.iter()) {
+            let one = p.share_secret(*secret, &mut rng2);
+            assert_eq!(*shares, one);
+        }
+    }
+
+    #[test]
+    fn share_secrets() {
+        use rand::{ChaChaRng, SeedableRng};
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let secrets = [FE::new(0), FE::new(77), FE::new(PRIME_ORDER - 1)];
+        let mut rng1 = ChaChaRng::from_seed(&[4, 5, 6][..]);
+        let mut rng2 = ChaChaRng::from_seed(&[4, 5, 6][..]);
+        let batch = p.share_secrets(&secrets, &mut rng1);
+        assert_eq!(batch.len(), 5);
+        for (_, ys) in batch.iter() {
+            assert_eq!(ys.len(), secrets.len());
+        }
+
+        for (i, secret) in secrets.iter().enumerate() {
+            let one = p.share_secret(*secret, &mut rng2);
+            for (a, b) in batch.iter().zip(one.iter()) {
+                assert_eq!((a.0, a.1[i]), (b.x, b.y));
+            }
+            let shares =
+                Vec::from_iter(batch[1..4].iter().map(|&(x, ref ys)| Share { x, y: ys[i] }));
+            assert_eq!(recover_secret(&shares), *secret);
+        }
+
+        let empty = p.share_secrets(&[], &mut rng);
+        assert_eq!(empty.len(), 5);
+        assert!(empty.iter().all(|(_, ys)| ys.is_empty()));
+    }
+
+    #[test]
+    fn interpolator() {
+        let mut pb = ParamBuilder::new(4, 6).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let secrets = Vec::from_iter((0..100).map(|_| rng.gen::<FE>()));
+        let per_x = p.share_secrets(&secrets, &mut rng);
+        let chosen = [&per_x[0], &per_x[2], &per_x[3], &per_x[5]];
+        let xs = Vec::from_iter(chosen.iter().map(|c| c.0));
+        let interp = LagrangeInterpolator::new(&xs).unwrap();
+        assert_eq!(interp.x_coordinates(), &xs[..]);
+
+        for (i, secret) in secrets.iter().enumerate() {
+            let ys = Vec::from_iter(chosen.iter().map(|c| c.1[i]));
+            let shares = Vec::from_iter(chosen.iter().map(|c| Share { x: c.0, y: c.1[i] }));
+            assert_eq!(interp.recover(&ys), *secret);
+            assert_eq!(interp.recover(&ys), recover_secret(&shares));
+        }
+
+        assert_eq!(
+            LagrangeInterpolator::<FE>::new(&[]).unwrap_err(),
+            RecoverError::Empty
+        );
+        assert_eq!(
+            LagrangeInterpolator::new(&[FE::new(1), FE::new(0)]).unwrap_err(),
+            RecoverError::ZeroX
+        );
+        assert_eq!(
+            LagrangeInterpolator::new(&[FE::new(3), FE::new(3)]).unwrap_err(),
+            RecoverError::DuplicateX
+        );
+    }
+
+    #[test]
+    fn correcting() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let mut shares = p.share_secret(FE::new(31337), &mut rng);
+
+        assert_eq!(recover_secret_correcting(&shares, 3), Ok(FE::new(31337)));
+        assert_eq!(
+            recover_secret_correcting(&shares[..3], 3),
+            Ok(FE::new(31337))
+        );
+
+        // One bad share out of k+2 is fine, wherever it is.
+        for i in 0..5 {
+            let mut corrupted = shares.clone();
+            corrupted[i].y += FE::new(1);
+            assert_eq!(recover_secret_correcting(&corrupted, 3), Ok(FE::new(31337)));
+            if i < 3 {
+                assert_ne!(recover_secret(&corrupted[..3]), FE::new(31337));
+            }
+        }
+
+        // Two bad shares are too many.
+        shares[1].y = rng.gen();
+        shares[3].y = rng.gen();
+        assert_eq!(
+            recover_secret_correcting(&shares, 3),
+            Err(DecodeError::TooManyErrors)
+        );
+
+        assert_eq!(
+            recover_secret_correcting(&shares[..2], 3),
+            Err(DecodeError::TooFewShares)
+        );
+        assert_eq!(
+            recover_secret_correcting(&shares, 0),
+            Err(DecodeError::TooFewShares)
+        );
+        shares[2].x = shares[0].x;
+        assert_eq!(
+            recover_secret_correcting(&shares, 3),
+            Err(DecodeError::DuplicateX)
+        );
+    }
+
+    #[test]
+    fn share_encoding() {
+        let share = Share {
+            x: FE::new(0x0102_0304),
+            y: FE::new(PRIME_ORDER - 1),
+        };
+        let encoded = share.to_bytes();
+        assert_eq!(&encoded[..8], &[0, 0, 0, 0, 1, 2, 3, 4]);
+        let decoded = Share::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded, share);
+
+        let zero_x = Share {
+            x: FE::new(0),
+            y: FE::new(7),
+        };
+        assert!(Share::from_bytes(&zero_x.to_bytes()).is_err());
+        assert!(Share::from_bytes(&encoded[..15]).is_err());
+        let mut unreduced = encoded;
+        unreduced[8..].copy_from_slice(&PRIME_ORDER.to_be_bytes());
+        assert!(Share::from_bytes(&unreduced).is_err());
+    }
+
+    #[test]
+    fn accessors() {
+        let mut pb = ParamBuilder::new(2, 3).unwrap();
+        let xs = [FE::new(5), FE::new(9), FE::new(1)];
+        for x in xs.iter() {
+            pb.add_x_coordinate(x);
+        }
+        let p = pb.finalize().unwrap();
+        assert_eq!(p.k(), 2);
+        assert_eq!(p.n(), 3);
+        assert_eq!(p.x_coordinates(), &xs[..]);
+    }
+
+    #[test]
+    fn bulk_x_coordinates() {
+        let xs = [FE::new(3), FE::new(4), FE::new(5), FE::new(6)];
+        let mut pb = ParamBuilder::new(2, 4).unwrap();
+        pb.add_x_coordinates(&xs);
+        assert_eq!(pb.finalize().unwrap().x_coordinates(), &xs[..]);
+
+        let mut pb = ParamBuilder::new(2, 3).unwrap();
+        pb.add_x_coordinates(&xs);
+        assert!(pb.finalize().is_err());
+    }
+
+    #[test]
+    fn share_eq() {
+        let share = Share {
+            x: FE::new(3),
+            y: FE::new(4),
+        };
+        let mut other = share.clone();
+        assert_eq!(share, other);
+        other.y = FE::new(5);
+        assert_ne!(share, other);
+    }
+
+    #[test]
+    fn refresh() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let shares = p.share_secret(FE::new(9999), &mut rng);
+
+        let refreshed = p.refresh_shares(&shares, &mut rng);
+        assert_eq!(refreshed.len(), shares.len());
+        for (old, new) in shares.iter().zip(refreshed.iter()) {
+            assert_eq!(old.x, new.x);
+            assert_ne!(old.y, new.y);
+        }
+        assert_eq!(recover_secret(&shares[0..3]), FE::new(9999));
+        assert_eq!(recover_secret(&refreshed[0..3]), FE::new(9999));
+        assert_eq!(recover_secret(&refreshed[2..5]), FE::new(9999));
+
+        // Refreshing a subset works too.
+        let again = p.refresh_shares(&refreshed[1..4], &mut rng);
+        assert_eq!(recover_secret(&again), FE::new(9999));
+    }
+
+    #[test]
+    fn incremental() {
+        let mut pb = ParamBuilder::new(4, 7).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let shares = p.share_secret(FE::new(31337), &mut rng);
+
+        let mut r = IncrementalReconstructor::new();
+        assert_eq!(r.n_shares(), 0);
+        for (m, share) in shares.iter().enumerate() {
+            r.add_share(share.clone()).unwrap();
+            assert_eq!(r.n_shares(), m + 1);
+            // We should always agree with Lagrange on the same shares.
+            assert_eq!(r.current_secret(), recover_secret(&shares[..m + 1]));
+            if m + 1 >= 4 {
+                assert_eq!(r.current_secret(), FE::new(31337));
+            }
+        }
+        assert!(r.add_share(shares[2].clone()).is_err());
+        assert_eq!(r.current_secret(), FE::new(31337));
+    }
+
+    #[test]
+    fn small_field_incremental() {
+        use self::f101::F101;
+        let mut rng = rand::thread_rng();
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        for x in 1..6 {
+            pb.add_x_coordinate(&F101(x * 7));
+        }
+        let p = pb.finalize().unwrap();
+
+        for secret in 0..101 {
+            let mut shares = p.share_secret(F101(secret), &mut rng);
+            shares.reverse();
+            let mut r = IncrementalReconstructor::new();
+            for share in shares {
+                r.add_share(share).unwrap();
+            }
+            assert_eq!(r.current_secret(), F101(secret));
+        }
+    }
+
+    #[test]
+    fn sequential() {
+        let mut rng = rand::thread_rng();
+        for k in 1..8 {
+            let mut pb = ParamBuilder::new(k, 10).unwrap();
+            for x in 1..11 {
+                pb.add_x_coordinate(&FE::new(x));
+            }
+            let p = pb.finalize().unwrap();
+            let secret = rng.gen();
+            let shares = p.share_secret(secret, &mut rng);
+            let ys: Vec<FE> = shares.iter().map(|s| s.y).collect();
+            assert_eq!(recover_secret_sequential(&ys, k as usize), secret);
+            assert_eq!(
+                recover_secret_sequential(&ys, k as usize),
+                recover_secret(&shares[..k as usize])
+            );
+        }
+        assert_eq!(recover_secret_sequential(&[], 0), FE::new(0));
+    }
+
+    #[test]
+    fn blinded() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let masks: Vec<FE> = (0..5).map(|_| rng.gen()).collect();
+        let blind: FE = rng.gen();
+        let shares = p.share_secret_blinded(FE::new(4242), &masks, blind, &mut rng);
+        assert_eq!(shares.len(), 5);
+
+        // Unblinding the shares gives shares of the secret.
+        let unblinded: Vec<_> = shares
+            .iter()
+            .zip(masks.iter())
+            .map(|(s, m)| Share {
+                x: s.x,
+                y: s.y + *m + blind,
+            })
+            .collect();
+        assert_eq!(recover_secret(&unblinded[0..3]), FE::new(4242));
+        assert_eq!(recover_secret(&unblinded[2..5]), FE::new(4242));
+
+        // With a uniform mask, the blinded shares are shares of a shifted
+        // secret.
+        let same_masks = vec![FE::new(100); 5];
+        let shares = p.share_secret_blinded(FE::new(4242), &same_masks, FE::new(42), &mut rng);
+        assert_eq!(recover_secret(&shares[1..4]), FE::new(4100));
+    }
+
+    #[test]
+    fn bytes() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let secret = b"Squeamish ossifrage";
+        let limbs = split_bytes(&p, &secret[..], &mut rng);
+        assert_eq!(limbs.len(), 3);
+        let some: Vec<_> = limbs.iter().map(|l| l[1..4].to_vec()).collect();
+        assert_eq!(
+            combine_bytes(&some, secret.len(), 3).unwrap(),
+            secret.to_vec()
+        );
+
+        assert!(combine_bytes(&some, 22, 3).is_err());
+        assert!(combine_bytes(&some[..2], secret.len(), 3).is_err());
+        let mut ragged = some.clone();
+        ragged[1].pop();
+        assert!(combine_bytes(&ragged, secret.len(), 3).is_err());
+        let too_few: Vec<_> = limbs.iter().map(|l| l[..2].to_vec()).collect();
+        assert_eq!(
+            combine_bytes(&too_few, secret.len(), 3),
+            Err("Too few shares for limb.")
+        );
+        assert!(combine_bytes(&some, secret.len(), 0).is_err());
+        let dup: Vec<_> = limbs
+            .iter()
+            .map(|l| vec![l[1].clone(), l[1].clone(), l[2].clone()])
+            .collect();
+        assert_eq!(
+            combine_bytes(&dup, secret.len(), 3),
+            Err(RecoverError::DuplicateX.as_str())
+        );
+
+        assert!(split_bytes(&p, &[], &mut rng).is_empty());
+        assert_eq!(combine_bytes(&[], 0, 3), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn bytes_per_party() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let mut secret = [0u8; 32];
+        rng.fill_bytes(&mut secret);
+        let parties = share_bytes(&secret, &p, &mut rng);
+        assert_eq!(parties.len(), 5);
+        for (shares, x) in parties.iter().zip(p.x_coordinates()) {
+            // One share for the length, and 5 limbs.
+            assert_eq!(shares.len(), 6);
+            assert!(shares.iter().all(|s| s.x == *x));
+        }
+        assert_eq!(recover_bytes(&parties[1..4], 3).unwrap(), secret.to_vec());
+        let others = [parties[0].clone(), parties[2].clone(), parties[4].clone()];
+        assert_eq!(recover_bytes(&others, 3).unwrap(), secret.to_vec());
+        assert_eq!(recover_bytes(&parties, 3).unwrap(), secret.to_vec());
+
+        // A partial final limb.
+        let parties = share_bytes(b"Hello", &p, &mut rng);
+        assert_eq!(parties[0].len(), 2);
+        assert_eq!(recover_bytes(&parties[2..], 3).unwrap(), b"Hello".to_vec());
+
+        let empty = share_bytes(&[], &p, &mut rng);
+        assert_eq!(empty[0].len(), 1);
+        assert_eq!(recover_bytes(&empty[..3], 3), Ok(Vec::new()));
+
+        assert!(recover_bytes(&[], 3).is_err());
+        assert!(recover_bytes(&[Vec::new()], 1).is_err());
+        assert_eq!(recover_bytes(&parties[..2], 3), Err("Too few parties."));
+        assert!(recover_bytes(&parties, 0).is_err());
+        let mut ragged = parties[..3].to_vec();
+        ragged[1].pop();
+        assert!(recover_bytes(&ragged, 3).is_err());
+        let mut mixed = parties[..3].to_vec();
+        mixed[0][1] = parties[4][1].clone();
+        assert!(recover_bytes(&mixed, 3).is_err());
+        // The same party twice.
+        let dup = [parties[0].clone(), parties[0].clone(), parties[1].clone()];
+        assert_eq!(
+            recover_bytes(&dup, 3),
+            Err(RecoverError::DuplicateX.as_str())
+        );
+    }
+
+    quickcheck! {
+        fn p_bytes_roundtrip(secret : Vec<u8>) -> bool {
+            let mut pb = ParamBuilder::new(2, 4).unwrap();
+            let mut rng = rand::thread_rng();
+            pb.fill_x_coordinates(&mut rng);
+            let p = pb.finalize().unwrap();
+            let limbs = split_bytes(&p, &secret, &mut rng);
+            let some: Vec<_> = limbs.iter().map(|l| l[2..4].to_vec()).collect();
+            combine_bytes(&some, secret.len(), 2) == Ok(secret)
+        }
+    }
+
+    #[test]
+    fn safe() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let shares = p.share_secret(FE::new(777), &mut rng);
+
+        assert_eq!(recover_secret_safe(&shares[0..3]), Ok(FE::new(777)));
+        assert_eq!(recover_secret_safe(&shares[2..5]), Ok(FE::new(777)));
+
+        let dup = [shares[0].clone(), shares[1].clone(), shares[0].clone()];
+        assert_eq!(recover_secret_safe(&dup), Err("Duplicate X coordinate."));
+        let same_x = [
+            shares[0].clone(),
+            Share {
+                x: shares[0].x,
+                y: shares[1].y,
+            },
+        ];
+        assert!(recover_secret_safe(&same_x).is_err());
+    }
+
+    #[test]
+    fn duplicate_x_coordinates() {
+        let mut pb = ParamBuilder::new(2, 3).unwrap();
+        pb.add_x_coordinate(&FE::new(5));
+        pb.add_x_coordinate(&FE::new(9));
+        pb.add_x_coordinate(&FE::new(5));
+        assert_eq!(pb.finalize().err(), Some("Duplicate X coordinate."));
+
+        // Equivalent representations of the same element are duplicates too.
+        let mut pb = ParamBuilder::new(2, 2).unwrap();
+        pb.add_x_coordinate(&FE::new(5));
+        pb.add_x_coordinate(&FE::new(PRIME_ORDER + 5));
+        assert!(pb.finalize().is_err());
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let mut pb = ParamBuilder::<FE>::new(5, 50).unwrap();
+            pb.fill_x_coordinates(&mut rng);
+            assert!(pb.finalize().is_ok());
+        }
+    }
+
+    #[test]
+    fn try_recover() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let shares = p.share_secret(FE::new(31337), &mut rng);
+
+        assert_eq!(try_recover_secret(&shares[1..4]), Ok(FE::new(31337)));
+        let empty: [Share<FE>; 0] = [];
+        assert_eq!(try_recover_secret(&empty), Err(RecoverError::Empty));
+
+        let dup = [shares[0].clone(), shares[1].clone(), shares[0].clone()];
+        assert_eq!(try_recover_secret(&dup), Err(RecoverError::DuplicateX));
+        let same_x = [
+            shares[2].clone(),
+            Share {
+                x: shares[2].x,
+                y: shares[3].y,
+            },
+        ];
+        assert_eq!(try_recover_secret(&same_x), Err(RecoverError::DuplicateX));
+
+        let zero_x = [
+            shares[0].clone(),
+            Share {
+                x: FE::new(0),
+                y: shares[1].y,
+            },
+        ];
+        assert_eq!(try_recover_secret(&zero_x), Err(RecoverError::ZeroX));
+
+        let msg: &'static str = RecoverError::ZeroX.into();
+        assert_eq!(msg, "No X coordinate may be zero.");
+        assert_eq!(
+            RecoverError::Empty.to_string(),
+            "No shares to recover from."
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn recover_panics_on_duplicates() {
+        let share = Share {
+            x: FE::new(5),
+            y: FE::new(6),
+        };
+        recover_secret(&[share.clone(), share]);
+    }
+
+    #[test]
+    fn vec_shares() {
+        let mut pb = ParamBuilder::new(4, 7).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let secrets = Vec::from_iter((0..100).map(|_| rng.gen::<FE>()));
+        let shares = p.share_secret_vec(&secrets, &mut rng);
+        assert_eq!(shares.len(), 7);
+        for (share, x) in shares.iter().zip(p.x_coordinates()) {
+            assert_eq!(share.x, *x);
+            assert_eq!(share.ys.len(), 100);
+        }
+        assert_eq!(recover_secret_vec(&shares[..4]), secrets);
+        assert_eq!(recover_secret_vec(&shares[3..]), secrets);
+
+        // Each secret's shares are ordinary shares, too.
+        let column = Vec::from_iter(shares[1..5].iter().map(|s| Share {
+            x: s.x,
+            y: s.ys[42],
+        }));
+        assert_eq!(recover_secret(&column), secrets[42]);
+
+        let none = p.share_secret_vec(&[], &mut rng);
+        assert_eq!(recover_secret_vec(&none[..4]), Vec::new());
+    }
+
+    #[test]
+    #[should_panic]
+    fn vec_shares_panic_on_ragged() {
+        let mut pb = ParamBuilder::new(2, 3).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let mut shares = p.share_secret_vec(&[FE::new(1), FE::new(2)], &mut rng);
+        shares[1].ys.pop();
+        recover_secret_vec(&shares[..2]);
+    }
+
+    #[test]
+    fn cost() {
+        assert_eq!(reconstruction_cost(0), 0);
+        assert_eq!(reconstruction_cost(1), 3);
+        assert_eq!(reconstruction_cost(2), 10);
+        assert_eq!(
+            reconstruction_cost(MAX_SHARES as usize),
+            2 * 1024 * 1024 + 1024
+        );
+    }
+
+    #[test]
+    fn demo_u64() {
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        let mut rng = rand::thread_rng();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+        let shares = p.share_secret(FE::new(12345), &mut rng);
+        let pairs: Vec<_> = shares.iter().map(|s| (s.x.value(), s.y.value())).collect();
+        assert_eq!(recover_secret_u64(&pairs[0..3]), Ok(12345));
+        assert_eq!(recover_secret_u64(&pairs[2..5]), Ok(12345));
+
+        assert!(recover_secret_u64(&[]).is_err());
+        let mut bad = pairs.clone();
+        bad[1].1 = PRIME_ORDER;
+        assert!(recover_secret_u64(&bad[0..3]).is_err());
+        bad[1] = (0, 17);
+        assert!(recover_secret_u64(&bad[0..3]).is_err());
+    }
+
+    #[test]
+    fn mandatory_shares() {
+        let mut rng = rand::thread_rng();
+        let mut pb = ParamBuilder::new(2, 4).unwrap();
+        for x in 1..5 {
+            pb.add_x_coordinate(&FE::new(x));
+        }
+        pb.add_mandatory_x_coordinate(&FE::new(1));
+        let p = pb.finalize().unwrap();
+        let shares = p.share_secret(FE::new(99), &mut rng);
+
+        assert!(p.can_reconstruct(&shares[0..2]));
+        assert!(p.can_reconstruct(&shares));
+        assert!(!p.can_reconstruct(&shares[0..1]));
+        // Enough shares, but not the mandatory one.
+        assert!(!p.can_reconstruct(&shares[1..4]));
+        // The same share twice doesn't count.
+        assert!(!p.can_reconstruct(&[shares[0].clone(), shares[0].clone()]));
+        // ... but it's only a policy: the secret is still there.
+        assert_eq!(recover_secret(&shares[2..4]), FE::new(99));
+
+        let mut pb = ParamBuilder::new(2, 2).unwrap();
+        pb.add_x_coordinate(&FE::new(1));
+        pb.add_x_coordinate(&FE::new(2));
+        pb.add_mandatory_x_coordinate(&FE::new(3));
+        assert!(pb.finalize().is_err());
+    }
+
+    /// A tiny prime field (the integers modulo 101), so that we can test
+    /// the Shamir code independently of FE, with numbers small enough to
+    /// check by hand.
+    mod f101 {
+        use num::traits::{Num, One, Zero};
+        use rand::{Rand, Rng};
+        use std::num::ParseIntError;
+        use std::ops::{Add, Div, Mul, Rem, Sub};
+
+        const P: u32 = 101;
+
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub struct F101(pub u32);
+
+        impl F101 {
+            pub fn new(v: u32) -> Self {
+                F101(v % P)
+            }
+            fn recip(self) -> Self {
+                // Fermat's little theorem: a^(P-2) == 1/a.
+                (0..P - 2).fold(F101(1), |acc, _| acc * self)
+            }
+            fn quotient(self, rhs: Self) -> Self {
+                self * rhs.recip()
+            }
+        }
+
+        // Implement an operator for both F101 and &F101 right-hand sides.
+        macro_rules! f101_op {
+            ($tr:ident, $method:ident, $a:ident, $b:ident => $e:expr) => {
+                impl $tr for F101 {
+                    type Output = F101;
+                    fn $method(self, rhs: F101) -> F101 {
+                        let ($a, $b) = (self, rhs);
+                        $e
+                    }
+                }
+                impl<'a> $tr<&'a F101> for F101 {
+                    type Output = F101;
+                    fn $method(self, rhs: &F101) -> F101 {
+                        self.$method(*rhs)
+                    }
+                }
+            };
+        }
+        f101_op!(Add, add, a, b => F101::new(a.0 + b.0));
+        f101_op!(Sub, sub, a, b => F101::new(a.0 + P - b.0));
+        f101_op!(Mul, mul, a, b => F101::new(a.0 * b.0));
+        f101_op!(Div, div, a, b => a.quotient(b));
+        // Every nonzero element divides every other one evenly.
+        f101_op!(Rem, rem, _a, _b => F101(0));
+
+        impl<'a, 'b> Sub<&'b F101> for &'a F101 {
+            type Output = F101;
+            fn sub(self, rhs: &'b F101) -> F101 {
+                *self - *rhs
+            }
+        }
+
+        impl Zero for F101 {
+            fn zero() -> F101 {
+                F101(0)
+            }
+            fn is_zero(&self) -> bool {
+                self.0 == 0
+            }
+        }
+        impl One for F101 {
+            fn one() -> F101 {
+                F101(1)
+            }
+        }
+        impl Num for F101 {
+            type FromStrRadixErr = ParseIntError;
+            fn from_str_radix(s: &str, radix: u32) -> Result<Self, ParseIntError> {
+                u32::from_str_radix(s, radix).map(F101::new)
+            }
+        }
+        impl Rand for F101 {
+            fn rand<R: Rng>(rng: &mut R) -> F101 {
+                F101(rng.gen_range(0, P))
+            }
+        }
+    }
+
+    #[test]
+    fn small_field_by_hand() {
+        use self::f101::F101;
+        let sh = |x, y| Share {
+            x: F101(x),
+            y: F101(y),
+        };
+
+        // f(x) = 7 + 3x (mod 101)
+        let line = [sh(1, 10), sh(2, 13), sh(5, 22)];
+        assert_eq!(recover_secret(&line[0..2]), F101(7));
+        assert_eq!(recover_secret(&line[1..3]), F101(7));
+
+        // f(x) = 42 + 5x + 60x^2 (mod 101)
+        let quadratic = [sh(1, 6), sh(2, 90), sh(3, 92)];
+        assert_eq!(recover_secret(&quadratic), F101(42));
+    }
+
+    #[test]
+    fn small_field_roundtrip() {
+        use self::f101::F101;
+        let mut rng = rand::thread_rng();
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        // Use fixed X coordinates: random ones would collide too often in
+        // a field this small.
+        for x in 1..6 {
+            pb.add_x_coordinate(&F101(x));
+        }
+        let p = pb.finalize().unwrap();
+
+        for secret in 0..101 {
+            let shares = p.share_secret(F101(secret), &mut rng);
+            assert_eq!(shares.len(), 5);
+            assert_eq!(recover_secret(&shares[0..3]), F101(secret));
+            assert_eq!(recover_secret(&shares[2..5]), F101(secret));
+            let spread = [shares[0].clone(), shares[2].clone(), shares[4].clone()];
+            assert_eq!(recover_secret(&spread), F101(secret));
+        }
     }
 }
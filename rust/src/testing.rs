@@ -0,0 +1,60 @@
+//! Helpers for testing code that uses privcount.
+//!
+//! Nothing here is needed to run the protocol: these functions compute
+//! the answers that the protocol _should_ give, so that tests can compare
+//! against them.  This module is only available with the `testing`
+//! feature.
+
+use std::collections::HashMap;
+
+use data::CtrId;
+
+/// Given the plaintext increments that each client made to each of its
+/// counters, return the tally that the TRs should reconstruct.
+///
+/// A counter that a client didn't mention counts as zero for that
+/// client.
+///
+/// # Examples
+/// ```
+/// use privcount::data::CtrId;
+/// use privcount::testing::expected_tally;
+/// use std::collections::HashMap;
+///
+/// let mut a = HashMap::new();
+/// a.insert(CtrId(1), 10);
+/// let mut b = HashMap::new();
+/// b.insert(CtrId(1), 5);
+/// b.insert(CtrId(2), 7);
+///
+/// let tally = expected_tally(&[a, b]);
+/// assert_eq!(tally[&CtrId(1)], 15);
+/// assert_eq!(tally[&CtrId(2)], 7);
+/// ```
+pub fn expected_tally(client_plaintexts: &[HashMap<CtrId, u64>]) -> HashMap<CtrId, u64> {
+    let mut result = HashMap::new();
+    for plaintext in client_plaintexts.iter() {
+        for (id, val) in plaintext.iter() {
+            *result.entry(*id).or_insert(0) += *val;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::*;
+
+    #[test]
+    fn tally() {
+        assert!(expected_tally(&[]).is_empty());
+
+        let mut a = HashMap::new();
+        a.insert(CtrId(1), 1);
+        a.insert(CtrId(3), 0);
+        let tally = expected_tally(&[a.clone(), a.clone(), HashMap::new()]);
+        assert_eq!(tally.len(), 2);
+        assert_eq!(tally[&CtrId(1)], 2);
+        assert_eq!(tally[&CtrId(3)], 0);
+    }
+}
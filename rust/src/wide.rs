@@ -0,0 +1,245 @@
+//! Wide counter values, built from two FE limbs.
+//!
+//! Some counters can get larger than a single FE can hold.  Rather than
+//! changing the field, we represent such a value as a pair of FE "limbs",
+//! and share each limb separately with the ordinary Shamir code.
+//!
+//! A WideFE with limbs `lo` and `hi` represents the integer
+//! `lo + hi * 2^LIMB_BITS`.  A freshly constructed WideFE has `lo` in range
+//! 0..2^LIMB_BITS-1, and `hi` in range 0..PRIME_ORDER-1, so it can
+//! represent any value up to about 2^102.
+//!
+//! Reconstruction works limb by limb: summing shares of several WideFE
+//! values gives shares of the per-limb sums, and recovering those gives
+//! a WideFE whose low limb may have grown past 2^LIMB_BITS.  That's fine:
+//! we propagate the excess into the high limb once the sum is known.
+//!
+//! Note that since shares can't carry between limbs, the bound on
+//! homomorphic sums is now per-limb: the sum of the low limbs and the sum
+//! of the high limbs must _each_ stay below PRIME_ORDER, or the result
+//! will wrap.  With LIMB_BITS = 40, you can safely add up at most
+//! 2^22-1 values whose low limbs are all at their maximum.
+
+use math::FE;
+use math::PRIME_ORDER;
+use rand::Rng;
+use shamir::{self, Params, Share};
+use std::ops::Add;
+
+/// Number of bits held by the low limb of a WideFE.
+pub const LIMB_BITS: u32 = 40;
+/// Mask for the bits of a value that belong in the low limb.
+const LIMB_MASK: u128 = (1 << LIMB_BITS) - 1;
+
+/// A wide integer, stored as a low and a high FE limb.
+///
+/// Two WideFEs are equal when they represent the same integer, even if
+/// one of them has an unnormalized low limb.
+#[derive(Debug, Copy, Clone)]
+pub struct WideFE {
+    lo: FE,
+    hi: FE,
+}
+
+impl PartialEq for WideFE {
+    fn eq(&self, other: &WideFE) -> bool {
+        self.value() == other.value()
+    }
+}
+
+impl Eq for WideFE {}
+
+/// One of the N split shares of a WideFE: a share of each limb, at the
+/// same X coordinate.
+#[derive(Clone, Debug)]
+pub struct WideShare {
+    /// The X coordinate for this share.
+    pub x: FE,
+    /// The Y coordinate for this share of the low limb.
+    pub lo: FE,
+    /// The Y coordinate for this share of the high limb.
+    pub hi: FE,
+}
+
+impl WideFE {
+    /// Construct a new WideFE from an integer.
+    ///
+    /// Returns None if `v` is too large to represent: that is, if
+    /// `v >> LIMB_BITS` is not less than PRIME_ORDER.
+    ///
+    /// # Examples
+    /// ```
+    /// use privcount::wide::WideFE;
+    /// let big = 1u128 << 90;
+    /// assert_eq!(WideFE::new(big).unwrap().value(), big);
+    /// assert_eq!(WideFE::new(!0), None);
+    /// ```
+    pub fn new(v: u128) -> Option<Self> {
+        let hi = v >> LIMB_BITS;
+        if hi >= PRIME_ORDER as u128 {
+            return None;
+        }
+        Some(WideFE {
+            lo: FE::new((v & LIMB_MASK) as u64),
+            hi: FE::new(hi as u64),
+        })
+    }
+
+    /// Construct a WideFE directly from its low and high limbs.
+    pub fn from_limbs(lo: FE, hi: FE) -> Self {
+        WideFE { lo, hi }
+    }
+
+    /// Return the low and high limbs of this WideFE.
+    pub fn limbs(self) -> (FE, FE) {
+        (self.lo, self.hi)
+    }
+
+    /// Return the integer represented by this WideFE.
+    pub fn value(self) -> u128 {
+        // hi is less than 2^62 and lo is less than 2^62, so this can't
+        // overflow.
+        ((self.hi.value() as u128) << LIMB_BITS) + self.lo.value() as u128
+    }
+
+    /// Move any excess in the low limb into the high limb, so that the
+    /// low limb is back in range 0..2^LIMB_BITS-1.
+    fn normalize(self) -> Self {
+        let carry = self.lo.value() >> LIMB_BITS;
+        WideFE {
+            lo: self.lo - FE::new(carry << LIMB_BITS),
+            hi: self.hi + FE::new(carry),
+        }
+    }
+}
+
+impl Add for WideFE {
+    type Output = Self;
+    /// Add two WideFE values, carrying from the low limb into the high one.
+    ///
+    /// (Don't use this to add shares: use the limb-by-limb addition on
+    /// WideShare instead.)
+    fn add(self, rhs: Self) -> Self {
+        WideFE {
+            lo: self.lo + rhs.lo,
+            hi: self.hi + rhs.hi,
+        }
+        .normalize()
+    }
+}
+
+impl Add for WideShare {
+    type Output = Self;
+    /// Add two shares at the same X coordinate, limb by limb.  The result
+    /// is a share of the sum of the two shared values.
+    fn add(self, rhs: Self) -> Self {
+        debug_assert_eq!(self.x, rhs.x);
+        WideShare {
+            x: self.x,
+            lo: self.lo + rhs.lo,
+            hi: self.hi + rhs.hi,
+        }
+    }
+}
+
+/// Split a WideFE according to the given parameters, by sharing each of
+/// its limbs.
+///
+/// (The security of this scheme is only as good as the RNG you use.)
+pub fn share_secret<R: Rng>(params: &Params<FE>, secret: WideFE, rng: &mut R) -> Vec<WideShare> {
+    let lo_shares = params.share_secret(secret.lo, rng);
+    let hi_shares = params.share_secret(secret.hi, rng);
+    lo_shares
+        .into_iter()
+        .zip(hi_shares)
+        .map(|(lo, hi)| {
+            debug_assert_eq!(lo.x, hi.x);
+            WideShare {
+                x: lo.x,
+                lo: lo.y,
+                hi: hi.y,
+            }
+        })
+        .collect()
+}
+
+/// Reconstruct a WideFE from any K of its shares.
+///
+/// (If the number of shares is not the same K used to split the
+/// secret, the output will be wrong.)
+///
+/// # Panics
+///
+/// Panics if `shares` is empty, or if any two shares have the same X
+/// coordinate, or if any X coordinate is zero, just like
+/// `shamir::recover_secret()`.
+pub fn recover_secret(shares: &[WideShare]) -> WideFE {
+    let lo_shares: Vec<_> = shares.iter().map(|s| Share { x: s.x, y: s.lo }).collect();
+    let hi_shares: Vec<_> = shares.iter().map(|s| Share { x: s.x, y: s.hi }).collect();
+    WideFE {
+        lo: shamir::recover_secret(&lo_shares),
+        hi: shamir::recover_secret(&hi_shares),
+    }
+    .normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand;
+    use shamir::ParamBuilder;
+    use wide::*;
+
+    #[test]
+    fn limbs() {
+        let v = (12345u128 << LIMB_BITS) + 678;
+        let w = WideFE::new(v).unwrap();
+        assert_eq!(w.limbs(), (FE::new(678), FE::new(12345)));
+        assert_eq!(w.value(), v);
+
+        let max = ((PRIME_ORDER as u128 - 1) << LIMB_BITS) + LIMB_MASK;
+        assert_eq!(WideFE::new(max).unwrap().value(), max);
+        assert_eq!(WideFE::new(max + 1), None);
+    }
+
+    #[test]
+    fn carry() {
+        let a = WideFE::new(LIMB_MASK).unwrap();
+        let b = WideFE::new(1).unwrap();
+        let sum = a + b;
+        assert_eq!(sum.limbs(), (FE::new(0), FE::new(1)));
+        assert_eq!(sum.value(), LIMB_MASK + 1);
+
+        let unnormalized = WideFE::from_limbs(FE::new(3 << LIMB_BITS), FE::new(2));
+        assert_eq!(unnormalized.value(), 5 << LIMB_BITS);
+        assert_eq!(unnormalized, WideFE::new(5 << LIMB_BITS).unwrap());
+        assert_ne!(unnormalized, WideFE::new(4 << LIMB_BITS).unwrap());
+    }
+
+    #[test]
+    fn shared_sum() {
+        let mut rng = rand::thread_rng();
+        let mut pb = ParamBuilder::new(3, 5).unwrap();
+        pb.fill_x_coordinates(&mut rng);
+        let p = pb.finalize().unwrap();
+
+        let values = [
+            (1u128 << 80) + 17,
+            LIMB_MASK,
+            (999u128 << 70) + LIMB_MASK,
+            5,
+        ];
+        let mut total: Option<Vec<WideShare>> = None;
+        for v in values.iter() {
+            let shares = share_secret(&p, WideFE::new(*v).unwrap(), &mut rng);
+            assert_eq!(recover_secret(&shares[1..4]).value(), *v);
+            total = Some(match total {
+                None => shares,
+                Some(t) => t.into_iter().zip(shares).map(|(a, b)| a + b).collect(),
+            });
+        }
+        let total = total.unwrap();
+        let expected: u128 = values.iter().sum();
+        assert_eq!(recover_secret(&total[0..3]).value(), expected);
+        assert_eq!(recover_secret(&total[2..5]).value(), expected);
+    }
+}
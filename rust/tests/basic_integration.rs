@@ -1,9 +1,9 @@
-extern crate crypto;
 extern crate privcount;
 extern crate rand;
 
 use privcount::data::*;
-use privcount::{client, server, shamir};
+use privcount::noise::NoNoise;
+use privcount::{client, server, shamir, testing};
 
 use rand::os::OsRng;
 use rand::Rng;
@@ -11,12 +11,11 @@ use std::collections::HashMap;
 use std::iter::FromIterator;
 
 fn gen_server_keys(rng: &mut Rng) -> server::ServerKeys {
-    use crypto::curve25519::curve25519_base;
     use privcount::encrypt::keygen;
     let mut signing_key = [0; 32];
     rng.fill_bytes(&mut signing_key);
     let seckey = keygen::curve25519_seckey_gen(rng);
-    let pk = curve25519_base(&seckey);
+    let pk = seckey.public();
     server::ServerKeys {
         enc_secret: seckey,
         public: TrKeys {
@@ -36,20 +35,24 @@ fn test_combination(n_counters: u32, n_clients: u32, n_trs: u32, k_value: u32) {
     let counter_ids = Vec::from_iter((1..n_counters + 1).map(|n| CtrId(n as u32)));
 
     let mut client_data = Vec::new();
+    let client_keys = Vec::from_iter((0..n_clients).map(|_| ClientKeyPair::generate(&mut rng)));
 
-    let mut accurate_sum = HashMap::new();
+    let mut plaintexts = Vec::new();
 
     // simulate each client.
     for client_idx in 0..n_clients {
-        let mut ctrs = client::CounterSet::new(&mut rng, &counter_ids, &tr_keys, k_value).unwrap();
+        let mut ctrs =
+            client::CounterSet::new(&mut rng, &counter_ids, &tr_keys, k_value, NoNoise).unwrap();
 
+        let mut plaintext = HashMap::new();
         for id in counter_ids.iter() {
             let to_add = id.0 + (client_idx * 17) as u32; // add a dummy value
             ctrs.ctr(*id).unwrap().inc(to_add);
-            let true_ctr = accurate_sum.entry(*id).or_insert(0);
-            *true_ctr += to_add;
+            plaintext.insert(*id, to_add as u64);
         }
-        client_data.push(ctrs.finalize(&mut rng).unwrap());
+        plaintexts.push(plaintext);
+        let keys = &client_keys[client_idx as usize];
+        client_data.push(ctrs.finalize(&mut rng, keys).unwrap());
     }
 
     // then simulate each server; create each one's share of each counter's
@@ -57,11 +60,8 @@ fn test_combination(n_counters: u32, n_clients: u32, n_trs: u32, k_value: u32) {
     let mut shares = Vec::new();
     for my_keys in server_keys.iter() {
         let mut all_my_client_data = Vec::new();
-        for this_client in client_data.iter() {
-            // all clients get the same id for now.
-            let client_id = ClientKey {
-                signing_key: [42; 32],
-            };
+        for (this_client, keys) in client_data.iter().zip(client_keys.iter()) {
+            let client_id = keys.public();
 
             // my data from this client
             let my_data = this_client
@@ -71,7 +71,7 @@ fn test_combination(n_counters: u32, n_clients: u32, n_trs: u32, k_value: u32) {
                 .unwrap();
 
             let decoded = my_keys
-                .decode_from(&client_id, &this_client.counter_ids, my_data)
+                .decode_from(client_id, &this_client.counter_ids, my_data)
                 .unwrap();
 
             all_my_client_data.push(decoded);
@@ -81,6 +81,7 @@ fn test_combination(n_counters: u32, n_clients: u32, n_trs: u32, k_value: u32) {
     }
 
     // use the first k shares to reconstruct the secret for each counter.
+    let expected = testing::expected_tally(&plaintexts);
     for cid in counter_ids.iter() {
         let mut ctr_shares = Vec::new();
         for &(x, ref map) in shares[0..k_value as usize].iter() {
@@ -92,8 +93,13 @@ fn test_combination(n_counters: u32, n_clients: u32, n_trs: u32, k_value: u32) {
         println!("{:?} : {}", cid, sum);
 
         // make sure that the reconstructed
-        assert_eq!(*accurate_sum.get(cid).unwrap() as u64, sum.value());
+        assert_eq!(expected[cid], sum.value());
     }
+
+    // Every client contributed to the client count.
+    let totals = server::reconstruct_totals(&shares[0..k_value as usize]).unwrap();
+    let result = server::TallyResult::new(totals);
+    assert_eq!(result.client_count(), Some(n_clients as u64));
 }
 
 #[test]
@@ -110,3 +116,287 @@ fn two_out_of_two() {
 fn three_out_of_five() {
     test_combination(10, 3, 5, 3);
 }
+
+#[test]
+fn three_out_of_five_protocol() {
+    use privcount::protocol;
+    let mut rng = OsRng::new().unwrap();
+
+    let server_keys = Vec::from_iter((0..5).map(|_| gen_server_keys(&mut rng)));
+    let counter_ids = Vec::from_iter((1..11).map(CtrId));
+    let increments = Vec::from_iter((0..3).map(|client_idx| {
+        HashMap::from_iter(
+            counter_ids
+                .iter()
+                .map(|id| (*id, u64::from(id.0 + client_idx * 17))),
+        )
+    }));
+
+    let totals = protocol::run(&mut rng, &counter_ids, &increments, &server_keys, 3).unwrap();
+    for id in counter_ids.iter() {
+        let expected: u64 = increments.iter().map(|incs| incs[id]).sum();
+        assert_eq!(totals[id], expected);
+    }
+    assert_eq!(server::TallyResult::new(totals).client_count(), Some(3));
+
+    assert!(protocol::run(&mut rng, &counter_ids, &increments, &server_keys, 6).is_err());
+    let mut unknown = HashMap::new();
+    unknown.insert(CtrId(99), 1);
+    assert!(protocol::run(&mut rng, &counter_ids, &[unknown], &server_keys, 3).is_err());
+}
+
+#[test]
+fn explicit_x_coords() {
+    use privcount::FE;
+    let mut rng = OsRng::new().unwrap();
+
+    let server_keys = Vec::from_iter((0..3).map(|_| gen_server_keys(&mut rng)));
+    let tr_keys = Vec::from_iter(server_keys.iter().map(|sk| sk.public.clone()));
+    let x_coords = [FE::new(1), FE::new(2), FE::new(3)];
+    let counter_ids = vec![CtrId(1), CtrId(2)];
+
+    assert!(client::CounterSet::new_with_x_coords(
+        &mut rng,
+        &counter_ids,
+        &tr_keys,
+        &x_coords[..2],
+        2,
+        NoNoise
+    )
+    .is_err());
+    let dup = [FE::new(1), FE::new(2), FE::new(1)];
+    assert!(client::CounterSet::new_with_x_coords(
+        &mut rng,
+        &counter_ids,
+        &tr_keys,
+        &dup,
+        2,
+        NoNoise
+    )
+    .is_err());
+    let zero = [FE::new(1), FE::new(0), FE::new(3)];
+    assert!(client::CounterSet::new_with_x_coords(
+        &mut rng,
+        &counter_ids,
+        &tr_keys,
+        &zero,
+        2,
+        NoNoise
+    )
+    .is_err());
+
+    let mut ctrs = client::CounterSet::new_with_x_coords(
+        &mut rng,
+        &counter_ids,
+        &tr_keys,
+        &x_coords,
+        2,
+        NoNoise,
+    )
+    .unwrap();
+    ctrs.ctr(CtrId(1)).unwrap().inc(99);
+    ctrs.ctr(CtrId(2)).unwrap().inc(4);
+    let client_keys = ClientKeyPair::generate(&mut rng);
+    let data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+
+    let client_id = client_keys.public();
+    let mut shares = Vec::new();
+    for (keys, (x, tr_data)) in server_keys
+        .iter()
+        .zip(x_coords.iter().zip(data.tr_data.iter()))
+    {
+        // The derived X coordinate is no longer the right one.
+        assert!(keys
+            .decode_from(client_id, &data.counter_ids, tr_data)
+            .is_err());
+        let decoded = keys
+            .decode_from_with_x(client_id, &data.counter_ids, tr_data, *x)
+            .unwrap();
+        shares.push((*x, server::sum_shares(&[decoded])));
+    }
+
+    for (cid, expected) in counter_ids.iter().zip([99, 4].iter()) {
+        let ctr_shares = Vec::from_iter(
+            shares[1..3]
+                .iter()
+                .map(|&(x, ref map)| shamir::Share { x, y: map[cid] }),
+        );
+        assert_eq!(shamir::recover_secret(&ctr_shares).value(), *expected);
+    }
+}
+
+#[test]
+fn add_counter_later() {
+    let mut rng = OsRng::new().unwrap();
+
+    let server_keys = Vec::from_iter((0..3).map(|_| gen_server_keys(&mut rng)));
+    let tr_keys = Vec::from_iter(server_keys.iter().map(|sk| sk.public.clone()));
+
+    let mut ctrs =
+        client::CounterSet::new(&mut rng, &[CtrId(1), CtrId(2)], &tr_keys, 2, NoNoise).unwrap();
+    ctrs.ctr(CtrId(1)).unwrap().inc(5);
+    ctrs.add_counter(&mut rng, CtrId(10)).unwrap();
+    assert!(ctrs.add_counter(&mut rng, CtrId(2)).is_err());
+    ctrs.ctr(CtrId(10)).unwrap().inc(77);
+    ctrs.ctr(CtrId(2)).unwrap().inc(3);
+    let client_keys = ClientKeyPair::generate(&mut rng);
+    let data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+    assert_eq!(
+        data.counter_ids,
+        vec![CtrId(1), CtrId(2), CLIENT_COUNT_ID, CtrId(10)]
+    );
+
+    let client_id = client_keys.public();
+    let shares = Vec::from_iter(
+        server_keys
+            .iter()
+            .zip(data.tr_data.iter())
+            .map(|(keys, d)| {
+                let decoded = keys.decode_from(client_id, &data.counter_ids, d).unwrap();
+                (keys.public.get_x_coord(), server::sum_shares(&[decoded]))
+            }),
+    );
+
+    for (cid, expected) in data.counter_ids.iter().zip([5, 3, 1, 77].iter()) {
+        let ctr_shares = Vec::from_iter(
+            shares[1..3]
+                .iter()
+                .map(|&(x, ref map)| shamir::Share { x, y: map[cid] }),
+        );
+        assert_eq!(shamir::recover_secret(&ctr_shares).value(), *expected);
+    }
+}
+
+#[test]
+fn count_min_sketch() {
+    let mut rng = OsRng::new().unwrap();
+
+    let server_keys = Vec::from_iter((0..3).map(|_| gen_server_keys(&mut rng)));
+    let tr_keys = Vec::from_iter(server_keys.iter().map(|sk| sk.public.clone()));
+    let sketch = client::CountMinSketch::new(CtrId(1000), 3, 32).unwrap();
+    let mut counter_ids = sketch.counter_ids();
+    counter_ids.push(CtrId(1));
+
+    let mut ctrs = client::CounterSet::new(&mut rng, &counter_ids, &tr_keys, 2, NoNoise).unwrap();
+    ctrs.inc_sketch(&sketch, b"torproject.org", 40).unwrap();
+    ctrs.inc_sketch(&sketch, b"example.com", 2).unwrap();
+    ctrs.inc_sketch(&sketch, b"torproject.org", 2).unwrap();
+    ctrs.ctr(CtrId(1)).unwrap().inc(9);
+    let client_keys = ClientKeyPair::generate(&mut rng);
+    let data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+
+    let client_id = client_keys.public();
+    let mut coordinator = server::Coordinator::new(2).unwrap();
+    for (keys, d) in server_keys.iter().zip(data.tr_data.iter()).skip(1) {
+        let decoded = keys.decode_from(client_id, &data.counter_ids, d).unwrap();
+        coordinator
+            .add_contribution(keys.public.get_x_coord(), server::sum_shares(&[decoded]))
+            .unwrap();
+    }
+    let result = coordinator.tally_result().unwrap();
+    assert_eq!(result.client_count(), Some(1));
+    assert_eq!(result.get(CtrId(1)), Some(9));
+    let totals = result.totals();
+
+    assert_eq!(totals[&CtrId(1)], 9);
+    assert!(sketch.query(b"torproject.org", totals).unwrap() >= 42);
+    assert!(sketch.query(b"example.com", totals).unwrap() >= 2);
+    assert!(sketch.query(b"torproject.org", totals).unwrap() <= 44);
+}
+
+#[test]
+fn gaussian_noise() {
+    use privcount::noise::GaussianNoise;
+    let mut rng = OsRng::new().unwrap();
+
+    let n_clients = 4;
+    let n_counters = 500;
+    let sigma = 100.0;
+    let server_keys = gen_server_keys(&mut rng);
+    let tr_keys = vec![server_keys.public.clone()];
+    let counter_ids = Vec::from_iter((1..n_counters + 1).map(CtrId));
+
+    let mut decoded = Vec::new();
+    for _ in 0..n_clients {
+        let noise = GaussianNoise::for_clients(sigma, n_clients).unwrap();
+        let mut ctrs = client::CounterSet::new(&mut rng, &counter_ids, &tr_keys, 1, noise).unwrap();
+        for id in counter_ids.iter() {
+            ctrs.ctr(*id).unwrap().inc(1000);
+        }
+        let client_keys = ClientKeyPair::generate(&mut rng);
+        let data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+        decoded.push(
+            server_keys
+                .decode_from(client_keys.public(), &data.counter_ids, &data.tr_data[0])
+                .unwrap(),
+        );
+    }
+    let sums = server::sum_shares(&decoded);
+    let x = server_keys.public.get_x_coord();
+
+    // The client count gets no noise.
+    let count = shamir::recover_secret(&[shamir::Share {
+        x,
+        y: sums[&CLIENT_COUNT_ID],
+    }]);
+    assert_eq!(count.value(), n_clients as u64);
+
+    // Every other counter is off by the sum of the clients' noise, which
+    // should have mean zero and standard deviation sigma.
+    let errors = Vec::from_iter(counter_ids.iter().map(|id| {
+        let total = shamir::recover_secret(&[shamir::Share { x, y: sums[id] }]);
+        (total.signed_value() - 1000 * n_clients as i64) as f64
+    }));
+    let mean = errors.iter().sum::<f64>() / errors.len() as f64;
+    let var = errors.iter().map(|e| (e - mean) * (e - mean)).sum::<f64>() / errors.len() as f64;
+    // The standard error of the mean is about 4.5, and of the variance,
+    // about 630.
+    assert!(mean.abs() < 25.0);
+    assert!((var - sigma * sigma).abs() < 3000.0);
+}
+
+#[test]
+fn tampered_data_is_rejected() {
+    let mut rng = OsRng::new().unwrap();
+
+    let server_keys = gen_server_keys(&mut rng);
+    let tr_keys = vec![server_keys.public.clone()];
+    let counter_ids = [CtrId(1), CtrId(2)];
+    let client_keys = ClientKeyPair::generate(&mut rng);
+    let mut ctrs = client::CounterSet::new(&mut rng, &counter_ids, &tr_keys, 1, NoNoise).unwrap();
+    ctrs.ctr(CtrId(1)).unwrap().inc(5);
+    let mut data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+
+    let client_id = client_keys.public();
+    assert!(server_keys
+        .decode_from(client_id, &data.counter_ids, &data.tr_data[0])
+        .is_ok());
+
+    // Somebody else's key doesn't verify.
+    let other = ClientKeyPair::generate(&mut rng);
+    assert_eq!(
+        server_keys
+            .decode_from(other.public(), &data.counter_ids, &data.tr_data[0])
+            .err(),
+        Some("Bad signature.")
+    );
+
+    // Nor does a reordered list of counters.
+    let mut reordered = data.counter_ids.clone();
+    reordered.swap(0, 1);
+    assert_eq!(
+        server_keys
+            .decode_from(client_id, &reordered, &data.tr_data[0])
+            .err(),
+        Some("Bad signature.")
+    );
+
+    // Nor do tampered counters.
+    data.tr_data[0].encrypted_counters[0] ^= 1;
+    assert_eq!(
+        server_keys
+            .decode_from(client_id, &data.counter_ids, &data.tr_data[0])
+            .err(),
+        Some("Bad signature.")
+    );
+}
@@ -0,0 +1,194 @@
+extern crate crypto;
+extern crate privcount;
+extern crate rand;
+
+use privcount::data::*;
+use privcount::{client, server, shamir, FE};
+
+use crypto::digest::Digest;
+use crypto::sha3::Sha3;
+use rand::{ChaChaRng, Rng, SeedableRng};
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+fn gen_server_keys<R: Rng>(rng: &mut R) -> server::ServerKeys {
+    use privcount::encrypt::keygen;
+    let mut signing_key = [0; 32];
+    rng.fill_bytes(&mut signing_key);
+    let seckey = keygen::curve25519_seckey_gen(rng);
+    let pk = seckey.public();
+    server::ServerKeys {
+        enc_secret: seckey,
+        public: TrKeys {
+            enc_key: pk,
+            signing_key,
+        },
+    }
+}
+
+/// Return the hex-encoded SHA3-256 digest of `data`.
+fn hex_digest(data: &[u8]) -> String {
+    let mut d = Sha3::sha3_256();
+    d.input(data);
+    d.result_str()
+}
+
+/// Encode a map of counter sums in counter order, as each counter ID
+/// followed by its sum.
+fn encode_sums(sums: &HashMap<CtrId, FE>, out: &mut Vec<u8>) {
+    let mut ids = Vec::from_iter(sums.keys().cloned());
+    ids.sort_by_key(|id| id.0);
+    for id in ids {
+        out.extend_from_slice(&id.0.to_be_bytes());
+        out.extend_from_slice(&sums[&id].to_bytes());
+    }
+}
+
+/// Run a small fixed scenario (3 counters, 2 clients, 3-of-5 TRs) with a
+/// seeded RNG, and check every intermediate value against known answers.
+///
+/// If you change the wire format or any derivation, this test will fail;
+/// update the expected values only once you're sure the change is
+/// intentional.
+#[test]
+fn golden_round_trip() {
+    let mut rng = ChaChaRng::from_seed(&[0x5052_4956, 0x434f_554e, 0x5400_0001][..]);
+
+    let server_keys = Vec::from_iter((0..5).map(|_| gen_server_keys(&mut rng)));
+    let tr_keys = Vec::from_iter(server_keys.iter().map(|sk| sk.public.clone()));
+    let counter_ids = vec![CtrId(1), CtrId(2), CtrId(3)];
+    let increments = [[10, 0, 7], [5, 1000, 3]];
+
+    let client_keys = ClientKeyPair::from_seed(&[0x42; 32]);
+    let mut client_data = Vec::new();
+    for incs in increments.iter() {
+        let mut ctrs =
+            client::CounterSet::new_insecure_for_testing(&mut rng, &counter_ids, &tr_keys, 3)
+                .unwrap();
+        for (id, v) in counter_ids.iter().zip(incs.iter()) {
+            ctrs.ctr(*id).unwrap().inc(*v);
+        }
+        client_data.push(ctrs.finalize(&mut rng, &client_keys).unwrap());
+    }
+
+    let expected_client_digests = [
+        "7d190f4ae5a62979392f7cbf86bb5a0850d6cea98d40fdfc43f6a66364c40ad6",
+        "f7e87d3be8887e24f7555053359de3c9b7b8cc4a072378bd187ec9c38cc3294b",
+    ];
+    for (data, expected) in client_data.iter().zip(expected_client_digests.iter()) {
+        assert_eq!(&hex_digest(&data.to_bytes()), expected);
+    }
+
+    let client_id = client_keys.public();
+    let mut tr_sums = Vec::new();
+    for keys in server_keys.iter() {
+        let decoded = Vec::from_iter(client_data.iter().map(|cd| {
+            let mine = cd.tr_data.iter().find(|d| d.keys == keys.public).unwrap();
+            keys.decode_from(client_id, &cd.counter_ids, mine).unwrap()
+        }));
+        tr_sums.push((keys.public.get_x_coord(), server::sum_shares(&decoded)));
+    }
+
+    let mut encoded = Vec::new();
+    for &(x, ref sums) in tr_sums.iter() {
+        encoded.extend_from_slice(&x.to_bytes());
+        encode_sums(sums, &mut encoded);
+    }
+    assert_eq!(
+        &hex_digest(&encoded),
+        "f9183c8a67548f5860a93e5d97c7dfb344cae8ec52a0132aeabf08c1ea53e991"
+    );
+
+    let expected_tally = [15, 1000, 10];
+    for (cid, expected) in counter_ids.iter().zip(expected_tally.iter()) {
+        let shares = Vec::from_iter(
+            tr_sums[1..4]
+                .iter()
+                .map(|&(x, ref sums)| shamir::Share { x, y: sums[cid] }),
+        );
+        assert_eq!(shamir::recover_secret(&shares).value(), *expected);
+    }
+
+    let totals = server::reconstruct_totals(&tr_sums[1..4]).unwrap();
+    assert_eq!(server::TallyResult::new(totals).client_count(), Some(2));
+}
+
+/// Every intermediate value from `seeded_run()`, in encoded form.
+#[derive(Debug, PartialEq)]
+struct SeededRun {
+    /// The shares of a few plain secrets.
+    secret_shares: Vec<Vec<u8>>,
+    /// Each client's CounterData, with its TrData for every TR.
+    client_data: Vec<Vec<u8>>,
+    /// Each TR's sums.
+    tr_sums: Vec<Vec<u8>>,
+}
+
+/// Run a small sharing and counting scenario with an RNG seeded from
+/// `seed`, and return every intermediate value.
+fn seeded_run(seed: &[u32]) -> SeededRun {
+    let mut rng = ChaChaRng::from_seed(seed);
+
+    let mut builder = shamir::ParamBuilder::new(3, 5).unwrap();
+    builder.fill_x_coordinates(&mut rng);
+    let params = builder.finalize().unwrap();
+    let mut secret_shares = Vec::new();
+    for secret in [0, 1, 1 << 40].iter() {
+        let shares = params.share_secret(FE::new(*secret), &mut rng);
+        secret_shares.push(Vec::from_iter(
+            shares.iter().flat_map(|s| s.to_bytes().to_vec()),
+        ));
+    }
+
+    let server_keys = Vec::from_iter((0..4).map(|_| gen_server_keys(&mut rng)));
+    let tr_keys = Vec::from_iter(server_keys.iter().map(|sk| sk.public.clone()));
+    let counter_ids = vec![CtrId(1), CtrId(2)];
+    let client_keys = ClientKeyPair::generate(&mut rng);
+    let mut client_data = Vec::new();
+    let mut encoded_client_data = Vec::new();
+    for incs in [[3, 4], [50, 0], [7, 7]].iter() {
+        let mut ctrs =
+            client::CounterSet::new_insecure_for_testing(&mut rng, &counter_ids, &tr_keys, 2)
+                .unwrap();
+        for (id, v) in counter_ids.iter().zip(incs.iter()) {
+            ctrs.ctr(*id).unwrap().inc(*v);
+        }
+        let data = ctrs.finalize(&mut rng, &client_keys).unwrap();
+        encoded_client_data.push(data.to_bytes());
+        client_data.push(data);
+    }
+
+    let mut tr_sums = Vec::new();
+    for keys in server_keys.iter() {
+        let decoded = Vec::from_iter(client_data.iter().map(|cd| {
+            let mine = cd.tr_data.iter().find(|d| d.keys == keys.public).unwrap();
+            keys.decode_from(client_keys.public(), &cd.counter_ids, mine)
+                .unwrap()
+        }));
+        let mut encoded = Vec::new();
+        encode_sums(&server::sum_shares(&decoded), &mut encoded);
+        tr_sums.push(encoded);
+    }
+
+    SeededRun {
+        secret_shares,
+        client_data: encoded_client_data,
+        tr_sums,
+    }
+}
+
+/// With the same seed, every share and ciphertext comes out the same;
+/// with a different seed, they don't.
+///
+/// This catches any randomness that doesn't come from the caller's RNG.
+#[test]
+fn seeded_runs_are_deterministic() {
+    let seed = [0x6465_7465, 0x726d_696e];
+    let first = seeded_run(&seed);
+    assert_eq!(first, seeded_run(&seed));
+
+    let other = seeded_run(&[0x6465_7465, 0x726d_696f]);
+    assert!(first.secret_shares != other.secret_shares);
+    assert!(first.client_data != other.client_data);
+    assert!(first.tr_sums != other.tr_sums);
+}